@@ -0,0 +1,163 @@
+//
+// Amendments
+//
+// The `{data}` of a contract is changed by amendments. A party proposes new data with
+// `AmendContract`, the parties approve or reject it, and once enough of them have approved it the
+// new data becomes a data version, effective from the date given in the proposal. Every version is
+// kept in the `{state}`, so the data can be read as of any time.
+//
+// How many approvals an amendment needs is set by `AMENDMENT_APPROVAL`: `all` parties (the
+// default), a `majority` of them, or `any` one. An amendment to a contract that declares no
+// parties needs no approval.
+//
+
+use chrono::{DateTime, Utc};
+use lib::utils::{
+    deserialize_datetime, deserialize_datetime_option, serialize_datetime,
+    serialize_datetime_option,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::env;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AmendmentStatus {
+    Proposed,
+    Approved,
+    Rejected,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Amendment {
+    #[serde(rename = "amendmentId")]
+    pub amendment_id: String,
+
+    #[serde(rename = "status")]
+    pub status: AmendmentStatus,
+
+    // The proposed `HelloWorldClause`.
+    #[serde(rename = "data")]
+    pub data: Value,
+
+    // When the new data takes effect, or as soon as the amendment is approved if that is later or
+    // this is not given.
+    #[serde(
+        rename = "effectiveFrom",
+        default,
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_datetime_option",
+        deserialize_with = "deserialize_datetime_option"
+    )]
+    pub effective_from: Option<DateTime<Utc>>,
+
+    #[serde(rename = "reason", default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+
+    // The subject of the caller who proposed it, when authentication is enabled.
+    #[serde(
+        rename = "proposedBy",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub proposed_by: Option<String>,
+
+    #[serde(
+        rename = "proposedAt",
+        serialize_with = "serialize_datetime",
+        deserialize_with = "deserialize_datetime"
+    )]
+    pub proposed_at: DateTime<Utc>,
+
+    // The parties that have approved it.
+    #[serde(rename = "approvals", default)]
+    pub approvals: Vec<String>,
+
+    #[serde(
+        rename = "rejectedBy",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub rejected_by: Option<String>,
+}
+
+//
+// A version of the `{data}`, in effect from `effectiveFrom` until the next version. The first
+// version is the data the contract was initialised with, and has no `effectiveFrom`.
+//
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DataVersion {
+    #[serde(rename = "version")]
+    pub version: u32,
+
+    #[serde(
+        rename = "effectiveFrom",
+        default,
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_datetime_option",
+        deserialize_with = "deserialize_datetime_option"
+    )]
+    pub effective_from: Option<DateTime<Utc>>,
+
+    #[serde(
+        rename = "amendmentId",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub amendment_id: Option<String>,
+
+    #[serde(rename = "data")]
+    pub data: Value,
+}
+
+impl DataVersion {
+    pub fn is_effective(&self, at: DateTime<Utc>) -> bool {
+        match self.effective_from {
+            Some(from) => from <= at,
+            None => true,
+        }
+    }
+}
+
+//
+// Function version_as_of
+//
+// The version in effect at `at`: the effective version with the latest `effectiveFrom`, and the
+// latest of those when several take effect at the same time.
+//
+pub fn version_as_of(versions: &[DataVersion], at: DateTime<Utc>) -> Option<&DataVersion> {
+    versions
+        .iter()
+        .filter(|version| version.is_effective(at))
+        .max_by_key(|version| (version.effective_from, version.version))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApprovalPolicy {
+    All,
+    Majority,
+    Any,
+}
+
+impl ApprovalPolicy {
+    pub fn from_env() -> Self {
+        match env::var("AMENDMENT_APPROVAL").as_deref() {
+            Ok("majority") => ApprovalPolicy::Majority,
+            Ok("any") => ApprovalPolicy::Any,
+            Ok("all") | Err(_) => ApprovalPolicy::All,
+            Ok(other) => panic!(
+                "AMENDMENT_APPROVAL must be all, majority or any, not {}",
+                other
+            ),
+        }
+    }
+
+    // Whether `approvals` approvals out of `parties` parties are enough.
+    pub fn is_satisfied(&self, approvals: usize, parties: usize) -> bool {
+        match self {
+            ApprovalPolicy::All => approvals >= parties,
+            ApprovalPolicy::Majority => approvals * 2 > parties || parties == 0,
+            ApprovalPolicy::Any => approvals >= 1 || parties == 0,
+        }
+    }
+}
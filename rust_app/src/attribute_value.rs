@@ -0,0 +1,296 @@
+//
+// Mapping between Concerto types and DynamoDB items
+//
+// Any generated Concerto struct is serialized with serde into a `serde_json::Value`, which is then
+// converted into `AttributeValue`s (and back again when reading). Items are therefore stored with the
+// same field names as the JSON representation of the model (`$class`, `$identifier`, `clauseId`, ...),
+// and nested concepts, numbers, datetimes, options and arrays round-trip without field-by-field code.
+// Items written before this mapping used the Rust field names (`_class`, `_identifier`, `clause_id`);
+// they are renamed when read, so existing tables keep working.
+//
+
+use aws_sdk_dynamodb::types::AttributeValue;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{Map, Number, Value};
+use std::collections::HashMap;
+
+// The attribute names of items written before the mapping, and the names they are read as.
+const LEGACY_ATTRIBUTES: &[(&str, &str)] = &[
+    ("_class", "$class"),
+    ("_identifier", "$identifier"),
+    ("clause_id", "clauseId"),
+];
+
+#[derive(Debug, thiserror::Error)]
+pub enum AttributeValueError {
+    #[error("JSON conversion failed: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("expected a JSON object, found: {0}")]
+    NotAnObject(Value),
+
+    #[error("'{0}' is not a valid number")]
+    InvalidNumber(String),
+
+    #[error("unsupported attribute type: {0}")]
+    UnsupportedType(&'static str),
+}
+
+//
+// Function to_item
+//
+// Serializes `value` into a DynamoDB item. The value must serialize to a JSON object.
+//
+pub fn to_item<T: Serialize>(
+    value: &T,
+) -> Result<HashMap<String, AttributeValue>, AttributeValueError> {
+    match serde_json::to_value(value)? {
+        Value::Object(map) => Ok(map
+            .into_iter()
+            .map(|(key, value)| (key, to_attribute_value(value)))
+            .collect()),
+        other => Err(AttributeValueError::NotAnObject(other)),
+    }
+}
+
+//
+// Function from_item
+//
+// Deserializes a DynamoDB item into `T`. Attributes that are not part of `T` (such as the `id` key)
+// are ignored, and legacy attribute names are renamed.
+//
+pub fn from_item<T: DeserializeOwned>(
+    item: HashMap<String, AttributeValue>,
+) -> Result<T, AttributeValueError> {
+    let mut map = item
+        .into_iter()
+        .map(|(key, value)| Ok((key, from_attribute_value(value)?)))
+        .collect::<Result<Map<String, Value>, AttributeValueError>>()?;
+    migrate_legacy_attributes(&mut map);
+
+    Ok(serde_json::from_value(Value::Object(map))?)
+}
+
+//
+// Function migrate_legacy_attributes
+//
+// Renames the attributes of an item written before the mapping. An attribute that already has its
+// new name is kept as it is.
+//
+fn migrate_legacy_attributes(map: &mut Map<String, Value>) {
+    for (legacy, name) in LEGACY_ATTRIBUTES {
+        if let Some(value) = map.remove(*legacy) {
+            map.entry(*name).or_insert(value);
+        }
+    }
+}
+
+pub fn to_attribute_value(value: Value) -> AttributeValue {
+    match value {
+        Value::Null => AttributeValue::Null(true),
+        Value::Bool(b) => AttributeValue::Bool(b),
+        Value::Number(n) => AttributeValue::N(n.to_string()),
+        Value::String(s) => AttributeValue::S(s),
+        Value::Array(values) => {
+            AttributeValue::L(values.into_iter().map(to_attribute_value).collect())
+        }
+        Value::Object(map) => AttributeValue::M(
+            map.into_iter()
+                .map(|(key, value)| (key, to_attribute_value(value)))
+                .collect(),
+        ),
+    }
+}
+
+pub fn from_attribute_value(value: AttributeValue) -> Result<Value, AttributeValueError> {
+    match value {
+        AttributeValue::Null(_) => Ok(Value::Null),
+        AttributeValue::Bool(b) => Ok(Value::Bool(b)),
+        AttributeValue::N(n) => parse_number(&n).map(Value::Number),
+        AttributeValue::S(s) => Ok(Value::String(s)),
+        AttributeValue::L(values) => values
+            .into_iter()
+            .map(from_attribute_value)
+            .collect::<Result<Vec<_>, _>>()
+            .map(Value::Array),
+        AttributeValue::M(map) => map
+            .into_iter()
+            .map(|(key, value)| Ok((key, from_attribute_value(value)?)))
+            .collect::<Result<Map<String, Value>, AttributeValueError>>()
+            .map(Value::Object),
+        AttributeValue::Ss(values) => Ok(Value::Array(
+            values.into_iter().map(Value::String).collect(),
+        )),
+        AttributeValue::Ns(values) => values
+            .iter()
+            .map(|n| parse_number(n).map(Value::Number))
+            .collect::<Result<Vec<_>, _>>()
+            .map(Value::Array),
+        AttributeValue::B(_) => Err(AttributeValueError::UnsupportedType("B")),
+        AttributeValue::Bs(_) => Err(AttributeValueError::UnsupportedType("BS")),
+        _ => Err(AttributeValueError::UnsupportedType("unknown")),
+    }
+}
+
+//
+// Function parse_number
+//
+// DynamoDB numbers are strings. Integers are kept as integers so that `Long`/`Integer` fields
+// deserialize exactly; everything else becomes a `Double`.
+//
+fn parse_number(n: &str) -> Result<Number, AttributeValueError> {
+    if let Ok(i) = n.parse::<i64>() {
+        return Ok(Number::from(i));
+    }
+    if let Ok(u) = n.parse::<u64>() {
+        return Ok(Number::from(u));
+    }
+    n.parse::<f64>()
+        .ok()
+        .and_then(Number::from_f64)
+        .ok_or_else(|| AttributeValueError::InvalidNumber(n.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lifecycle::ContractStatus;
+    use crate::state::ContractState;
+    use crate::test_support::{clause, party, state};
+    use aws_sdk_dynamodb::primitives::Blob;
+    use lib::org_accordproject_helloworldstate::HelloWorldClause;
+    use serde_json::json;
+
+    #[test]
+    fn the_contract_round_trips_through_an_item() {
+        let mut clause = clause();
+        clause.parties = Some(vec![party("auth0|fred", &["buyer"])]);
+
+        let mut item = to_item(&clause).unwrap();
+        assert_eq!(
+            item.get("$class"),
+            Some(&AttributeValue::S(clause._class.clone()))
+        );
+
+        // The key of the item is not part of the contract.
+        item.insert("id".to_string(), AttributeValue::S("data".to_string()));
+        let read: HelloWorldClause = from_item(item).unwrap();
+        assert_eq!(
+            serde_json::to_value(read).unwrap(),
+            serde_json::to_value(clause).unwrap()
+        );
+    }
+
+    #[test]
+    fn the_state_round_trips_through_an_item() {
+        let mut state = state(ContractStatus::Active);
+        state.clause.counter = 3.0;
+
+        let read: ContractState = from_item(to_item(&state).unwrap()).unwrap();
+        assert_eq!(
+            serde_json::to_value(read).unwrap(),
+            serde_json::to_value(state).unwrap()
+        );
+    }
+
+    #[test]
+    fn items_written_before_the_mapping_are_read() {
+        let attribute = |value: &str| AttributeValue::S(value.to_string());
+        let data = HashMap::from([
+            ("id".to_string(), attribute("data")),
+            ("_identifier".to_string(), attribute("contract-1")),
+            ("clause_id".to_string(), attribute("clause-1")),
+            (
+                "_class".to_string(),
+                attribute("org.accordproject.helloworldstate@0.1.0.HelloWorldClause"),
+            ),
+            ("name".to_string(), attribute("Fred Blogs")),
+        ]);
+        let state = HashMap::from([
+            ("id".to_string(), attribute("state")),
+            ("_identifier".to_string(), attribute("contract-1")),
+            ("counter".to_string(), AttributeValue::N("0".to_string())),
+            (
+                "_class".to_string(),
+                attribute("org.accordproject.helloworldstate@0.1.0.HelloWorldState"),
+            ),
+        ]);
+
+        let clause: HelloWorldClause = from_item(data).unwrap();
+        assert_eq!(clause._identifier, "contract-1");
+        assert_eq!(clause.clause_id, "clause-1");
+        assert_eq!(clause.name, "Fred Blogs");
+
+        // States written before the lifecycle belong to contracts already in use.
+        let state: ContractState = from_item(state).unwrap();
+        assert_eq!(state.clause._identifier, "contract-1");
+        assert_eq!(state.status, ContractStatus::legacy());
+    }
+
+    #[test]
+    fn current_attribute_names_win_over_legacy_ones() {
+        let item = HashMap::from([
+            (
+                "$class".to_string(),
+                AttributeValue::S("current".to_string()),
+            ),
+            (
+                "_class".to_string(),
+                AttributeValue::S("legacy".to_string()),
+            ),
+        ]);
+
+        let read: Value = from_item(item).unwrap();
+        assert_eq!(read, json!({ "$class": "current" }));
+    }
+
+    #[test]
+    fn numbers_keep_their_type() {
+        let value = json!({
+            "long": -42,
+            "large": u64::MAX,
+            "double": 1.5,
+            "nothing": null,
+            "list": [true, "text"]
+        });
+
+        let read: Value = from_item(to_item(&value).unwrap()).unwrap();
+        assert_eq!(read, value);
+        assert!(read["long"].is_i64());
+        assert!(read["large"].is_u64());
+        assert!(read["double"].is_f64());
+    }
+
+    #[test]
+    fn sets_are_read_as_arrays() {
+        let item = HashMap::from([
+            (
+                "names".to_string(),
+                AttributeValue::Ss(vec!["a".to_string(), "b".to_string()]),
+            ),
+            (
+                "counts".to_string(),
+                AttributeValue::Ns(vec!["1".to_string(), "2.5".to_string()]),
+            ),
+        ]);
+
+        let read: Value = from_item(item).unwrap();
+        assert_eq!(read, json!({ "names": ["a", "b"], "counts": [1, 2.5] }));
+    }
+
+    #[test]
+    fn unsupported_values_are_errors() {
+        assert!(matches!(
+            to_item(&json!([1, 2])),
+            Err(AttributeValueError::NotAnObject(_))
+        ));
+        assert!(matches!(
+            from_attribute_value(AttributeValue::B(Blob::new(vec![1]))),
+            Err(AttributeValueError::UnsupportedType("B"))
+        ));
+        assert!(matches!(
+            from_attribute_value(AttributeValue::N("twelve".to_string())),
+            Err(AttributeValueError::InvalidNumber(n)) if n == "twelve"
+        ));
+    }
+}
@@ -0,0 +1,107 @@
+//
+// Clock
+//
+// Every timestamp produced by the contract comes from a `Clock`, so executions can be made
+// deterministic. Production uses the `SystemClock`; tests and replays use a `FixedClock`.
+//
+
+use chrono::{DateTime, Duration, Utc};
+use std::{env, sync::Mutex};
+
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+//
+// A clock that starts at a fixed time and advances by `step` every time it is read.
+// With a zero step it always returns the same time.
+//
+pub struct FixedClock {
+    now: Mutex<DateTime<Utc>>,
+    step: Duration,
+}
+
+impl FixedClock {
+    pub fn new(now: DateTime<Utc>, step: Duration) -> Self {
+        Self {
+            now: Mutex::new(now),
+            step,
+        }
+    }
+}
+
+impl Clock for FixedClock {
+    fn now(&self) -> DateTime<Utc> {
+        let mut now = self.now.lock().unwrap_or_else(|e| e.into_inner());
+        let current = *now;
+        *now = current + self.step;
+        current
+    }
+}
+
+//
+// Function clock_from_env
+//
+// Uses a `FixedClock` starting at `FIXED_CLOCK_TIME` (RFC 3339) when it is set, advancing by
+// `FIXED_CLOCK_STEP_MS` milliseconds per reading, and the `SystemClock` otherwise.
+//
+pub fn clock_from_env() -> Box<dyn Clock> {
+    let Ok(time) = env::var("FIXED_CLOCK_TIME") else {
+        return Box::new(SystemClock);
+    };
+
+    let start = DateTime::parse_from_rfc3339(&time)
+        .expect("FIXED_CLOCK_TIME must be an RFC 3339 timestamp")
+        .with_timezone(&Utc);
+    let step = env::var("FIXED_CLOCK_STEP_MS")
+        .map(|step| {
+            step.parse::<i64>()
+                .expect("FIXED_CLOCK_STEP_MS must be a whole number of milliseconds")
+        })
+        .unwrap_or(0);
+
+    Box::new(FixedClock::new(start, Duration::milliseconds(step)))
+}
+
+//
+// Function use_request_timestamp
+//
+// When `USE_REQUEST_TIMESTAMP` is `true`, the `$timestamp` of a request is used as the logical
+// execution time instead of the clock.
+//
+pub fn use_request_timestamp() -> bool {
+    env::var("USE_REQUEST_TIMESTAMP")
+        .map(|value| value.eq_ignore_ascii_case("true"))
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::at;
+
+    #[test]
+    fn a_fixed_clock_without_a_step_stands_still() {
+        let clock = FixedClock::new(at("2024-01-01T00:00:00Z"), Duration::zero());
+
+        assert_eq!(clock.now(), at("2024-01-01T00:00:00Z"));
+        assert_eq!(clock.now(), at("2024-01-01T00:00:00Z"));
+    }
+
+    #[test]
+    fn a_fixed_clock_advances_by_its_step_on_every_reading() {
+        let clock = FixedClock::new(at("2024-01-01T00:00:00Z"), Duration::milliseconds(1500));
+
+        assert_eq!(clock.now(), at("2024-01-01T00:00:00Z"));
+        assert_eq!(clock.now(), at("2024-01-01T00:00:01.500Z"));
+        assert_eq!(clock.now(), at("2024-01-01T00:00:03Z"));
+    }
+}
@@ -0,0 +1,267 @@
+//
+// Agreement Delivery
+//
+// Sends generated agreements to their recipients. A `DeliveryChannel` delivers to every recipient
+// of a job; the channels in use are named, comma separated, by `DELIVERY_CHANNELS`:
+//
+// - `webhook` posts the delivery as JSON to `DELIVERY_WEBHOOK_URL`.
+// - `smtp` emails the recipients through the server at `SMTP_HOST`.
+// - `outbox` writes the delivery as a JSON file to `DELIVERY_OUTBOX_DIR`, for tests.
+//
+// Without `DELIVERY_CHANNELS` nothing is delivered by the contract, and the generation service is
+// relied on to send the agreement.
+//
+// Deliveries are sent from the outbox, so a delivery may be repeated. The outbox records the
+// channels that delivered it, so a retry only goes through the channels that failed, and every
+// channel passes on its `dedupKey` so receivers can ignore any other repeats.
+//
+
+use crate::error::ContractError;
+use crate::recipient::Recipient;
+use async_trait::async_trait;
+use outbox::OutboxChannel;
+use serde::{Deserialize, Serialize};
+use smtp::SmtpChannel;
+use std::env;
+use webhook::WebhookChannel;
+
+pub mod outbox;
+pub mod smtp;
+pub mod webhook;
+
+// A generated agreement, ready to be sent.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Delivery {
+    #[serde(rename = "dedupKey")]
+    pub dedup_key: String,
+
+    #[serde(rename = "jobId")]
+    pub job_id: String,
+
+    pub recipients: Vec<Recipient>,
+
+    #[serde(
+        rename = "documentUrl",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub document_url: Option<String>,
+
+    #[serde(
+        rename = "documentHash",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub document_hash: Option<String>,
+}
+
+impl Delivery {
+    pub fn subject(&self) -> String {
+        "Your agreement is ready".to_string()
+    }
+
+    pub fn body(&self) -> String {
+        let mut body = "The agreement has been generated.\n".to_string();
+        if let Some(url) = &self.document_url {
+            body.push_str(&format!("\nDocument: {}\n", url));
+        }
+        if let Some(hash) = &self.document_hash {
+            body.push_str(&format!("SHA-256: {}\n", hash));
+        }
+        body.push_str(&format!("\nReference: {}\n", self.job_id));
+        body
+    }
+}
+
+#[async_trait]
+pub trait DeliveryChannel: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    async fn deliver(&self, delivery: &Delivery) -> Result<(), ContractError>;
+}
+
+#[derive(Default)]
+pub struct Courier {
+    channels: Vec<Box<dyn DeliveryChannel>>,
+}
+
+impl Courier {
+    pub fn new(channels: Vec<Box<dyn DeliveryChannel>>) -> Self {
+        Self { channels }
+    }
+
+    //
+    // Function from_env
+    //
+    // The channels named by `DELIVERY_CHANNELS`.
+    //
+    pub fn from_env() -> Self {
+        let names = env::var("DELIVERY_CHANNELS").unwrap_or_default();
+
+        let channels = names
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(|name| -> Box<dyn DeliveryChannel> {
+                match name {
+                    "webhook" => Box::new(WebhookChannel::from_env()),
+                    "smtp" => Box::new(SmtpChannel::from_env()),
+                    "outbox" => Box::new(OutboxChannel::from_env()),
+                    other => panic!(
+                        "DELIVERY_CHANNELS may only name webhook, smtp and outbox, found '{}'",
+                        other
+                    ),
+                }
+            })
+            .collect();
+
+        Self::new(channels)
+    }
+
+    //
+    // Function deliver
+    //
+    // Delivers through every channel not in `delivered_via`, even when one of them fails, and reports
+    // the failures together. The channels that deliver are added to `delivered_via`.
+    //
+    pub async fn deliver(
+        &self,
+        delivery: &Delivery,
+        delivered_via: &mut Vec<String>,
+    ) -> Result<(), ContractError> {
+        let mut errors = vec![];
+
+        for channel in &self.channels {
+            if delivered_via.iter().any(|name| name == channel.name()) {
+                continue;
+            }
+
+            match channel.deliver(delivery).await {
+                Ok(()) => {
+                    tracing::info!(job_id = %delivery.job_id, channel = channel.name(), "delivered");
+                    delivered_via.push(channel.name().to_string());
+                }
+                Err(e) => {
+                    tracing::warn!(
+                        job_id = %delivery.job_id,
+                        channel = channel.name(),
+                        error = %e,
+                        "delivery failed"
+                    );
+                    errors.push(format!("{}: {}", channel.name(), e));
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(ContractError::Delivery(errors.join("; ")))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    // Records the deliveries it is given, failing the first `failures` of them.
+    #[derive(Clone)]
+    struct Recording {
+        name: &'static str,
+        failures: Arc<Mutex<u32>>,
+        delivered: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Recording {
+        fn new(name: &'static str, failures: u32) -> Self {
+            Self {
+                name,
+                failures: Arc::new(Mutex::new(failures)),
+                delivered: Arc::default(),
+            }
+        }
+
+        fn deliveries(&self) -> usize {
+            self.delivered.lock().unwrap().len()
+        }
+    }
+
+    #[async_trait]
+    impl DeliveryChannel for Recording {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        async fn deliver(&self, delivery: &Delivery) -> Result<(), ContractError> {
+            let mut failures = self.failures.lock().unwrap();
+            if *failures > 0 {
+                *failures -= 1;
+                return Err(ContractError::Delivery("unavailable".to_string()));
+            }
+
+            self.delivered
+                .lock()
+                .unwrap()
+                .push(delivery.dedup_key.clone());
+            Ok(())
+        }
+    }
+
+    fn delivery() -> Delivery {
+        Delivery {
+            dedup_key: "deliver:job-1".to_string(),
+            job_id: "job-1".to_string(),
+            recipients: vec![Recipient::party("fred@example.com")],
+            document_url: Some("s3://agreements/job-1.pdf".to_string()),
+            document_hash: Some("abc123".to_string()),
+        }
+    }
+
+    #[tokio::test]
+    async fn a_retry_only_goes_through_the_channels_that_failed() {
+        let webhook = Recording::new("webhook", 0);
+        let smtp = Recording::new("smtp", 1);
+        let courier = Courier::new(vec![Box::new(webhook.clone()), Box::new(smtp.clone())]);
+        let mut delivered_via = vec![];
+
+        let first = courier.deliver(&delivery(), &mut delivered_via).await;
+        assert!(matches!(first, Err(ContractError::Delivery(e)) if e.starts_with("smtp: ")));
+        assert_eq!(delivered_via, ["webhook"]);
+
+        courier
+            .deliver(&delivery(), &mut delivered_via)
+            .await
+            .unwrap();
+        assert_eq!(delivered_via, ["webhook", "smtp"]);
+        assert_eq!(webhook.deliveries(), 1);
+        assert_eq!(smtp.deliveries(), 1);
+    }
+
+    #[tokio::test]
+    async fn every_failure_is_reported() {
+        let courier = Courier::new(vec![
+            Box::new(Recording::new("webhook", 1)),
+            Box::new(Recording::new("smtp", 1)),
+        ]);
+        let mut delivered_via = vec![];
+
+        let result = courier.deliver(&delivery(), &mut delivered_via).await;
+        assert!(matches!(
+            result,
+            Err(ContractError::Delivery(e))
+                if e.starts_with("webhook: ") && e.contains("; smtp: ")
+        ));
+        assert!(delivered_via.is_empty());
+    }
+
+    #[test]
+    fn the_body_names_the_document() {
+        let body = delivery().body();
+
+        assert!(body.contains("Document: s3://agreements/job-1.pdf"));
+        assert!(body.contains("SHA-256: abc123"));
+        assert!(body.contains("Reference: job-1"));
+    }
+}
@@ -0,0 +1,85 @@
+//
+// Outbox Delivery
+//
+// Writes every delivery as a JSON file named after its `dedupKey` to `DELIVERY_OUTBOX_DIR`, which
+// defaults to `/tmp/outbox`, so repeated deliveries overwrite each other. Nothing is sent; tests
+// read the files instead.
+//
+
+use super::{Delivery, DeliveryChannel};
+use crate::error::ContractError;
+use async_trait::async_trait;
+use std::{env, fs, path::PathBuf};
+
+const DEFAULT_OUTBOX_DIR: &str = "/tmp/outbox";
+
+pub struct OutboxChannel {
+    directory: PathBuf,
+}
+
+impl OutboxChannel {
+    pub fn from_env() -> Self {
+        let directory =
+            env::var("DELIVERY_OUTBOX_DIR").unwrap_or_else(|_| DEFAULT_OUTBOX_DIR.to_string());
+
+        Self {
+            directory: PathBuf::from(directory),
+        }
+    }
+}
+
+#[async_trait]
+impl DeliveryChannel for OutboxChannel {
+    fn name(&self) -> &'static str {
+        "outbox"
+    }
+
+    async fn deliver(&self, delivery: &Delivery) -> Result<(), ContractError> {
+        let json = serde_json::to_vec_pretty(delivery)
+            .map_err(|e| ContractError::Delivery(e.to_string()))?;
+
+        fs::create_dir_all(&self.directory).map_err(|e| ContractError::Delivery(e.to_string()))?;
+        fs::write(
+            self.directory.join(format!("{}.json", delivery.dedup_key)),
+            json,
+        )
+        .map_err(|e| ContractError::Delivery(e.to_string()))?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recipient::Recipient;
+
+    #[tokio::test]
+    async fn a_repeated_delivery_overwrites_the_file() {
+        let directory = env::temp_dir().join(format!("outbox-{}", uuid::Uuid::new_v4()));
+        let channel = OutboxChannel {
+            directory: directory.clone(),
+        };
+        let mut delivery = Delivery {
+            dedup_key: "deliver:job-1".to_string(),
+            job_id: "job-1".to_string(),
+            recipients: vec![Recipient::party("fred@example.com")],
+            document_url: None,
+            document_hash: None,
+        };
+
+        channel.deliver(&delivery).await.unwrap();
+        delivery.document_hash = Some("abc123".to_string());
+        channel.deliver(&delivery).await.unwrap();
+
+        let files = fs::read_dir(&directory).unwrap().count();
+        let written: serde_json::Value =
+            serde_json::from_slice(&fs::read(directory.join("deliver:job-1.json")).unwrap())
+                .unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(files, 1);
+        assert_eq!(written["documentHash"], "abc123");
+        assert_eq!(written["recipients"][0]["email"], "fred@example.com");
+    }
+}
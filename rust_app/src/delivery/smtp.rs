@@ -0,0 +1,210 @@
+//
+// SMTP Delivery
+//
+// Emails the recipients through the server at `SMTP_HOST`. Parties are addressed directly, and
+// everyone else is copied in. `SMTP_TLS` is `starttls` (the default), `tls`, or `none` for local
+// stand-ins such as MailHog; `SMTP_USERNAME` and `SMTP_PASSWORD` are optional.
+//
+// The `Message-ID` of the email is derived from the `dedupKey`, so mail systems can recognise a
+// repeated delivery.
+//
+
+use super::{Delivery, DeliveryChannel};
+use crate::error::ContractError;
+use crate::recipient::{Recipient, RecipientRole};
+use async_trait::async_trait;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use std::env;
+
+pub struct SmtpChannel {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl SmtpChannel {
+    pub fn from_env() -> Self {
+        let host = env::var("SMTP_HOST").expect("SMTP_HOST must be set");
+        let from = env::var("SMTP_FROM")
+            .expect("SMTP_FROM must be set")
+            .parse::<Mailbox>()
+            .expect("SMTP_FROM must be an email address");
+
+        let mut builder = match env::var("SMTP_TLS").as_deref() {
+            Ok("starttls") | Err(_) => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&host)
+                .expect("failed to configure SMTP"),
+            Ok("tls") => AsyncSmtpTransport::<Tokio1Executor>::relay(&host)
+                .expect("failed to configure SMTP"),
+            Ok("none") => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&host),
+            Ok(other) => panic!(
+                "SMTP_TLS must be 'starttls', 'tls' or 'none', found '{}'",
+                other
+            ),
+        };
+        if let Ok(port) = env::var("SMTP_PORT") {
+            builder = builder.port(port.parse().expect("SMTP_PORT must be a port number"));
+        }
+        if let (Ok(username), Ok(password)) = (env::var("SMTP_USERNAME"), env::var("SMTP_PASSWORD"))
+        {
+            builder = builder.credentials(Credentials::new(username, password));
+        }
+
+        Self {
+            transport: builder.build(),
+            from,
+        }
+    }
+}
+
+fn mailbox(recipient: &Recipient) -> Result<Mailbox, ContractError> {
+    let address = recipient
+        .email
+        .parse()
+        .map_err(|e| ContractError::Delivery(format!("{}: {}", recipient.email, e)))?;
+
+    Ok(Mailbox::new(recipient.name.clone(), address))
+}
+
+#[async_trait]
+impl DeliveryChannel for SmtpChannel {
+    fn name(&self) -> &'static str {
+        "smtp"
+    }
+
+    async fn deliver(&self, delivery: &Delivery) -> Result<(), ContractError> {
+        let mut message = Message::builder()
+            .from(self.from.clone())
+            .message_id(Some(format!(
+                "<{}@{}>",
+                delivery.dedup_key.replace(':', "."),
+                self.from.email.domain()
+            )))
+            .subject(delivery.subject());
+
+        for recipient in &delivery.recipients {
+            message = match recipient.role {
+                RecipientRole::Party => message.to(mailbox(recipient)?),
+                RecipientRole::Cc | RecipientRole::Counsel => message.cc(mailbox(recipient)?),
+            };
+        }
+
+        let message = message
+            .body(delivery.body())
+            .map_err(|e| ContractError::Delivery(e.to_string()))?;
+
+        self.transport
+            .send(message)
+            .await
+            .map_err(|e| ContractError::Delivery(e.to_string()))?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+    use tokio::task::JoinHandle;
+
+    // A local stand-in for an SMTP server, which accepts one email and returns the commands and
+    // message it received.
+    async fn server() -> (u16, JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let received = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = stream.into_split();
+            let mut lines = BufReader::new(reader).lines();
+            let mut received = String::new();
+            let mut in_data = false;
+
+            writer.write_all(b"220 localhost ESMTP\r\n").await.unwrap();
+            while let Some(line) = lines.next_line().await.unwrap() {
+                received.push_str(&line);
+                received.push('\n');
+
+                let reply: &[u8] = if in_data {
+                    if line != "." {
+                        continue;
+                    }
+                    in_data = false;
+                    b"250 queued\r\n"
+                } else if line.starts_with("DATA") {
+                    in_data = true;
+                    b"354 go ahead\r\n"
+                } else if line.starts_with("QUIT") {
+                    writer.write_all(b"221 bye\r\n").await.unwrap();
+                    break;
+                } else {
+                    b"250 OK\r\n"
+                };
+                writer.write_all(reply).await.unwrap();
+            }
+
+            received
+        });
+
+        (port, received)
+    }
+
+    fn channel(port: u16) -> SmtpChannel {
+        SmtpChannel {
+            transport: AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous("127.0.0.1")
+                .port(port)
+                .build(),
+            from: "Contracts <contracts@example.com>".parse().unwrap(),
+        }
+    }
+
+    fn delivery(recipients: Vec<Recipient>) -> Delivery {
+        Delivery {
+            dedup_key: "deliver:job-1".to_string(),
+            job_id: "job-1".to_string(),
+            recipients,
+            document_url: Some("s3://agreements/job-1.pdf".to_string()),
+            document_hash: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn parties_are_addressed_and_everyone_else_is_copied_in() {
+        let (port, received) = server().await;
+        let counsel = Recipient {
+            email: "counsel@example.com".to_string(),
+            role: RecipientRole::Counsel,
+            name: Some("Counsel".to_string()),
+        };
+
+        channel(port)
+            .deliver(&delivery(vec![
+                Recipient::party("fred@example.com"),
+                counsel,
+            ]))
+            .await
+            .unwrap();
+
+        let received = received.await.unwrap();
+        assert!(received.contains("MAIL FROM:<contracts@example.com>"));
+        assert!(received.contains("RCPT TO:<fred@example.com>"));
+        assert!(received.contains("RCPT TO:<counsel@example.com>"));
+        assert!(received.contains("To: fred@example.com"));
+        assert!(received.contains("Cc: Counsel <counsel@example.com>"));
+        assert!(received.contains("Message-ID: <deliver.job-1@example.com>"));
+        assert!(received.contains("Document: s3://agreements/job-1.pdf"));
+    }
+
+    #[tokio::test]
+    async fn an_invalid_address_fails_the_delivery() {
+        let result = channel(1)
+            .deliver(&delivery(vec![Recipient::party("not an address")]))
+            .await;
+
+        assert!(
+            matches!(result, Err(ContractError::Delivery(e)) if e.starts_with("not an address"))
+        );
+    }
+}
@@ -0,0 +1,129 @@
+//
+// Webhook Delivery
+//
+// Posts the delivery as JSON to `DELIVERY_WEBHOOK_URL`, which sends it on to the recipients. The
+// `dedupKey` is also sent as the `Idempotency-Key` header.
+//
+
+use super::{Delivery, DeliveryChannel};
+use crate::error::ContractError;
+use crate::generation::duration_from_env;
+use async_trait::async_trait;
+use reqwest::Client;
+use std::env;
+
+const DEFAULT_TIMEOUT_MS: u64 = 2500;
+
+pub struct WebhookChannel {
+    http: Client,
+    url: String,
+}
+
+impl WebhookChannel {
+    pub fn from_env() -> Self {
+        let url = env::var("DELIVERY_WEBHOOK_URL").expect("DELIVERY_WEBHOOK_URL must be set");
+        let http = Client::builder()
+            .timeout(duration_from_env(
+                "DELIVERY_WEBHOOK_TIMEOUT_MS",
+                DEFAULT_TIMEOUT_MS,
+            ))
+            .build()
+            .expect("failed to build the HTTP client");
+
+        Self { http, url }
+    }
+}
+
+#[async_trait]
+impl DeliveryChannel for WebhookChannel {
+    fn name(&self) -> &'static str {
+        "webhook"
+    }
+
+    async fn deliver(&self, delivery: &Delivery) -> Result<(), ContractError> {
+        let response = self
+            .http
+            .post(&self.url)
+            .header("Idempotency-Key", &delivery.dedup_key)
+            .json(delivery)
+            .send()
+            .await
+            .map_err(|e| ContractError::Delivery(e.to_string()))?;
+
+        if !response.status().is_success() {
+            return Err(ContractError::Delivery(format!(
+                "webhook responded with status {}",
+                response.status()
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recipient::Recipient;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio::task::JoinHandle;
+
+    // Answers one call with `status`, and returns the request it received.
+    async fn receiver(status: u16) -> (String, JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/deliveries", listener.local_addr().unwrap());
+
+        let received = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buffer = vec![0; 64 * 1024];
+            let read = stream.read(&mut buffer).await.unwrap();
+            let response = format!(
+                "HTTP/1.1 {} Status\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+                status
+            );
+            let _ = stream.write_all(response.as_bytes()).await;
+
+            String::from_utf8_lossy(&buffer[..read]).to_string()
+        });
+
+        (url, received)
+    }
+
+    fn delivery() -> Delivery {
+        Delivery {
+            dedup_key: "deliver:job-1".to_string(),
+            job_id: "job-1".to_string(),
+            recipients: vec![Recipient::party("fred@example.com")],
+            document_url: None,
+            document_hash: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn the_delivery_is_posted_with_its_dedup_key() {
+        let (url, received) = receiver(204).await;
+        let channel = WebhookChannel {
+            http: Client::new(),
+            url,
+        };
+
+        channel.deliver(&delivery()).await.unwrap();
+
+        let request = received.await.unwrap().to_lowercase();
+        assert!(request.starts_with("post /deliveries"));
+        assert!(request.contains("idempotency-key: deliver:job-1"));
+    }
+
+    #[tokio::test]
+    async fn an_unsuccessful_status_fails_the_delivery() {
+        let (url, _) = receiver(503).await;
+        let channel = WebhookChannel {
+            http: Client::new(),
+            url,
+        };
+
+        let result = channel.deliver(&delivery()).await;
+        assert!(matches!(result, Err(ContractError::Delivery(e)) if e.contains("503")));
+    }
+}
@@ -0,0 +1,229 @@
+//
+// KMS Key Provider
+//
+// Generates and decrypts data keys with the KMS key `ENCRYPTION_KMS_KEY_ID`, a key id, ARN or
+// alias. KMS can rotate the key material itself; pointing `ENCRYPTION_KMS_KEY_ID` at another key
+// rotates the key, and the function must then still be allowed to decrypt with the previous one.
+//
+// The KMS client is built once, when the provider is, and reused by every request.
+//
+
+use super::{DataKey, KeyProvider};
+use crate::error::ContractError;
+use async_trait::async_trait;
+use aws_sdk_kms::{primitives::Blob, types::DataKeySpec, Client};
+
+pub struct KmsKeyProvider {
+    key_id: String,
+    client: Client,
+}
+
+impl KmsKeyProvider {
+    pub async fn new(key_id: String) -> Self {
+        let config = aws_config::load_from_env().await;
+        let client = Client::new(&config);
+
+        Self { key_id, client }
+    }
+}
+
+#[async_trait]
+impl KeyProvider for KmsKeyProvider {
+    async fn generate_data_key(&self) -> Result<DataKey, ContractError> {
+        let output = self
+            .client
+            .generate_data_key()
+            .key_id(&self.key_id)
+            .key_spec(DataKeySpec::Aes256)
+            .send()
+            .await
+            .map_err(|e| ContractError::Encryption(format!("{:?}", e)))?;
+
+        match (
+            output.key_id(),
+            output.plaintext(),
+            output.ciphertext_blob(),
+        ) {
+            (Some(key_id), Some(plaintext), Some(encrypted)) => Ok(DataKey {
+                key_id: key_id.to_string(),
+                plaintext: plaintext.as_ref().to_vec(),
+                encrypted: encrypted.as_ref().to_vec(),
+            }),
+            _ => Err(ContractError::Encryption(
+                "KMS returned an incomplete data key".to_string(),
+            )),
+        }
+    }
+
+    async fn decrypt_data_key(
+        &self,
+        key_id: &str,
+        encrypted: &[u8],
+    ) -> Result<Vec<u8>, ContractError> {
+        let output = self
+            .client
+            .decrypt()
+            .key_id(key_id)
+            .ciphertext_blob(Blob::new(encrypted))
+            .send()
+            .await
+            .map_err(|e| ContractError::Encryption(format!("{:?}", e)))?;
+
+        output
+            .plaintext()
+            .map(|plaintext| plaintext.as_ref().to_vec())
+            .ok_or_else(|| ContractError::Encryption("KMS returned no data key".to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_sdk_kms::config::{retry::RetryConfig, Credentials, Region};
+    use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+    use serde_json::{json, Value};
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    // A local stand-in for KMS that knows the keys `key_ids`. A data key is "encrypted" as the id of
+    // its key followed by the key itself, and only decrypts under that key id.
+    async fn kms(key_ids: &'static [&'static str]) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                tokio::spawn(async move {
+                    let mut stream = BufReader::new(stream);
+                    loop {
+                        let mut target = String::new();
+                        let mut length = 0;
+                        loop {
+                            let mut line = String::new();
+                            if stream.read_line(&mut line).await.unwrap_or(0) == 0 {
+                                return;
+                            }
+                            let line = line.trim_end().to_lowercase();
+                            if line.is_empty() {
+                                break;
+                            }
+                            if let Some(value) = line.strip_prefix("content-length:") {
+                                length = value.trim().parse().unwrap();
+                            }
+                            if let Some(value) = line.strip_prefix("x-amz-target:") {
+                                target = value.trim().to_string();
+                            }
+                        }
+                        let mut body = vec![0; length];
+                        stream.read_exact(&mut body).await.unwrap();
+                        let request: Value = serde_json::from_slice(&body).unwrap();
+
+                        let (status, response) = answer(key_ids, &target, &request);
+                        let response = response.to_string();
+                        let reply = format!(
+                            "HTTP/1.1 {} Status\r\ncontent-type: application/x-amz-json-1.1\r\ncontent-length: {}\r\n\r\n{}",
+                            status,
+                            response.len(),
+                            response
+                        );
+                        stream.write_all(reply.as_bytes()).await.unwrap();
+                    }
+                });
+            }
+        });
+
+        url
+    }
+
+    fn answer(key_ids: &[&str], target: &str, request: &Value) -> (u16, Value) {
+        let key_id = request["KeyId"].as_str().unwrap_or_default();
+        if !key_ids.contains(&key_id) {
+            return (
+                400,
+                json!({ "__type": "NotFoundException", "message": format!("{} not found", key_id) }),
+            );
+        }
+
+        match target {
+            "trentservice.generatedatakey" => {
+                let plaintext = [key_id.len() as u8; 32];
+                let mut encrypted = key_id.as_bytes().to_vec();
+                encrypted.extend(plaintext);
+                (
+                    200,
+                    json!({
+                        "KeyId": key_id,
+                        "Plaintext": BASE64.encode(plaintext),
+                        "CiphertextBlob": BASE64.encode(encrypted),
+                    }),
+                )
+            }
+            "trentservice.decrypt" => {
+                let encrypted = BASE64
+                    .decode(request["CiphertextBlob"].as_str().unwrap())
+                    .unwrap();
+                match encrypted.strip_prefix(key_id.as_bytes()) {
+                    Some(plaintext) => (
+                        200,
+                        json!({ "KeyId": key_id, "Plaintext": BASE64.encode(plaintext) }),
+                    ),
+                    None => (
+                        400,
+                        json!({ "__type": "IncorrectKeyException", "message": "wrong key" }),
+                    ),
+                }
+            }
+            other => panic!("unexpected KMS call {}", other),
+        }
+    }
+
+    fn provider(url: &str, key_id: &str) -> KmsKeyProvider {
+        let config = aws_sdk_kms::Config::builder()
+            .endpoint_url(url)
+            .region(Region::new("eu-west-1"))
+            .credentials_provider(Credentials::new("test", "test", None, None, "test"))
+            .retry_config(RetryConfig::disabled())
+            .build();
+
+        KmsKeyProvider {
+            key_id: key_id.to_string(),
+            client: Client::from_conf(config),
+        }
+    }
+
+    #[tokio::test]
+    async fn data_keys_are_decrypted_after_a_rotation() {
+        let url = kms(&["alias/contracts-2024-01", "alias/contracts-2024-06"]).await;
+        let before = provider(&url, "alias/contracts-2024-01");
+        let after = provider(&url, "alias/contracts-2024-06");
+
+        let old = before.generate_data_key().await.unwrap();
+        assert_eq!(old.key_id, "alias/contracts-2024-01");
+        assert_eq!(
+            after
+                .decrypt_data_key(&old.key_id, &old.encrypted)
+                .await
+                .unwrap(),
+            old.plaintext
+        );
+        assert_eq!(
+            after.generate_data_key().await.unwrap().key_id,
+            "alias/contracts-2024-06"
+        );
+    }
+
+    #[tokio::test]
+    async fn unknown_key_ids_are_rejected() {
+        let url = kms(&["alias/contracts-2024-06"]).await;
+        let provider = provider(&url, "alias/contracts-2024-06");
+
+        let result = provider
+            .decrypt_data_key("alias/contracts-2024-01", b"alias/contracts-2024-01")
+            .await;
+        assert!(matches!(
+            result,
+            Err(ContractError::Encryption(e)) if e.contains("NotFoundException")
+        ));
+    }
+}
@@ -0,0 +1,172 @@
+//
+// Local Key Provider
+//
+// Keeps the keys in a JSON file, for development and tests:
+//
+// { "currentKeyId": "2024-06", "keys": { "2024-01": "<base64>", "2024-06": "<base64>" } }
+//
+// Every key is 256 bits. Data keys are encrypted by the current key; the older keys are only used
+// to decrypt data keys encrypted before the current key was added.
+//
+
+use super::{open, seal, DataKey, KeyProvider};
+use crate::error::ContractError;
+use async_trait::async_trait;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+
+#[derive(Deserialize)]
+struct KeyFile {
+    #[serde(rename = "currentKeyId")]
+    current_key_id: String,
+
+    keys: HashMap<String, String>,
+}
+
+pub struct LocalKeyProvider {
+    current_key_id: String,
+    keys: HashMap<String, Vec<u8>>,
+}
+
+impl LocalKeyProvider {
+    pub fn from_file(path: &str) -> Self {
+        let json = fs::read_to_string(path)
+            .unwrap_or_else(|e| panic!("failed to read ENCRYPTION_KEY_FILE {}: {}", path, e));
+        let file: KeyFile = serde_json::from_str(&json)
+            .unwrap_or_else(|e| panic!("ENCRYPTION_KEY_FILE {} is not a key file: {}", path, e));
+
+        let keys = file
+            .keys
+            .into_iter()
+            .map(|(key_id, key)| match BASE64.decode(&key) {
+                Ok(key) if key.len() == 32 => (key_id, key),
+                _ => panic!(
+                    "key {} in ENCRYPTION_KEY_FILE is not a base64 256-bit key",
+                    key_id
+                ),
+            })
+            .collect::<HashMap<_, _>>();
+        if !keys.contains_key(&file.current_key_id) {
+            panic!(
+                "ENCRYPTION_KEY_FILE has no key {}, its currentKeyId",
+                file.current_key_id
+            );
+        }
+
+        Self {
+            current_key_id: file.current_key_id,
+            keys,
+        }
+    }
+}
+
+#[async_trait]
+impl KeyProvider for LocalKeyProvider {
+    async fn generate_data_key(&self) -> Result<DataKey, ContractError> {
+        let plaintext = rand::random::<[u8; 32]>().to_vec();
+        let encrypted = seal(
+            &self.keys[&self.current_key_id],
+            &plaintext,
+            self.current_key_id.as_bytes(),
+        )?;
+
+        Ok(DataKey {
+            key_id: self.current_key_id.clone(),
+            plaintext,
+            encrypted,
+        })
+    }
+
+    async fn decrypt_data_key(
+        &self,
+        key_id: &str,
+        encrypted: &[u8],
+    ) -> Result<Vec<u8>, ContractError> {
+        let key = self
+            .keys
+            .get(key_id)
+            .ok_or_else(|| ContractError::Encryption(format!("unknown key {}", key_id)))?;
+
+        open(key, encrypted, key_id.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn provider(current_key_id: &str, keys: &[(&str, u8)]) -> LocalKeyProvider {
+        LocalKeyProvider {
+            current_key_id: current_key_id.to_string(),
+            keys: keys
+                .iter()
+                .map(|(key_id, byte)| (key_id.to_string(), vec![*byte; 32]))
+                .collect(),
+        }
+    }
+
+    #[tokio::test]
+    async fn data_keys_are_decrypted_after_a_rotation() {
+        let before = provider("2024-01", &[("2024-01", 1)]);
+        let after = provider("2024-06", &[("2024-01", 1), ("2024-06", 2)]);
+
+        let old = before.generate_data_key().await.unwrap();
+        assert_eq!(old.key_id, "2024-01");
+        assert_eq!(
+            after
+                .decrypt_data_key(&old.key_id, &old.encrypted)
+                .await
+                .unwrap(),
+            old.plaintext
+        );
+
+        let new = after.generate_data_key().await.unwrap();
+        assert_eq!(new.key_id, "2024-06");
+        assert_eq!(
+            after
+                .decrypt_data_key(&new.key_id, &new.encrypted)
+                .await
+                .unwrap(),
+            new.plaintext
+        );
+    }
+
+    #[tokio::test]
+    async fn unknown_key_ids_are_rejected() {
+        let before = provider("2024-01", &[("2024-01", 1)]);
+        let after = provider("2024-06", &[("2024-06", 2)]);
+
+        let old = before.generate_data_key().await.unwrap();
+        assert!(matches!(
+            after.decrypt_data_key(&old.key_id, &old.encrypted).await,
+            Err(ContractError::Encryption(e)) if e == "unknown key 2024-01"
+        ));
+    }
+
+    #[tokio::test]
+    async fn data_keys_are_bound_to_their_key_id() {
+        // The same key material under another id does not decrypt the data key.
+        let provider = provider("2024-01", &[("2024-01", 1), ("copy", 1)]);
+
+        let data_key = provider.generate_data_key().await.unwrap();
+        assert!(provider
+            .decrypt_data_key("copy", &data_key.encrypted)
+            .await
+            .is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "is not a base64 256-bit key")]
+    fn short_keys_are_refused() {
+        let path = std::env::temp_dir().join(format!("short-keys-{}.json", std::process::id()));
+        fs::write(
+            &path,
+            r#"{ "currentKeyId": "short", "keys": { "short": "c2hvcnQ=" } }"#,
+        )
+        .unwrap();
+
+        LocalKeyProvider::from_file(path.to_str().unwrap());
+    }
+}
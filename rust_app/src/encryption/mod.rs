@@ -0,0 +1,378 @@
+//
+// Field-level Encryption
+//
+// The personal fields (see `redact::Personal`) of the contract `{data}` and `{state}`, of the
+// outbox messages and of the stored responses of idempotent requests are encrypted before they
+// are written to DynamoDB and decrypted when they are read. Encryption is by envelope: every write
+// encrypts the fields with a new data key, and stores that data key encrypted by a key of the
+// `KeyProvider`:
+//
+// - `ENCRYPTION_KEY_FILE` names a JSON file of keys, for development and tests.
+// - `ENCRYPTION_KMS_KEY_ID` names a KMS key.
+//
+// Without either, nothing is encrypted.
+//
+// A key is rotated by making a new key current. Every record names the key its data key was
+// encrypted with, so records written before the rotation can still be read, and they are encrypted
+// with the current key the next time they are written.
+//
+
+use crate::error::ContractError;
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use async_trait::async_trait;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use kms::KmsKeyProvider;
+use local::LocalKeyProvider;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::env;
+
+pub mod kms;
+pub mod local;
+
+// Holds the encrypted data key of a record.
+pub const ENVELOPE_FIELD: &str = "$encryption";
+
+// Replaces the value of an encrypted field.
+const ENCRYPTED_FIELD: &str = "$encrypted";
+
+const NONCE_LENGTH: usize = 12;
+
+// A data key, in plain text and encrypted by the key `key_id`.
+pub struct DataKey {
+    pub key_id: String,
+    pub plaintext: Vec<u8>,
+    pub encrypted: Vec<u8>,
+}
+
+//
+// Supplies data keys, in the manner of KMS `GenerateDataKey` and `Decrypt`.
+//
+#[async_trait]
+pub trait KeyProvider: Send + Sync {
+    // A new 256-bit data key, encrypted by the current key.
+    async fn generate_data_key(&self) -> Result<DataKey, ContractError>;
+
+    // Decrypts a data key encrypted by the key `key_id`.
+    async fn decrypt_data_key(
+        &self,
+        key_id: &str,
+        encrypted: &[u8],
+    ) -> Result<Vec<u8>, ContractError>;
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Envelope {
+    #[serde(rename = "keyId")]
+    key_id: String,
+
+    #[serde(rename = "encryptedKey")]
+    encrypted_key: String,
+}
+
+pub struct FieldEncryption {
+    provider: Box<dyn KeyProvider>,
+}
+
+impl FieldEncryption {
+    //
+    // Function from_env
+    //
+    // Returns `None`, disabling encryption, when no key provider is configured.
+    //
+    pub async fn from_env() -> Option<Self> {
+        let key_file = env::var("ENCRYPTION_KEY_FILE")
+            .ok()
+            .filter(|path| !path.is_empty());
+        let kms_key_id = env::var("ENCRYPTION_KMS_KEY_ID")
+            .ok()
+            .filter(|key_id| !key_id.is_empty());
+
+        let provider: Box<dyn KeyProvider> = match (key_file, kms_key_id) {
+            (Some(path), None) => Box::new(LocalKeyProvider::from_file(&path)),
+            (None, Some(key_id)) => Box::new(KmsKeyProvider::new(key_id).await),
+            (Some(_), Some(_)) => {
+                panic!("set only one of ENCRYPTION_KEY_FILE and ENCRYPTION_KMS_KEY_ID")
+            }
+            (None, None) => return None,
+        };
+
+        Some(Self { provider })
+    }
+
+    //
+    // Function encrypt
+    //
+    // Encrypts the fields at `paths` in `record` with a new data key.
+    //
+    pub async fn encrypt(&self, record: &mut Value, paths: &[String]) -> Result<(), ContractError> {
+        let data_key = self.provider.generate_data_key().await?;
+
+        for path in paths {
+            let segments = path.split('.').collect::<Vec<_>>();
+            encrypt_at(record, &segments, path, &data_key.plaintext)?;
+        }
+
+        if let Value::Object(map) = record {
+            map.insert(
+                ENVELOPE_FIELD.to_string(),
+                json!(Envelope {
+                    key_id: data_key.key_id,
+                    encrypted_key: BASE64.encode(&data_key.encrypted),
+                }),
+            );
+        }
+
+        Ok(())
+    }
+}
+
+//
+// Function encrypt_record
+//
+// Encrypts the fields at `paths` in `record` when encryption is enabled.
+//
+pub async fn encrypt_record(
+    encryption: Option<&FieldEncryption>,
+    record: &mut Value,
+    paths: &[String],
+) -> Result<(), ContractError> {
+    match encryption {
+        Some(encryption) => encryption.encrypt(record, paths).await,
+        None => Ok(()),
+    }
+}
+
+//
+// Function decrypt_record
+//
+// Decrypts every encrypted field of `record`, whichever fields are configured now. A record
+// written without encryption is returned unchanged.
+//
+pub async fn decrypt_record(
+    encryption: Option<&FieldEncryption>,
+    record: &mut Value,
+) -> Result<(), ContractError> {
+    let envelope = match record {
+        Value::Object(map) => match map.remove(ENVELOPE_FIELD) {
+            Some(envelope) => serde_json::from_value::<Envelope>(envelope)
+                .map_err(|e| ContractError::Encryption(e.to_string()))?,
+            None => return Ok(()),
+        },
+        _ => return Ok(()),
+    };
+
+    let encryption = encryption.ok_or_else(|| {
+        ContractError::Encryption("the record is encrypted but no key is configured".to_string())
+    })?;
+    let encrypted_key = BASE64
+        .decode(&envelope.encrypted_key)
+        .map_err(|e| ContractError::Encryption(e.to_string()))?;
+    let data_key = encryption
+        .provider
+        .decrypt_data_key(&envelope.key_id, &encrypted_key)
+        .await?;
+
+    decrypt_all(record, "", &data_key)
+}
+
+fn encrypt_at(
+    value: &mut Value,
+    segments: &[&str],
+    path: &str,
+    key: &[u8],
+) -> Result<(), ContractError> {
+    match (value, segments.split_first()) {
+        (Value::Array(items), _) => {
+            for item in items {
+                encrypt_at(item, segments, path, key)?;
+            }
+        }
+        (Value::Object(map), Some((name, rest))) => {
+            if let Some(value) = map.get_mut(*name) {
+                encrypt_at(value, rest, path, key)?;
+            }
+        }
+        (Value::Null, None) => {}
+        (value, None) => {
+            let sealed = seal(key, value.to_string().as_bytes(), path.as_bytes())?;
+            *value = json!({ ENCRYPTED_FIELD: BASE64.encode(sealed) });
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+// The path of an encrypted field is authenticated with it, so it cannot be moved to another field.
+fn decrypt_all(value: &mut Value, path: &str, key: &[u8]) -> Result<(), ContractError> {
+    match value {
+        Value::Array(items) => {
+            for item in items {
+                decrypt_all(item, path, key)?;
+            }
+        }
+        Value::Object(map) => {
+            if let Some(sealed) = encrypted_value(map) {
+                let sealed = BASE64
+                    .decode(sealed)
+                    .map_err(|e| ContractError::Encryption(e.to_string()))?;
+                let plaintext = open(key, &sealed, path.as_bytes())?;
+                *value = serde_json::from_slice(&plaintext)
+                    .map_err(|e| ContractError::Encryption(e.to_string()))?;
+                return Ok(());
+            }
+
+            for (name, value) in map.iter_mut() {
+                let path = if path.is_empty() {
+                    name.clone()
+                } else {
+                    format!("{}.{}", path, name)
+                };
+                decrypt_all(value, &path, key)?;
+            }
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+fn encrypted_value(map: &Map<String, Value>) -> Option<&str> {
+    match map.len() {
+        1 => map.get(ENCRYPTED_FIELD).and_then(Value::as_str),
+        _ => None,
+    }
+}
+
+//
+// Function seal
+//
+// Encrypts `plaintext` with AES-256-GCM under a random nonce, which is prepended to the result.
+//
+pub fn seal(key: &[u8], plaintext: &[u8], aad: &[u8]) -> Result<Vec<u8>, ContractError> {
+    let cipher = Aes256Gcm::new_from_slice(key)
+        .map_err(|_| ContractError::Encryption("keys must be 256 bits".to_string()))?;
+    let nonce: [u8; NONCE_LENGTH] = rand::random();
+    let ciphertext = cipher
+        .encrypt(
+            Nonce::from_slice(&nonce),
+            Payload {
+                msg: plaintext,
+                aad,
+            },
+        )
+        .map_err(|_| ContractError::Encryption("encryption failed".to_string()))?;
+
+    Ok([nonce.as_slice(), &ciphertext].concat())
+}
+
+//
+// Function open
+//
+// Decrypts the output of `seal`.
+//
+pub fn open(key: &[u8], sealed: &[u8], aad: &[u8]) -> Result<Vec<u8>, ContractError> {
+    if sealed.len() < NONCE_LENGTH {
+        return Err(ContractError::Encryption(
+            "the ciphertext is truncated".to_string(),
+        ));
+    }
+    let cipher = Aes256Gcm::new_from_slice(key)
+        .map_err(|_| ContractError::Encryption("keys must be 256 bits".to_string()))?;
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LENGTH);
+
+    cipher
+        .decrypt(
+            Nonce::from_slice(nonce),
+            Payload {
+                msg: ciphertext,
+                aad,
+            },
+        )
+        .map_err(|_| {
+            ContractError::Encryption("decryption failed, the key or data is wrong".to_string())
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::idempotency::IdempotencyRecord;
+    use crate::outbox::OutboxMessage;
+    use crate::redact::Personal;
+
+    fn encryption(name: &str) -> FieldEncryption {
+        let path = env::temp_dir().join(format!("{}-{}.json", name, std::process::id()));
+        let key = BASE64.encode([7u8; 32]);
+        std::fs::write(
+            &path,
+            json!({ "currentKeyId": "test", "keys": { "test": key } }).to_string(),
+        )
+        .unwrap();
+
+        FieldEncryption {
+            provider: Box::new(LocalKeyProvider::from_file(path.to_str().unwrap())),
+        }
+    }
+
+    #[tokio::test]
+    async fn outbox_payloads_are_stored_encrypted() {
+        let encryption = encryption("outbox-keys");
+        let message = json!({
+            "dedupKey": "deliver#1",
+            "message": {
+                "type": "deliver",
+                "payload": {
+                    "notifyTo": "fred@example.com",
+                    "recipients": [{ "email": "fred@example.com", "name": "Fred Bloggs" }],
+                    "data": { "name": "Fred Bloggs" }
+                }
+            }
+        });
+
+        let mut record = message.clone();
+        encrypt_record(
+            Some(&encryption),
+            &mut record,
+            &OutboxMessage::personal_fields(),
+        )
+        .await
+        .unwrap();
+        let stored = record.to_string();
+        assert!(!stored.contains("fred@example.com"));
+        assert!(!stored.contains("Fred Bloggs"));
+        assert!(stored.contains("deliver#1"));
+
+        decrypt_record(Some(&encryption), &mut record)
+            .await
+            .unwrap();
+        assert_eq!(record, message);
+    }
+
+    #[tokio::test]
+    async fn stored_responses_are_encrypted_whole() {
+        let encryption = encryption("response-keys");
+        let response = json!({
+            "idempotencyKey": "retry-1",
+            "response": { "output": "Hello Fred Bloggs", "counter": 2 }
+        });
+
+        let mut record = response.clone();
+        encrypt_record(
+            Some(&encryption),
+            &mut record,
+            &IdempotencyRecord::personal_fields(),
+        )
+        .await
+        .unwrap();
+        assert!(!record.to_string().contains("Fred Bloggs"));
+        assert_eq!(record["idempotencyKey"], "retry-1");
+
+        decrypt_record(Some(&encryption), &mut record)
+            .await
+            .unwrap();
+        assert_eq!(record, response);
+    }
+}
@@ -0,0 +1,433 @@
+//
+// Right to Erasure
+//
+// `ErasePersonalData` (see `handlers::erase_personal_data`) removes a person's personal data from
+// the contract while keeping a verifiable record of it. Every personal field (see `redact::Personal`) of the `{data}`, of its
+// versions and amendments, of the signatures and of the generated agreements that holds one of the
+// person's values, their party identifier and any other `identifiers` given such as an email
+// address, is replaced with a tombstone: `$erased:` followed by 32 random hex digits. A value gets
+// the same tombstone everywhere within one erasure, so the fields that held it can still be told
+// apart from the others, but tombstones are unrelated to the values and cannot be traced back to
+// them. The values are also replaced in the outbox messages and in the stored responses of
+// idempotent requests.
+//
+// Erasing changes the hash of the contract data (see `hash::contract_data_hash`), so the erasure
+// keeps a proof for every data hash a party signed: the hash of the same data once erased. The
+// signatures can then still be checked against the erased data, through the proof.
+//
+// The erasure is recorded in the `{state}`, naming the tombstones but not the erased values, and a
+// `PersonalDataErasedEvent` is emitted.
+//
+
+use crate::error::ContractError;
+use crate::hash::contract_data_hash;
+use crate::state::PartySignature;
+use chrono::{DateTime, Utc};
+use lib::org_accordproject_helloworldstate::*;
+use lib::utils::{deserialize_datetime, serialize_datetime};
+use rand::{rngs::OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+
+const TOMBSTONE_PREFIX: &str = "$erased:";
+
+//
+// An erasure, as recorded in the `{state}`.
+//
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Erasure {
+    #[serde(rename = "erasureId")]
+    pub erasure_id: String,
+
+    #[serde(
+        rename = "erasedAt",
+        serialize_with = "serialize_datetime",
+        deserialize_with = "deserialize_datetime"
+    )]
+    pub erased_at: DateTime<Utc>,
+
+    // The subject of the caller who asked for the erasure, when authentication is enabled, or its
+    // tombstone when the caller erased their own data.
+    #[serde(
+        rename = "requestedBy",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub requested_by: Option<String>,
+
+    #[serde(rename = "reason", default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+
+    // The tombstones that replaced the erased values.
+    #[serde(rename = "tombstones")]
+    pub tombstones: Vec<String>,
+
+    #[serde(rename = "fields")]
+    pub fields: usize,
+
+    #[serde(rename = "proofs", default, skip_serializing_if = "Vec::is_empty")]
+    pub proofs: Vec<ErasureProof>,
+}
+
+//
+// What an erasure changes outside the `{state}`.
+//
+pub struct Erase {
+    // The erased `{data}` the contract was initialised with.
+    pub data: HelloWorldClause,
+
+    // The tombstone of each erased value, to erase it from the outbox messages and the stored
+    // responses. Never stored.
+    pub tombstones: HashMap<String, String>,
+}
+
+//
+// A signed data hash, and the hash of the same data once erased.
+//
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ErasureProof {
+    #[serde(rename = "dataHash")]
+    pub data_hash: String,
+
+    #[serde(rename = "hashVersion")]
+    pub hash_version: u32,
+
+    #[serde(rename = "erasedDataHash")]
+    pub erased_data_hash: String,
+}
+
+//
+// Function tombstones
+//
+// A new random tombstone for each of `values`.
+//
+pub fn tombstones(values: &[&str]) -> HashMap<String, String> {
+    values
+        .iter()
+        .map(|value| {
+            let mut bytes = [0; 16];
+            OsRng.fill_bytes(&mut bytes);
+            (
+                value.to_string(),
+                format!("{}{}", TOMBSTONE_PREFIX, hex::encode(bytes)),
+            )
+        })
+        .collect()
+}
+
+//
+// Function erase
+//
+// Replaces the values found at the personal `paths` of `record` with their `tombstones`. Returns
+// the number of fields replaced.
+//
+pub fn erase<S: AsRef<str>>(
+    record: &mut Value,
+    paths: &[S],
+    tombstones: &HashMap<String, String>,
+) -> usize {
+    let mut erased = 0;
+    for path in paths {
+        visit_strings(
+            record,
+            &path.as_ref().split('.').collect::<Vec<_>>(),
+            &mut |value| {
+                if let Some(tombstone) = tombstones.get(value.as_str()) {
+                    *value = tombstone.clone();
+                    erased += 1;
+                }
+            },
+        );
+    }
+    erased
+}
+
+//
+// Function erase_text
+//
+// Replaces the values anywhere in the strings of `record`, such as a stored response, with their
+// `tombstones`. Where several values start at the same place the longest is replaced, and the text
+// is read once, so a tombstone is never itself rewritten. Returns the number of strings changed.
+//
+pub fn erase_text(record: &mut Value, tombstones: &HashMap<String, String>) -> usize {
+    let mut tombstones = tombstones
+        .iter()
+        .filter(|(value, _)| !value.is_empty())
+        .collect::<Vec<_>>();
+    tombstones.sort_by_key(|(value, _)| std::cmp::Reverse(value.len()));
+
+    let mut erased = 0;
+    visit_all_strings(record, &mut |text| {
+        let mut result = String::with_capacity(text.len());
+        let mut rest = text.as_str();
+        while let Some(c) = rest.chars().next() {
+            match tombstones
+                .iter()
+                .find(|(value, _)| rest.starts_with(value.as_str()))
+            {
+                Some((value, tombstone)) => {
+                    result.push_str(tombstone);
+                    rest = &rest[value.len()..];
+                }
+                None => {
+                    result.push(c);
+                    rest = &rest[c.len_utf8()..];
+                }
+            }
+        }
+
+        if result != *text {
+            *text = result;
+            erased += 1;
+        }
+    });
+    erased
+}
+
+//
+// Function data_copies
+//
+// The copies of the contract data, as they are hashed: the initial `data` and the data of every
+// version in the `{state}` `record`.
+//
+pub fn data_copies(data: &Value, record: &Value) -> Result<Vec<Value>, ContractError> {
+    let versions = record
+        .get("dataVersions")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(|version| version.get("data"));
+
+    std::iter::once(data)
+        .chain(versions)
+        .map(|copy| {
+            serde_json::from_value::<HelloWorldClause>(copy.clone())
+                .and_then(serde_json::to_value)
+                .map_err(|e| ContractError::InvalidState(e.to_string()))
+        })
+        .collect()
+}
+
+//
+// Function proofs
+//
+// The proofs of an erasure. `before` and `after` are the copies of the contract data, the initial
+// data and the data of every version, before and after the erasure. A proof is kept for every copy
+// whose hash was signed, or was the erased hash of an earlier proof, and that the erasure changed.
+//
+pub fn proofs(
+    before: &[Value],
+    after: &[Value],
+    signatures: &[PartySignature],
+    erasures: &[Erasure],
+) -> Result<Vec<ErasureProof>, ContractError> {
+    let signed = signatures
+        .iter()
+        .map(|s| (s.hash_version, s.data_hash.as_str()))
+        .chain(
+            erasures
+                .iter()
+                .flat_map(|erasure| &erasure.proofs)
+                .map(|p| (p.hash_version, p.erased_data_hash.as_str())),
+        )
+        .collect::<Vec<_>>();
+
+    let mut proofs: Vec<ErasureProof> = vec![];
+    for (before, after) in before.iter().zip(after) {
+        for (hash_version, data_hash) in &signed {
+            if contract_data_hash(before, *hash_version)? != *data_hash {
+                continue;
+            }
+
+            let proof = ErasureProof {
+                data_hash: data_hash.to_string(),
+                hash_version: *hash_version,
+                erased_data_hash: contract_data_hash(after, *hash_version)?,
+            };
+            if proof.erased_data_hash != proof.data_hash && !proofs.contains(&proof) {
+                proofs.push(proof);
+            }
+        }
+    }
+
+    Ok(proofs)
+}
+
+// Calls `f` with every string in `value`.
+fn visit_all_strings(value: &mut Value, f: &mut dyn FnMut(&mut String)) {
+    match value {
+        Value::Array(items) => items.iter_mut().for_each(|item| visit_all_strings(item, f)),
+        Value::Object(map) => map.values_mut().for_each(|item| visit_all_strings(item, f)),
+        Value::String(s) => f(s),
+        _ => {}
+    }
+}
+
+// Calls `f` with every string at `path` in `value`. Arrays are traversed.
+fn visit_strings(value: &mut Value, path: &[&str], f: &mut dyn FnMut(&mut String)) {
+    match (value, path.split_first()) {
+        (Value::Array(items), _) => {
+            for item in items {
+                visit_strings(item, path, f);
+            }
+        }
+        (Value::Object(map), Some((name, rest))) => {
+            if let Some(value) = map.get_mut(*name) {
+                visit_strings(value, rest, f);
+            }
+        }
+        (Value::String(s), None) => f(s),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash::{data_hash, HASH_VERSION};
+    use crate::redact::Personal;
+    use crate::state::ContractState;
+    use crate::test_support::{at, clause, party};
+    use serde_json::json;
+
+    fn signature(data: &Value) -> PartySignature {
+        PartySignature {
+            party: "auth0|fred".to_string(),
+            data_hash: data_hash(data),
+            hash_version: HASH_VERSION,
+            signature: "c2lnbmF0dXJl".to_string(),
+            signed_at: at("2024-01-01T00:00:00Z"),
+        }
+    }
+
+    #[test]
+    fn erasing_signed_data_proves_what_it_was_signed_as() {
+        let mut clause = clause();
+        clause.parties = Some(vec![party("auth0|fred", &["owner"])]);
+        let mut data = serde_json::to_value(&clause).unwrap();
+        let signature = signature(&data);
+        let record = json!({});
+
+        let before = data_copies(&data, &record).unwrap();
+        erase(
+            &mut data,
+            &HelloWorldClause::personal_fields(),
+            &tombstones(&["auth0|fred"]),
+        );
+        let after = data_copies(&data, &record).unwrap();
+        let proofs = proofs(&before, &after, std::slice::from_ref(&signature), &[]).unwrap();
+
+        assert_eq!(
+            proofs,
+            vec![ErasureProof {
+                data_hash: signature.data_hash,
+                hash_version: HASH_VERSION,
+                erased_data_hash: data_hash(&data),
+            }]
+        );
+    }
+
+    #[test]
+    fn a_second_erasure_proves_the_first_erased_hash() {
+        let mut clause = clause();
+        clause.parties = Some(vec![party("auth0|fred", &["owner"])]);
+        let mut data = serde_json::to_value(&clause).unwrap();
+        let signature = signature(&data);
+        let record = json!({});
+
+        let before = data_copies(&data, &record).unwrap();
+        erase(
+            &mut data,
+            &HelloWorldClause::personal_fields(),
+            &tombstones(&["auth0|fred"]),
+        );
+        let first = Erasure {
+            erasure_id: "first".to_string(),
+            erased_at: at("2024-02-01T00:00:00Z"),
+            requested_by: None,
+            reason: None,
+            tombstones: vec![],
+            fields: 1,
+            proofs: proofs(
+                &before,
+                &data_copies(&data, &record).unwrap(),
+                std::slice::from_ref(&signature),
+                &[],
+            )
+            .unwrap(),
+        };
+
+        let before = data_copies(&data, &record).unwrap();
+        erase(
+            &mut data,
+            &HelloWorldClause::personal_fields(),
+            &tombstones(&["Fred Bloggs"]),
+        );
+        let second = proofs(
+            &before,
+            &data_copies(&data, &record).unwrap(),
+            &[signature],
+            std::slice::from_ref(&first),
+        )
+        .unwrap();
+
+        assert_eq!(second.len(), 1);
+        assert_eq!(second[0].data_hash, first.proofs[0].erased_data_hash);
+        assert_eq!(second[0].erased_data_hash, data_hash(&data));
+    }
+
+    #[test]
+    fn data_that_was_not_signed_has_no_proof() {
+        let mut data = serde_json::to_value(clause()).unwrap();
+        let record = json!({});
+
+        let before = data_copies(&data, &record).unwrap();
+        erase(
+            &mut data,
+            &HelloWorldClause::personal_fields(),
+            &tombstones(&["Fred Bloggs"]),
+        );
+        let after = data_copies(&data, &record).unwrap();
+
+        assert!(proofs(&before, &after, &[], &[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn tombstones_are_random_and_shared_within_an_erasure() {
+        let tombstones = tombstones(&["auth0|fred"]);
+        let mut record = json!({
+            "signatures": [{ "party": "auth0|fred" }],
+            "amendments": [{ "proposedBy": "auth0|fred", "approvals": ["auth0|fred", "auth0|jane"] }]
+        });
+
+        let erased = erase(&mut record, &ContractState::personal_fields(), &tombstones);
+
+        let tombstone = &tombstones["auth0|fred"];
+        assert_eq!(erased, 3);
+        assert_eq!(record["signatures"][0]["party"], *tombstone);
+        assert_eq!(record["amendments"][0]["proposedBy"], *tombstone);
+        assert_eq!(record["amendments"][0]["approvals"][1], "auth0|jane");
+        assert_ne!(*tombstone, super::tombstones(&["auth0|fred"])["auth0|fred"]);
+        assert!(!tombstone.contains(&crate::hash::sha256_hex(b"auth0|fred")));
+    }
+
+    #[test]
+    fn erases_values_within_text() {
+        let tombstones = tombstones(&["Fred", "Fred Bloggs"]);
+        let mut response = json!({
+            "message": "Agreement will be sent to Fred Bloggs and Fred",
+            "recipients": ["Jane"]
+        });
+
+        assert_eq!(erase_text(&mut response, &tombstones), 1);
+        assert_eq!(
+            response["message"],
+            format!(
+                "Agreement will be sent to {} and {}",
+                tombstones["Fred Bloggs"], tombstones["Fred"]
+            )
+        );
+        assert_eq!(response["recipients"][0], "Jane");
+    }
+}
@@ -0,0 +1,134 @@
+use crate::attribute_value::AttributeValueError;
+use crate::lifecycle::{ContractStatus, Transition};
+use aws_sdk_dynamodb::error::SdkError;
+use std::fmt::Debug;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ContractError {
+    #[error("Contract is not initialized")]
+    NotInitialized,
+
+    #[error("AWS SDK error: {0}")]
+    Store(String),
+
+    #[error("Invalid stored item: {0}")]
+    Conversion(#[from] AttributeValueError),
+
+    #[error("Invalid contract state: {0}")]
+    InvalidState(String),
+
+    #[error("Counter overflow: {0} cannot be incremented without losing precision")]
+    CounterOverflow(f64),
+
+    #[error("The contract state was modified by another request, please retry")]
+    ConcurrentModification,
+
+    #[error("The contract is {0:?}, and only accepts this request when active")]
+    NotActive(ContractStatus),
+
+    #[error("The contract has been terminated")]
+    Terminated,
+
+    #[error(
+        "The contract has already been used, and initialising it again would lose its history"
+    )]
+    AlreadyInitialized,
+
+    #[error("A {from:?} contract cannot {transition:?}")]
+    InvalidTransition {
+        from: ContractStatus,
+        transition: Transition,
+    },
+
+    #[error("Authentication failed: {0}")]
+    Unauthenticated(String),
+
+    #[error("{subject} is not allowed to send {request}")]
+    Forbidden { subject: String, request: String },
+
+    #[error("Invalid signature: {0}")]
+    InvalidSignature(String),
+
+    #[error("Field encryption failed: {0}")]
+    Encryption(String),
+
+    #[error("Invalid request: {0}")]
+    InvalidRequest(String),
+
+    #[error("Idempotency key {0} was already used for a different request")]
+    IdempotencyConflict(String),
+
+    #[error("Unknown request type: {0}")]
+    UnknownRequest(String),
+
+    #[error("Invalid response: {0}")]
+    InvalidResponse(String),
+
+    #[error("Agreement rendering failed: {0}")]
+    Render(String),
+
+    #[error("Failed to store the agreement: {0}")]
+    Output(String),
+
+    #[error("Agreement delivery failed: {0}")]
+    Delivery(String),
+
+    #[error("Unknown amendment: {0}")]
+    UnknownAmendment(String),
+
+    #[error("Unknown agreement generation job: {0}")]
+    UnknownAgreementJob(String),
+
+    #[error("Agreement generation failed: {0}")]
+    Upstream(String),
+
+    #[error("Agreement generation failed with status {status}: {message}")]
+    UpstreamStatus { status: u16, message: String },
+
+    #[error("Agreement generation timed out")]
+    UpstreamTimeout,
+
+    #[error("Agreement generation is unavailable after repeated failures, please retry later")]
+    CircuitOpen,
+}
+
+impl ContractError {
+    // The name of the variant, for metrics.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ContractError::NotInitialized => "NotInitialized",
+            ContractError::Store(_) => "Store",
+            ContractError::Conversion(_) => "Conversion",
+            ContractError::InvalidState(_) => "InvalidState",
+            ContractError::CounterOverflow(_) => "CounterOverflow",
+            ContractError::ConcurrentModification => "ConcurrentModification",
+            ContractError::NotActive(_) => "NotActive",
+            ContractError::Terminated => "Terminated",
+            ContractError::AlreadyInitialized => "AlreadyInitialized",
+            ContractError::InvalidTransition { .. } => "InvalidTransition",
+            ContractError::Unauthenticated(_) => "Unauthenticated",
+            ContractError::Forbidden { .. } => "Forbidden",
+            ContractError::InvalidSignature(_) => "InvalidSignature",
+            ContractError::Encryption(_) => "Encryption",
+            ContractError::InvalidRequest(_) => "InvalidRequest",
+            ContractError::IdempotencyConflict(_) => "IdempotencyConflict",
+            ContractError::UnknownRequest(_) => "UnknownRequest",
+            ContractError::InvalidResponse(_) => "InvalidResponse",
+            ContractError::Render(_) => "Render",
+            ContractError::Output(_) => "Output",
+            ContractError::Delivery(_) => "Delivery",
+            ContractError::UnknownAmendment(_) => "UnknownAmendment",
+            ContractError::UnknownAgreementJob(_) => "UnknownAgreementJob",
+            ContractError::Upstream(_) => "Upstream",
+            ContractError::UpstreamStatus { .. } => "UpstreamStatus",
+            ContractError::UpstreamTimeout => "UpstreamTimeout",
+            ContractError::CircuitOpen => "CircuitOpen",
+        }
+    }
+}
+
+impl<E: Debug, R: Debug> From<SdkError<E, R>> for ContractError {
+    fn from(error: SdkError<E, R>) -> Self {
+        ContractError::Store(format!("{:?}", error))
+    }
+}
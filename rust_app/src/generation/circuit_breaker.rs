@@ -0,0 +1,218 @@
+//
+// Circuit Breaker
+//
+// Stops calling the generation service after `failure_threshold` consecutive upstream failures:
+// the service could not be reached, timed out, or answered with a 5xx, 408 or 429 status. While
+// open, calls fail fast with `ContractError::CircuitOpen`. After `open_for` a single trial call is
+// let through: if the service fails again the circuit reopens, if it answers in any other way,
+// even with an error such as a 400 for invalid data, the circuit closes.
+//
+// The breaker lives for as long as the Lambda execution environment, so it is shared by all the
+// invocations handled by that environment.
+//
+
+use super::duration_from_env;
+use crate::error::ContractError;
+use std::{
+    env,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+const DEFAULT_FAILURE_THRESHOLD: u32 = 5;
+const DEFAULT_OPEN_MS: u64 = 30_000;
+
+enum BreakerState {
+    Closed { failures: u32 },
+    Open { until: Instant },
+    HalfOpen,
+}
+
+pub struct CircuitBreaker {
+    failure_threshold: u32,
+    open_for: Duration,
+    state: Mutex<BreakerState>,
+}
+
+impl CircuitBreaker {
+    pub fn new(failure_threshold: u32, open_for: Duration) -> Self {
+        Self {
+            failure_threshold: failure_threshold.max(1),
+            open_for,
+            state: Mutex::new(BreakerState::Closed { failures: 0 }),
+        }
+    }
+
+    //
+    // Function from_env
+    //
+    // Reads the threshold from `GENERATE_AGREEMENT_BREAKER_THRESHOLD` and how long the circuit stays
+    // open from `GENERATE_AGREEMENT_BREAKER_OPEN_MS`.
+    //
+    pub fn from_env() -> Self {
+        let failure_threshold = env::var("GENERATE_AGREEMENT_BREAKER_THRESHOLD")
+            .map(|value| {
+                value
+                    .parse::<u32>()
+                    .expect("GENERATE_AGREEMENT_BREAKER_THRESHOLD must be a whole number")
+            })
+            .unwrap_or(DEFAULT_FAILURE_THRESHOLD);
+
+        Self::new(
+            failure_threshold,
+            duration_from_env("GENERATE_AGREEMENT_BREAKER_OPEN_MS", DEFAULT_OPEN_MS),
+        )
+    }
+
+    //
+    // Function allow
+    //
+    // Checks whether a call may be made now.
+    //
+    pub fn allow(&self) -> Result<(), ContractError> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+
+        match *state {
+            BreakerState::Closed { .. } => Ok(()),
+            BreakerState::Open { until } if Instant::now() >= until => {
+                *state = BreakerState::HalfOpen;
+                Ok(())
+            }
+            BreakerState::Open { .. } | BreakerState::HalfOpen => Err(ContractError::CircuitOpen),
+        }
+    }
+
+    pub fn record_success(&self) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        *state = BreakerState::Closed { failures: 0 };
+    }
+
+    pub fn record_failure(&self) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+
+        *state = match *state {
+            BreakerState::Closed { failures } if failures + 1 < self.failure_threshold => {
+                BreakerState::Closed {
+                    failures: failures + 1,
+                }
+            }
+            _ => {
+                tracing::warn!(
+                    open_for_ms = self.open_for.as_millis() as u64,
+                    "circuit breaker opened, the generation service will not be called"
+                );
+                BreakerState::Open {
+                    until: Instant::now() + self.open_for,
+                }
+            }
+        };
+    }
+}
+
+//
+// Function is_failure
+//
+// Whether `error` shows the service is unhealthy, as opposed to an answer about the request itself.
+//
+pub fn is_failure(error: &ContractError) -> bool {
+    match error {
+        ContractError::Upstream(_) | ContractError::UpstreamTimeout => true,
+        ContractError::UpstreamStatus { status, .. } => {
+            *status >= 500 || matches!(status, 408 | 429)
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread::sleep;
+
+    fn status(status: u16) -> ContractError {
+        ContractError::UpstreamStatus {
+            status,
+            message: String::new(),
+        }
+    }
+
+    fn trip(breaker: &CircuitBreaker) {
+        for _ in 0..breaker.failure_threshold {
+            breaker.allow().unwrap();
+            breaker.record_failure();
+        }
+    }
+
+    #[test]
+    fn opens_after_consecutive_failures() {
+        let breaker = CircuitBreaker::new(3, Duration::from_secs(60));
+
+        breaker.record_failure();
+        breaker.record_failure();
+        assert!(breaker.allow().is_ok());
+
+        breaker.record_failure();
+        assert!(matches!(breaker.allow(), Err(ContractError::CircuitOpen)));
+    }
+
+    #[test]
+    fn a_success_resets_the_failure_count() {
+        let breaker = CircuitBreaker::new(2, Duration::from_secs(60));
+
+        breaker.record_failure();
+        breaker.record_success();
+        breaker.record_failure();
+        assert!(breaker.allow().is_ok());
+    }
+
+    #[test]
+    fn lets_a_single_probe_through_once_open_for_has_passed() {
+        let breaker = CircuitBreaker::new(1, Duration::from_millis(20));
+        trip(&breaker);
+        assert!(matches!(breaker.allow(), Err(ContractError::CircuitOpen)));
+
+        sleep(Duration::from_millis(30));
+        assert!(breaker.allow().is_ok());
+        assert!(matches!(breaker.allow(), Err(ContractError::CircuitOpen)));
+    }
+
+    #[test]
+    fn closes_when_the_probe_succeeds() {
+        let breaker = CircuitBreaker::new(1, Duration::ZERO);
+        trip(&breaker);
+        breaker.allow().unwrap();
+
+        breaker.record_success();
+        assert!(breaker.allow().is_ok());
+        assert!(breaker.allow().is_ok());
+    }
+
+    #[test]
+    fn reopens_when_the_probe_fails() {
+        let breaker = CircuitBreaker::new(1, Duration::from_millis(50));
+        trip(&breaker);
+        sleep(Duration::from_millis(60));
+        breaker.allow().unwrap();
+
+        breaker.record_failure();
+        assert!(matches!(breaker.allow(), Err(ContractError::CircuitOpen)));
+    }
+
+    #[test]
+    fn server_errors_are_failures_and_client_errors_are_not() {
+        for failure in [
+            status(500),
+            status(502),
+            status(503),
+            status(408),
+            status(429),
+            ContractError::UpstreamTimeout,
+            ContractError::Upstream("connection refused".to_string()),
+        ] {
+            assert!(is_failure(&failure), "{} is a failure", failure);
+        }
+        for answer in [status(400), status(404), status(409), status(422)] {
+            assert!(!is_failure(&answer), "{} is not a failure", answer);
+        }
+    }
+}
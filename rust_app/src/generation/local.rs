@@ -0,0 +1,206 @@
+//
+// Local Agreement Rendering
+//
+// Renders the agreement from the template grammar without calling the generation service. The
+// grammar is read from the file at `TEMPLATE_GRAMMAR_PATH`, or taken from `TEMPLATE_GRAMMAR`, and
+// defaults to the grammar of the hello-world-state template.
+//
+// The rendered agreement is written as a document in the requested format and stored in the
+// `DocumentStore` configured by `AGREEMENT_OUTPUT`. PDFs have a signature block for the party
+// named in the `{data}`, a footer identifying the contract and the hash of the `{data}`, and the
+// requested watermark. The Ergo `options` only apply to the generation service.
+//
+
+use super::output::{store_from_env, DocumentStore};
+use super::{GenerationBackend, GenerationRequest, GenerationResult, OutputFormat};
+use crate::error::ContractError;
+use crate::hash::{contract_data_hash, sha256_hex, HASH_VERSION};
+use crate::pdf::{write_pdf, Block};
+use crate::render::{render, to_blocks, RenderedAgreement};
+use async_trait::async_trait;
+use serde_json::Value;
+use std::{env, fs};
+
+const DEFAULT_GRAMMAR: &str = "Name of the person to greet: {{name}}.\n\nThank you!\n";
+
+pub struct LocalBackend {
+    grammar: String,
+    store: Box<dyn DocumentStore>,
+}
+
+impl LocalBackend {
+    pub fn new(grammar: String, store: Box<dyn DocumentStore>) -> Self {
+        Self { grammar, store }
+    }
+
+    pub fn from_env() -> Self {
+        let grammar = match env::var("TEMPLATE_GRAMMAR_PATH") {
+            Ok(path) => fs::read_to_string(&path)
+                .unwrap_or_else(|e| panic!("failed to read TEMPLATE_GRAMMAR_PATH {}: {}", path, e)),
+            Err(_) => env::var("TEMPLATE_GRAMMAR").unwrap_or_else(|_| DEFAULT_GRAMMAR.to_string()),
+        };
+
+        Self::new(grammar, store_from_env())
+    }
+}
+
+#[async_trait]
+impl GenerationBackend for LocalBackend {
+    async fn generate(
+        &self,
+        request: &GenerationRequest,
+    ) -> Result<GenerationResult, ContractError> {
+        let rendered = render(&self.grammar, &request.data)?;
+
+        let document = match request.format {
+            OutputFormat::Pdf => write_agreement_pdf(&rendered, request)?,
+            OutputFormat::Html => rendered.html.clone().into_bytes(),
+            OutputFormat::Markdown => rendered.markdown.clone().into_bytes(),
+            OutputFormat::Text => rendered.text.clone().into_bytes(),
+        };
+        let document_hash = sha256_hex(&document);
+
+        let document_url = self
+            .store
+            .put(
+                &format!("{}.{}", request.job_id, request.format.extension()),
+                request.format.content_type(),
+                document,
+            )
+            .await?;
+
+        Ok(GenerationResult {
+            job_id: Some(request.job_id.clone()),
+            status: Some("completed".to_string()),
+            document_url: Some(document_url),
+            document_hash: Some(document_hash),
+            message: Some("Agreement has been generated".to_string()),
+            attempts: 1,
+        })
+    }
+}
+
+fn write_agreement_pdf(
+    rendered: &RenderedAgreement,
+    request: &GenerationRequest,
+) -> Result<Vec<u8>, ContractError> {
+    let mut blocks = to_blocks(&rendered.markdown);
+    blocks.push(Block::Heading("Signatures".to_string()));
+    blocks.push(Block::Signature(signatory(&request.data)));
+
+    // The hash the parties sign, so the document can be matched to their signatures.
+    let footer = format!(
+        "Contract {} - Data hash v{} {}",
+        contract_id(&request.data),
+        HASH_VERSION,
+        contract_data_hash(&request.data, HASH_VERSION)?
+    );
+
+    Ok(write_pdf(
+        &blocks,
+        &footer,
+        request.watermark.as_deref(),
+        request.locale.as_deref(),
+    ))
+}
+
+fn contract_id(data: &Value) -> &str {
+    ["contractId", "clauseId", "$identifier"]
+        .iter()
+        .find_map(|field| data.get(field).and_then(Value::as_str))
+        .unwrap_or("unknown")
+}
+
+fn signatory(data: &Value) -> String {
+    data.get("name")
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::sync::{Arc, Mutex};
+
+    // A stored document: its name, content type and bytes.
+    type Document = (String, String, Vec<u8>);
+
+    #[derive(Default, Clone)]
+    struct MemoryStore {
+        documents: Arc<Mutex<Vec<Document>>>,
+    }
+
+    #[async_trait]
+    impl DocumentStore for MemoryStore {
+        async fn put(
+            &self,
+            name: &str,
+            content_type: &str,
+            bytes: Vec<u8>,
+        ) -> Result<String, ContractError> {
+            self.documents.lock().unwrap().push((
+                name.to_string(),
+                content_type.to_string(),
+                bytes,
+            ));
+            Ok(format!("memory:///{}", name))
+        }
+    }
+
+    fn request(format: OutputFormat) -> GenerationRequest {
+        GenerationRequest {
+            job_id: "job-1".to_string(),
+            callback_url: None,
+            callback_token: None,
+            data: json!({ "name": "Fred Bloggs", "clauseId": "clause-1" }),
+            notify_to: "fred@example.com".to_string(),
+            recipients: vec![],
+            template: "hello-world-state@0.15.0.cta".to_string(),
+            options: json!({}),
+            format,
+            locale: None,
+            watermark: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn agreements_can_be_generated_as_plain_text() {
+        let store = MemoryStore::default();
+        let backend = LocalBackend::new(
+            "# Greeting\n\nName of the person to greet: **{{name}}**.\n".to_string(),
+            Box::new(store.clone()),
+        );
+
+        let result = backend
+            .generate(&request(OutputFormat::Text))
+            .await
+            .unwrap();
+
+        let documents = store.documents.lock().unwrap();
+        let (name, content_type, bytes) = &documents[0];
+        assert_eq!(name, "job-1.txt");
+        assert_eq!(content_type, "text/plain; charset=utf-8");
+        let text = String::from_utf8(bytes.clone()).unwrap();
+        assert!(text.contains("Name of the person to greet: Fred Bloggs."));
+        assert!(!text.contains("**"));
+        assert_eq!(result.document_url.as_deref(), Some("memory:///job-1.txt"));
+        assert_eq!(result.document_hash, Some(sha256_hex(bytes)));
+    }
+
+    #[test]
+    fn formats_are_named_in_lowercase() {
+        for (name, format) in [
+            ("pdf", OutputFormat::Pdf),
+            ("html", OutputFormat::Html),
+            ("markdown", OutputFormat::Markdown),
+            ("text", OutputFormat::Text),
+        ] {
+            assert_eq!(
+                serde_json::from_value::<OutputFormat>(json!(name)).unwrap(),
+                format
+            );
+        }
+    }
+}
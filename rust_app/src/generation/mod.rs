@@ -0,0 +1,179 @@
+//
+// Agreement Generation
+//
+// A `GenerationBackend` renders the agreement from the template and the contract `{data}`. The
+// `RemoteBackend` calls the agreement generation service; the `LocalBackend` renders the template
+// grammar itself, for deployments that cannot reach the service.
+//
+
+use crate::error::ContractError;
+use crate::recipient::Recipient;
+use async_trait::async_trait;
+use local::LocalBackend;
+use remote::RemoteBackend;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{env, time::Duration};
+
+pub mod circuit_breaker;
+pub mod local;
+pub mod output;
+pub mod remote;
+pub mod retry;
+
+// What to generate. This is also the body posted to the generation service.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GenerationRequest {
+    #[serde(rename = "jobId")]
+    pub job_id: String,
+
+    #[serde(rename = "callbackUrl")]
+    pub callback_url: Option<String>,
+
+    // Sent back with the `AgreementGenerationCallback`, to prove it comes from the service.
+    #[serde(
+        rename = "callbackToken",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub callback_token: Option<String>,
+
+    pub data: Value,
+
+    #[serde(rename = "notifyTo")]
+    pub notify_to: String,
+
+    pub recipients: Vec<Recipient>,
+
+    pub template: String,
+
+    // The `org.accordproject.ergo.options.Options` of the request, or `{}`.
+    pub options: Value,
+
+    pub format: OutputFormat,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub locale: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub watermark: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    #[default]
+    Pdf,
+    Html,
+    Markdown,
+    Text,
+}
+
+impl OutputFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Pdf => "pdf",
+            OutputFormat::Html => "html",
+            OutputFormat::Markdown => "md",
+            OutputFormat::Text => "txt",
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            OutputFormat::Pdf => "application/pdf",
+            OutputFormat::Html => "text/html; charset=utf-8",
+            OutputFormat::Markdown => "text/markdown; charset=utf-8",
+            OutputFormat::Text => "text/plain; charset=utf-8",
+        }
+    }
+
+    //
+    // Function from_env
+    //
+    // The format of agreements whose request does not name one, from `AGREEMENT_FORMAT`: `pdf`
+    // (the default), `html`, `markdown` or `text`.
+    //
+    pub fn from_env() -> Self {
+        match env::var("AGREEMENT_FORMAT") {
+            Ok(format) if !format.is_empty() => {
+                serde_json::from_value(Value::String(format.clone())).unwrap_or_else(|_| {
+                    panic!(
+                        "AGREEMENT_FORMAT must be 'pdf', 'html', 'markdown' or 'text', found '{}'",
+                        format
+                    )
+                })
+            }
+            _ => OutputFormat::default(),
+        }
+    }
+}
+
+// The body returned by the generation service. Every field is optional since the service only
+// returns what applies to the request, e.g. a `jobId` for queued jobs or a `documentUrl` once the
+// document exists.
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub struct GenerationResult {
+    #[serde(rename = "jobId", default, skip_serializing_if = "Option::is_none")]
+    pub job_id: Option<String>,
+
+    #[serde(rename = "status", default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<String>,
+
+    #[serde(
+        rename = "documentUrl",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub document_url: Option<String>,
+
+    #[serde(
+        rename = "documentHash",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub document_hash: Option<String>,
+
+    #[serde(rename = "message", default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+
+    // The number of calls it took to get this result.
+    #[serde(skip)]
+    pub attempts: u32,
+}
+
+#[async_trait]
+pub trait GenerationBackend: Send + Sync {
+    async fn generate(
+        &self,
+        request: &GenerationRequest,
+    ) -> Result<GenerationResult, ContractError>;
+}
+
+//
+// Function backend_from_env
+//
+// Selects the backend named by `AGREEMENT_BACKEND`: `remote` (the default) or `local`.
+//
+pub fn backend_from_env() -> Box<dyn GenerationBackend> {
+    match env::var("AGREEMENT_BACKEND").as_deref() {
+        Ok("local") => Box::new(LocalBackend::from_env()),
+        Ok("remote") | Err(_) => Box::new(RemoteBackend::from_env()),
+        Ok(other) => panic!(
+            "AGREEMENT_BACKEND must be 'remote' or 'local', found '{}'",
+            other
+        ),
+    }
+}
+
+pub fn duration_from_env(name: &str, default_ms: u64) -> Duration {
+    let ms = env::var(name)
+        .map(|value| {
+            value
+                .parse::<u64>()
+                .unwrap_or_else(|_| panic!("{} must be a whole number of milliseconds", name))
+        })
+        .unwrap_or(default_ms);
+
+    Duration::from_millis(ms)
+}
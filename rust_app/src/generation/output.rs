@@ -0,0 +1,168 @@
+//
+// Document Output
+//
+// Where locally generated documents are written. `AGREEMENT_OUTPUT` is either a local directory,
+// given as `file:///path/to/dir`, or a bucket in an S3-compatible store, given as
+// `s3://bucket/prefix`. For stores other than AWS S3, `AGREEMENT_S3_ENDPOINT` sets the endpoint.
+//
+
+use crate::error::ContractError;
+use async_trait::async_trait;
+use aws_sdk_s3::primitives::ByteStream;
+use std::{env, fs, path::PathBuf};
+
+const DEFAULT_OUTPUT: &str = "file:///tmp/agreements";
+
+#[async_trait]
+pub trait DocumentStore: Send + Sync {
+    // Stores the document under `name` and returns its URL.
+    async fn put(
+        &self,
+        name: &str,
+        content_type: &str,
+        bytes: Vec<u8>,
+    ) -> Result<String, ContractError>;
+}
+
+pub struct DirectoryStore {
+    directory: PathBuf,
+}
+
+#[async_trait]
+impl DocumentStore for DirectoryStore {
+    async fn put(
+        &self,
+        name: &str,
+        _content_type: &str,
+        bytes: Vec<u8>,
+    ) -> Result<String, ContractError> {
+        let path = self.directory.join(name);
+
+        fs::create_dir_all(&self.directory).map_err(|e| ContractError::Output(e.to_string()))?;
+        fs::write(&path, bytes).map_err(|e| ContractError::Output(e.to_string()))?;
+
+        Ok(format!("file://{}", path.display()))
+    }
+}
+
+pub struct S3Store {
+    bucket: String,
+    prefix: String,
+    endpoint: Option<String>,
+}
+
+impl S3Store {
+    // `location` is the bucket, optionally followed by `/` and the prefix of the keys.
+    fn new(location: &str, endpoint: Option<String>) -> Self {
+        let (bucket, prefix) = location.split_once('/').unwrap_or((location, ""));
+        let prefix = if prefix.is_empty() || prefix.ends_with('/') {
+            prefix.to_string()
+        } else {
+            format!("{}/", prefix)
+        };
+
+        Self {
+            bucket: bucket.to_string(),
+            prefix,
+            endpoint,
+        }
+    }
+}
+
+#[async_trait]
+impl DocumentStore for S3Store {
+    async fn put(
+        &self,
+        name: &str,
+        content_type: &str,
+        bytes: Vec<u8>,
+    ) -> Result<String, ContractError> {
+        // Initialize the S3 client.
+        let config = aws_config::load_from_env().await;
+        let mut s3_config = aws_sdk_s3::config::Builder::from(&config);
+        if let Some(endpoint) = &self.endpoint {
+            s3_config = s3_config.endpoint_url(endpoint).force_path_style(true);
+        }
+        let s3_client = aws_sdk_s3::Client::from_conf(s3_config.build());
+
+        let key = format!("{}{}", self.prefix, name);
+        s3_client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(&key)
+            .content_type(content_type)
+            .body(ByteStream::from(bytes))
+            .send()
+            .await
+            .map_err(|e| ContractError::Output(format!("{:?}", e)))?;
+
+        Ok(format!("s3://{}/{}", self.bucket, key))
+    }
+}
+
+//
+// Function store_from_env
+//
+// The store named by `AGREEMENT_OUTPUT`, defaulting to `file:///tmp/agreements`.
+//
+pub fn store_from_env() -> Box<dyn DocumentStore> {
+    let output = env::var("AGREEMENT_OUTPUT").unwrap_or_else(|_| DEFAULT_OUTPUT.to_string());
+
+    if let Some(directory) = output.strip_prefix("file://") {
+        return Box::new(DirectoryStore {
+            directory: PathBuf::from(directory),
+        });
+    }
+
+    if let Some(location) = output.strip_prefix("s3://") {
+        return Box::new(S3Store::new(
+            location,
+            env::var("AGREEMENT_S3_ENDPOINT").ok(),
+        ));
+    }
+
+    panic!(
+        "AGREEMENT_OUTPUT must start with file:// or s3://, found '{}'",
+        output
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn documents_are_written_to_the_directory() {
+        let directory = env::temp_dir().join(format!("agreements-{}", uuid::Uuid::new_v4()));
+        let store = DirectoryStore {
+            directory: directory.clone(),
+        };
+
+        let url = store
+            .put("job-1.pdf", "application/pdf", b"%PDF-1.4".to_vec())
+            .await
+            .unwrap();
+
+        let written = fs::read(directory.join("job-1.pdf")).unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(
+            url,
+            format!("file://{}", directory.join("job-1.pdf").display())
+        );
+        assert_eq!(written, b"%PDF-1.4");
+    }
+
+    #[test]
+    fn s3_prefixes_end_with_a_slash() {
+        for (location, bucket, prefix) in [
+            ("agreements", "agreements", ""),
+            ("agreements/", "agreements", ""),
+            ("agreements/signed", "agreements", "signed/"),
+            ("agreements/signed/", "agreements", "signed/"),
+        ] {
+            let store = S3Store::new(location, None);
+            assert_eq!(store.bucket, bucket);
+            assert_eq!(store.prefix, prefix, "{}", location);
+        }
+    }
+}
@@ -0,0 +1,320 @@
+//
+// Remote Agreement Generation Service
+//
+// Client for the service at `GENERATE_AGREEMENT_URL`, which renders the agreement from the template
+// and the contract `{data}`. Non-success status codes, timeouts and connection failures are
+// reported as errors, and the service's response body is parsed so callers can tell what happened.
+//
+// Transient failures are retried according to the `RetryPolicy`, and a `CircuitBreaker` stops
+// calling the service for a while after repeated failures. Every call carries the job id as its
+// `Idempotency-Key`, since the same job may be sent again from the outbox, and the W3C trace
+// context of its span (see `telemetry`).
+//
+
+use super::circuit_breaker::{is_failure, CircuitBreaker};
+use super::retry::{is_retryable, RetryPolicy};
+use super::{duration_from_env, GenerationBackend, GenerationRequest, GenerationResult};
+use crate::error::ContractError;
+use crate::metrics::upstream_call;
+use crate::telemetry;
+use async_trait::async_trait;
+use reqwest::{Client, StatusCode};
+use serde::Deserialize;
+use std::env;
+use tracing::{Instrument, Span};
+
+const DEFAULT_TIMEOUT_MS: u64 = 2500;
+const DEFAULT_CONNECT_TIMEOUT_MS: u64 = 1000;
+
+// Error details returned by the generation service with a non-success status code.
+#[derive(Deserialize, Debug, Default)]
+struct GenerationError {
+    #[serde(default)]
+    error: Option<String>,
+
+    #[serde(default)]
+    message: Option<String>,
+}
+
+pub struct RemoteBackend {
+    http: Client,
+    url: String,
+    retry: RetryPolicy,
+    breaker: CircuitBreaker,
+}
+
+impl RemoteBackend {
+    //
+    // Function from_env
+    //
+    // Reads the service URL from `GENERATE_AGREEMENT_URL`. The request timeout and connect timeout
+    // are read from `GENERATE_AGREEMENT_TIMEOUT_MS` and `GENERATE_AGREEMENT_CONNECT_TIMEOUT_MS`.
+    //
+    pub fn from_env() -> Self {
+        let url = env::var("GENERATE_AGREEMENT_URL").expect("GENERATE_AGREEMENT_URL must be set");
+        let timeout = duration_from_env("GENERATE_AGREEMENT_TIMEOUT_MS", DEFAULT_TIMEOUT_MS);
+        let connect_timeout = duration_from_env(
+            "GENERATE_AGREEMENT_CONNECT_TIMEOUT_MS",
+            DEFAULT_CONNECT_TIMEOUT_MS,
+        );
+
+        let http = Client::builder()
+            .timeout(timeout)
+            .connect_timeout(connect_timeout)
+            .build()
+            .expect("failed to build the HTTP client");
+
+        Self {
+            http,
+            url,
+            retry: RetryPolicy::from_env(),
+            breaker: CircuitBreaker::from_env(),
+        }
+    }
+
+    //
+    // Function send
+    //
+    // A single call to the generation service, in its own span.
+    //
+    async fn send(&self, body: &GenerationRequest) -> Result<GenerationResult, ContractError> {
+        let span = telemetry::http_span("POST", &self.url);
+        let result = self.post(body).instrument(span.clone()).await;
+        if result.is_err() {
+            telemetry::record_error(&span);
+        }
+        result
+    }
+
+    async fn post(&self, body: &GenerationRequest) -> Result<GenerationResult, ContractError> {
+        tracing::debug!(url = %self.url, job_id = %body.job_id, "calling the generation service");
+
+        let mut request = self
+            .http
+            .post(&self.url)
+            .header("Idempotency-Key", &body.job_id);
+        for (name, value) in telemetry::trace_headers() {
+            request = request.header(name, value);
+        }
+        let response = request.json(body).send().await.map_err(upstream_error)?;
+
+        let status = response.status();
+        telemetry::record_status(&Span::current(), status.as_u16());
+        let text = response.text().await.map_err(upstream_error)?;
+        tracing::debug!(status = status.as_u16(), "generation service responded");
+
+        if !status.is_success() {
+            return Err(ContractError::UpstreamStatus {
+                status: status.as_u16(),
+                message: error_message(status, &text),
+            });
+        }
+
+        let mut result = if text.trim().is_empty() {
+            GenerationResult::default()
+        } else {
+            // The service processed the request, so sending it again would not help.
+            serde_json::from_str(&text).map_err(|e| {
+                ContractError::InvalidResponse(format!("the generation service answered: {}", e))
+            })?
+        };
+        if result.status.is_none() {
+            result.status = Some(default_status(status).to_string());
+        }
+
+        Ok(result)
+    }
+}
+
+#[async_trait]
+impl GenerationBackend for RemoteBackend {
+    //
+    // Function generate
+    //
+    // Posts `body` to the generation service, retrying transient failures, and returns the parsed
+    // result of the first successful call.
+    //
+    async fn generate(&self, body: &GenerationRequest) -> Result<GenerationResult, ContractError> {
+        let mut attempt = 1;
+
+        loop {
+            self.breaker.allow()?;
+
+            let error = match upstream_call(self.send(body)).await {
+                Ok(mut result) => {
+                    self.breaker.record_success();
+                    result.attempts = attempt;
+                    tracing::info!(attempts = attempt, "generation succeeded");
+                    return Ok(result);
+                }
+                Err(error) => error,
+            };
+
+            // Every answer settles a half-open breaker, not only the failures that are retried.
+            if is_failure(&error) {
+                self.breaker.record_failure();
+            } else {
+                self.breaker.record_success();
+            }
+
+            if !is_retryable(&error) || attempt >= self.retry.max_attempts {
+                tracing::warn!(attempts = attempt, error = %error, "generation failed");
+                return Err(error);
+            }
+
+            let delay = self.retry.delay(attempt);
+            tracing::info!(
+                attempt,
+                error = %error,
+                retry_in_ms = delay.as_millis() as u64,
+                "generation attempt failed, retrying"
+            );
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+}
+
+fn upstream_error(error: reqwest::Error) -> ContractError {
+    if error.is_timeout() {
+        ContractError::UpstreamTimeout
+    } else {
+        ContractError::Upstream(error.to_string())
+    }
+}
+
+fn error_message(status: StatusCode, text: &str) -> String {
+    let details = serde_json::from_str::<GenerationError>(text).unwrap_or_default();
+
+    details
+        .error
+        .or(details.message)
+        .or_else(|| (!text.trim().is_empty()).then(|| text.trim().to_string()))
+        .unwrap_or_else(|| status.to_string())
+}
+
+fn default_status(status: StatusCode) -> &'static str {
+    if status == StatusCode::ACCEPTED {
+        "accepted"
+    } else {
+        "completed"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generation::OutputFormat;
+    use serde_json::json;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    // Answers every call with the next of `statuses`.
+    async fn service(statuses: Vec<u16>) -> String {
+        service_with_bodies(statuses.into_iter().map(|status| (status, "{}")).collect()).await
+    }
+
+    // Answers every call with the next of `responses`, as a status and a body.
+    async fn service_with_bodies(responses: Vec<(u16, &'static str)>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/generate", listener.local_addr().unwrap());
+
+        tokio::spawn(async move {
+            for (status, body) in responses {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut buffer = vec![0; 64 * 1024];
+                let _ = stream.read(&mut buffer).await;
+                let response = format!(
+                    "HTTP/1.1 {} Status\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+
+        url
+    }
+
+    fn backend(url: String, breaker: CircuitBreaker) -> RemoteBackend {
+        RemoteBackend {
+            http: Client::new(),
+            url,
+            retry: RetryPolicy {
+                max_attempts: 1,
+                base_delay: Duration::ZERO,
+                max_delay: Duration::ZERO,
+            },
+            breaker,
+        }
+    }
+
+    fn request() -> GenerationRequest {
+        GenerationRequest {
+            job_id: "job-1".to_string(),
+            callback_url: None,
+            callback_token: None,
+            data: json!({}),
+            notify_to: "fred@example.com".to_string(),
+            recipients: vec![],
+            template: "hello-world-state@0.15.0.cta".to_string(),
+            options: json!({}),
+            format: OutputFormat::Pdf,
+            locale: None,
+            watermark: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn a_server_error_opens_the_breaker() {
+        let backend = backend(
+            service(vec![500]).await,
+            CircuitBreaker::new(1, Duration::from_secs(60)),
+        );
+
+        let first = backend.generate(&request()).await;
+        assert!(matches!(
+            first,
+            Err(ContractError::UpstreamStatus { status: 500, .. })
+        ));
+        let second = backend.generate(&request()).await;
+        assert!(matches!(second, Err(ContractError::CircuitOpen)));
+    }
+
+    #[tokio::test]
+    async fn a_client_error_from_the_probe_closes_the_breaker() {
+        let backend = backend(
+            service(vec![503, 400, 200]).await,
+            CircuitBreaker::new(1, Duration::ZERO),
+        );
+
+        let outage = backend.generate(&request()).await;
+        assert!(matches!(
+            outage,
+            Err(ContractError::UpstreamStatus { status: 503, .. })
+        ));
+        let probe = backend.generate(&request()).await;
+        assert!(matches!(
+            probe,
+            Err(ContractError::UpstreamStatus { status: 400, .. })
+        ));
+        assert!(backend.generate(&request()).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn an_unreadable_answer_is_not_retried() {
+        let mut backend = backend(
+            service_with_bodies(vec![(200, "<html>"), (200, "{}")]).await,
+            CircuitBreaker::new(1, Duration::from_secs(60)),
+        );
+        backend.retry.max_attempts = 2;
+
+        let result = backend.generate(&request()).await;
+        assert!(matches!(result, Err(ContractError::InvalidResponse(_))));
+
+        // The service answered, so the breaker stays closed.
+        assert!(backend.generate(&request()).await.is_ok());
+    }
+}
@@ -0,0 +1,141 @@
+//
+// Retry Policy
+//
+// Bounded retries with exponential backoff and full jitter: the delay before retry `n` is a random
+// duration between zero and `base_delay * 2^(n - 1)`, capped at `max_delay`.
+//
+
+use super::duration_from_env;
+use crate::error::ContractError;
+use rand::Rng;
+use std::{env, time::Duration};
+
+const DEFAULT_MAX_ATTEMPTS: u32 = 3;
+const DEFAULT_BASE_DELAY_MS: u64 = 100;
+const DEFAULT_MAX_DELAY_MS: u64 = 1000;
+
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl RetryPolicy {
+    //
+    // Function from_env
+    //
+    // Reads the policy from `GENERATE_AGREEMENT_MAX_ATTEMPTS`, `GENERATE_AGREEMENT_BACKOFF_BASE_MS`
+    // and `GENERATE_AGREEMENT_BACKOFF_MAX_MS`.
+    //
+    pub fn from_env() -> Self {
+        let max_attempts = env::var("GENERATE_AGREEMENT_MAX_ATTEMPTS")
+            .map(|value| {
+                value
+                    .parse::<u32>()
+                    .expect("GENERATE_AGREEMENT_MAX_ATTEMPTS must be a whole number")
+            })
+            .unwrap_or(DEFAULT_MAX_ATTEMPTS)
+            .max(1);
+
+        Self {
+            max_attempts,
+            base_delay: duration_from_env(
+                "GENERATE_AGREEMENT_BACKOFF_BASE_MS",
+                DEFAULT_BASE_DELAY_MS,
+            ),
+            max_delay: duration_from_env("GENERATE_AGREEMENT_BACKOFF_MAX_MS", DEFAULT_MAX_DELAY_MS),
+        }
+    }
+
+    //
+    // Function delay
+    //
+    // The jittered delay to wait after the failed attempt number `attempt` (starting at 1).
+    //
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let ceiling = self.ceiling(attempt);
+
+        Duration::from_millis(rand::thread_rng().gen_range(0..=ceiling.as_millis() as u64))
+    }
+
+    // The longest delay after the failed attempt number `attempt`.
+    fn ceiling(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(16);
+
+        self.base_delay
+            .saturating_mul(1 << exponent)
+            .min(self.max_delay)
+    }
+}
+
+//
+// Function is_retryable
+//
+// Failures where the service did not process the request, or asked us to come back later, are
+// retried. Everything else (such as a 400 for invalid data) would fail again in the same way.
+//
+pub fn is_retryable(error: &ContractError) -> bool {
+    match error {
+        ContractError::Upstream(_) | ContractError::UpstreamTimeout => true,
+        ContractError::UpstreamStatus { status, .. } => {
+            matches!(status, 408 | 429 | 502 | 503 | 504)
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(1000),
+        }
+    }
+
+    #[test]
+    fn the_ceiling_doubles_until_the_maximum() {
+        let ceilings = (1..=6)
+            .map(|attempt| policy().ceiling(attempt).as_millis())
+            .collect::<Vec<_>>();
+
+        assert_eq!(ceilings, [100, 200, 400, 800, 1000, 1000]);
+        assert_eq!(policy().ceiling(u32::MAX), Duration::from_millis(1000));
+    }
+
+    #[test]
+    fn delays_are_jittered_below_the_ceiling() {
+        let policy = policy();
+
+        for attempt in 1..=6 {
+            for _ in 0..100 {
+                assert!(policy.delay(attempt) <= policy.ceiling(attempt));
+            }
+        }
+    }
+
+    #[test]
+    fn only_transient_failures_are_retried() {
+        let status = |status| ContractError::UpstreamStatus {
+            status,
+            message: String::new(),
+        };
+
+        for retried in [408, 429, 502, 503, 504] {
+            assert!(is_retryable(&status(retried)), "{} is retried", retried);
+        }
+        for final_status in [400, 401, 404, 422, 500] {
+            assert!(
+                !is_retryable(&status(final_status)),
+                "{} is final",
+                final_status
+            );
+        }
+        assert!(is_retryable(&ContractError::UpstreamTimeout));
+        assert!(is_retryable(&ContractError::Upstream("reset".to_string())));
+        assert!(!is_retryable(&ContractError::CircuitOpen));
+    }
+}
@@ -0,0 +1,387 @@
+use super::{ClauseContext, ClauseHandler, ClauseOutput};
+use crate::delivery::Delivery;
+use crate::error::ContractError;
+use crate::outbox::{Outbound, OutboxMessage};
+use crate::redact::Personal;
+use crate::state::AgreementStatus;
+use async_trait::async_trait;
+use hmac::{Hmac, Mac};
+use lib::utils::serialize_datetime;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::Sha256;
+use std::env;
+
+pub const AGREEMENT_CALLBACK_REQUEST_CLASS: &str =
+    "org.accordproject.helloworldstate.AgreementGenerationCallback";
+pub const AGREEMENT_CALLBACK_RESPONSE_CLASS: &str =
+    "org.accordproject.helloworldstate.AgreementGenerationCallbackResponse";
+pub const AGREEMENT_GENERATED_EVENT_CLASS: &str =
+    "org.accordproject.helloworldstate.AgreementGeneratedEvent";
+
+// Sent by the generation service when a queued job has finished.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct AgreementGenerationCallback {
+    #[serde(rename = "$class")]
+    pub _class: String,
+
+    #[serde(rename = "jobId")]
+    pub job_id: String,
+
+    pub status: AgreementStatus,
+
+    #[serde(rename = "documentUrl", default)]
+    pub document_url: Option<String>,
+
+    #[serde(rename = "documentHash", default)]
+    pub document_hash: Option<String>,
+
+    #[serde(default)]
+    pub error: Option<String>,
+
+    // The `callbackToken` of the generation request, proving the callback comes from the service.
+    #[serde(rename = "callbackToken", default, skip_serializing)]
+    pub callback_token: Option<String>,
+}
+
+impl Personal for AgreementGenerationCallback {}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct AgreementGenerationCallbackResponse {
+    #[serde(rename = "$class")]
+    pub _class: String,
+
+    #[serde(rename = "jobId")]
+    pub job_id: String,
+
+    pub status: AgreementStatus,
+}
+
+pub struct AgreementCallbackHandler {
+    secret: Option<Vec<u8>>,
+}
+
+impl AgreementCallbackHandler {
+    //
+    // Callbacks from the generation service are checked with `AGREEMENT_CALLBACK_SECRET`. Without
+    // it, only the outcomes recorded by the contract itself are accepted.
+    //
+    pub fn from_env() -> Self {
+        Self {
+            secret: callback_secret_from_env(),
+        }
+    }
+}
+
+//
+// Records the outcome of a queued generation job in the `{state}` and emits an
+// `AgreementGeneratedEvent`. The first time a completed agreement is reported, its delivery to the
+// recipients is placed in the outbox.
+//
+// A callback sent to the contract must carry the `callbackToken` issued for its job. Callbacks
+// for a job that has already finished are refused, except for the outcomes recorded by the
+// contract itself, which leave the job as the service reported it.
+//
+#[async_trait]
+impl ClauseHandler for AgreementCallbackHandler {
+    type Request = AgreementGenerationCallback;
+    type Response = AgreementGenerationCallbackResponse;
+
+    fn request_class(&self) -> &'static str {
+        AGREEMENT_CALLBACK_REQUEST_CLASS
+    }
+
+    async fn handle(
+        &self,
+        context: ClauseContext<'_>,
+        callback: AgreementGenerationCallback,
+    ) -> Result<ClauseOutput<AgreementGenerationCallbackResponse>, ContractError> {
+        if callback.status == AgreementStatus::Queued {
+            return Err(ContractError::InvalidRequest(
+                "a callback must report a completed or failed job".to_string(),
+            ));
+        }
+
+        if !context.internal {
+            let secret = self.secret.as_deref().ok_or_else(|| {
+                ContractError::Unauthenticated(
+                    "callbacks are not accepted without AGREEMENT_CALLBACK_SECRET".to_string(),
+                )
+            })?;
+            let token = callback.callback_token.as_deref().unwrap_or_default();
+            if !verify_callback_token(secret, &context.data._identifier, &callback.job_id, token) {
+                return Err(ContractError::Unauthenticated(format!(
+                    "invalid callback token for job {}",
+                    callback.job_id
+                )));
+            }
+        }
+
+        let mut state = context.state;
+        let job = state
+            .agreement_mut(&callback.job_id)
+            .ok_or_else(|| ContractError::UnknownAgreementJob(callback.job_id.clone()))?;
+
+        if job.status != AgreementStatus::Queued {
+            if !context.internal {
+                return Err(ContractError::InvalidRequest(format!(
+                    "job {} has already finished with status {:?}",
+                    job.job_id, job.status
+                )));
+            }
+
+            let response = AgreementGenerationCallbackResponse {
+                _class: AGREEMENT_CALLBACK_RESPONSE_CLASS.to_string(),
+                job_id: job.job_id.clone(),
+                status: job.status,
+            };
+            return Ok(ClauseOutput {
+                response,
+                state,
+                emit: vec![],
+                outbox: vec![],
+                erase: None,
+            });
+        }
+
+        job.status = callback.status;
+        job.updated_at = context.now;
+        job.document_url = callback.document_url;
+        job.document_hash = callback.document_hash;
+        job.error = callback.error;
+
+        let mut outbox = vec![];
+        if job.status == AgreementStatus::Completed {
+            let dedup_key = format!("deliver:{}", job.job_id);
+            let delivery = Delivery {
+                dedup_key: dedup_key.clone(),
+                job_id: job.job_id.clone(),
+                recipients: job.recipients(),
+                document_url: job.document_url.clone(),
+                document_hash: job.document_hash.clone(),
+            };
+            outbox.push(OutboxMessage::new(
+                dedup_key,
+                Outbound::Deliver(delivery),
+                context.now,
+            ));
+        }
+
+        let event = json!({
+            "$class": AGREEMENT_GENERATED_EVENT_CLASS,
+            "jobId": job.job_id,
+            "status": job.status,
+            "documentUrl": job.document_url,
+            "documentHash": job.document_hash,
+            "$timestamp": serialize_datetime(&context.now, serde_json::value::Serializer)
+                .map_err(|e| ContractError::InvalidResponse(e.to_string()))?,
+        });
+
+        let response = AgreementGenerationCallbackResponse {
+            _class: AGREEMENT_CALLBACK_RESPONSE_CLASS.to_string(),
+            job_id: job.job_id.clone(),
+            status: job.status,
+        };
+
+        Ok(ClauseOutput {
+            response,
+            state,
+            emit: vec![event],
+            outbox,
+            erase: None,
+        })
+    }
+}
+
+//
+// Function callback_secret_from_env
+//
+// The secret callback tokens are derived from, `AGREEMENT_CALLBACK_SECRET`.
+//
+pub fn callback_secret_from_env() -> Option<Vec<u8>> {
+    env::var("AGREEMENT_CALLBACK_SECRET")
+        .ok()
+        .filter(|secret| !secret.is_empty())
+        .map(String::into_bytes)
+}
+
+//
+// Function callback_token
+//
+// The token issued to the generation service for job `job_id` of contract `contract_id`: an
+// HMAC-SHA256 of both, so it cannot be made without the secret and only settles its own job.
+//
+pub fn callback_token(secret: &[u8], contract_id: &str, job_id: &str) -> String {
+    hex::encode(
+        token_mac(secret, contract_id, job_id)
+            .finalize()
+            .into_bytes(),
+    )
+}
+
+fn verify_callback_token(secret: &[u8], contract_id: &str, job_id: &str, token: &str) -> bool {
+    hex::decode(token).is_ok_and(|token| {
+        token_mac(secret, contract_id, job_id)
+            .verify_slice(&token)
+            .is_ok()
+    })
+}
+
+fn token_mac(secret: &[u8], contract_id: &str, job_id: &str) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC takes keys of any length");
+    mac.update(contract_id.as_bytes());
+    mac.update(b"\n");
+    mac.update(job_id.as_bytes());
+    mac
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::ClauseContext;
+    use crate::ids::IdGenerator;
+    use crate::lifecycle::ContractStatus;
+    use crate::state::{AgreementJob, ContractState};
+    use crate::test_support::{at, clause, state, CONTRACT_ID};
+
+    const SECRET: &[u8] = b"callback secret";
+
+    fn handler() -> AgreementCallbackHandler {
+        AgreementCallbackHandler {
+            secret: Some(SECRET.to_vec()),
+        }
+    }
+
+    fn with_job(status: AgreementStatus) -> ContractState {
+        let mut state = state(ContractStatus::Active);
+        state.agreements.push(AgreementJob {
+            job_id: "job-1".to_string(),
+            status,
+            notify_to: "fred@example.com".to_string(),
+            recipients: vec![],
+            requested_by: None,
+            requested_at: at("2024-01-01T00:00:00Z"),
+            updated_at: at("2024-01-01T00:00:00Z"),
+            document_url: None,
+            document_hash: None,
+            error: None,
+        });
+        state
+    }
+
+    fn callback(job_id: &str, token: Option<String>) -> AgreementGenerationCallback {
+        AgreementGenerationCallback {
+            _class: AGREEMENT_CALLBACK_REQUEST_CLASS.to_string(),
+            job_id: job_id.to_string(),
+            status: AgreementStatus::Completed,
+            document_url: Some("https://example.com/job-1.pdf".to_string()),
+            document_hash: None,
+            error: None,
+            callback_token: token,
+        }
+    }
+
+    async fn send(
+        handler: &AgreementCallbackHandler,
+        state: ContractState,
+        callback: AgreementGenerationCallback,
+        internal: bool,
+    ) -> Result<ClauseOutput<AgreementGenerationCallbackResponse>, ContractError> {
+        let data = clause();
+        let context = ClauseContext {
+            data: &data,
+            initial: &data,
+            state,
+            now: at("2024-01-02T00:00:00Z"),
+            ids: IdGenerator::new(
+                CONTRACT_ID,
+                &json!({}),
+                &json!({}),
+                at("2024-01-02T00:00:00Z"),
+            ),
+            caller: None,
+            internal,
+        };
+        handler.handle(context, callback).await
+    }
+
+    #[tokio::test]
+    async fn accepts_the_token_issued_for_the_job() {
+        let token = callback_token(SECRET, CONTRACT_ID, "job-1");
+        let output = send(
+            &handler(),
+            with_job(AgreementStatus::Queued),
+            callback("job-1", Some(token)),
+            false,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(output.response.status, AgreementStatus::Completed);
+        assert_eq!(output.outbox.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn refuses_a_missing_or_forged_token() {
+        for token in [
+            None,
+            Some("not hex".to_string()),
+            Some(callback_token(b"another secret", CONTRACT_ID, "job-1")),
+            Some(callback_token(SECRET, "another-contract", "job-1")),
+            Some(callback_token(SECRET, CONTRACT_ID, "job-2")),
+        ] {
+            let result = send(
+                &handler(),
+                with_job(AgreementStatus::Queued),
+                callback("job-1", token),
+                false,
+            )
+            .await;
+            assert!(matches!(result, Err(ContractError::Unauthenticated(_))));
+        }
+    }
+
+    #[tokio::test]
+    async fn refuses_callbacks_without_a_secret() {
+        let token = callback_token(SECRET, CONTRACT_ID, "job-1");
+        let result = send(
+            &AgreementCallbackHandler { secret: None },
+            with_job(AgreementStatus::Queued),
+            callback("job-1", Some(token)),
+            false,
+        )
+        .await;
+
+        assert!(matches!(result, Err(ContractError::Unauthenticated(_))));
+    }
+
+    #[tokio::test]
+    async fn refuses_a_callback_for_a_finished_job() {
+        let token = callback_token(SECRET, CONTRACT_ID, "job-1");
+        let result = send(
+            &handler(),
+            with_job(AgreementStatus::Completed),
+            callback("job-1", Some(token)),
+            false,
+        )
+        .await;
+
+        assert!(matches!(result, Err(ContractError::InvalidRequest(_))));
+    }
+
+    #[tokio::test]
+    async fn an_internal_outcome_for_a_finished_job_changes_nothing() {
+        let output = send(
+            &AgreementCallbackHandler { secret: None },
+            with_job(AgreementStatus::Failed),
+            callback("job-1", None),
+            true,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(output.response.status, AgreementStatus::Failed);
+        assert_eq!(output.state.agreements[0].status, AgreementStatus::Failed);
+        assert!(output.emit.is_empty() && output.outbox.is_empty());
+    }
+}
@@ -0,0 +1,72 @@
+use super::{ClauseContext, ClauseHandler, ClauseOutput};
+use crate::error::ContractError;
+use crate::redact::Personal;
+use crate::state::AgreementJob;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+pub const AGREEMENT_STATUS_REQUEST_CLASS: &str =
+    "org.accordproject.helloworldstate.GetAgreementStatusRequest";
+pub const AGREEMENT_STATUS_RESPONSE_CLASS: &str =
+    "org.accordproject.helloworldstate.AgreementStatusResponse";
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct GetAgreementStatusRequest {
+    #[serde(rename = "$class")]
+    pub _class: String,
+
+    // Only return this job. All jobs are returned when it is omitted.
+    #[serde(rename = "jobId", default)]
+    pub job_id: Option<String>,
+}
+
+impl Personal for GetAgreementStatusRequest {}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct AgreementStatusResponse {
+    #[serde(rename = "$class")]
+    pub _class: String,
+
+    pub agreements: Vec<AgreementJob>,
+}
+
+pub struct AgreementStatusHandler;
+
+//
+// Returns the agreement generation jobs recorded in the `{state}`.
+//
+#[async_trait]
+impl ClauseHandler for AgreementStatusHandler {
+    type Request = GetAgreementStatusRequest;
+    type Response = AgreementStatusResponse;
+
+    fn request_class(&self) -> &'static str {
+        AGREEMENT_STATUS_REQUEST_CLASS
+    }
+
+    async fn handle(
+        &self,
+        context: ClauseContext<'_>,
+        request: GetAgreementStatusRequest,
+    ) -> Result<ClauseOutput<AgreementStatusResponse>, ContractError> {
+        let agreements = match &request.job_id {
+            Some(job_id) => vec![context
+                .state
+                .agreement(job_id)
+                .cloned()
+                .ok_or_else(|| ContractError::UnknownAgreementJob(job_id.clone()))?],
+            None => context.state.agreements.clone(),
+        };
+
+        Ok(ClauseOutput {
+            response: AgreementStatusResponse {
+                _class: AGREEMENT_STATUS_RESPONSE_CLASS.to_string(),
+                agreements,
+            },
+            state: context.state,
+            emit: vec![],
+            outbox: vec![],
+            erase: None,
+        })
+    }
+}
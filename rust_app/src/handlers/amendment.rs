@@ -0,0 +1,500 @@
+use super::{ClauseContext, ClauseHandler, ClauseOutput};
+use crate::amendment::{Amendment, AmendmentStatus, ApprovalPolicy, DataVersion};
+use crate::auth::Caller;
+use crate::error::ContractError;
+use crate::lifecycle::ContractStatus;
+use crate::redact::Personal;
+use crate::state::ContractState;
+use crate::HELLO_WORLD_CLAUSE_CLASS;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use lib::org_accordproject_helloworldstate::*;
+use lib::utils::{deserialize_datetime_option, serialize_datetime, serialize_datetime_option};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+pub const AMEND_CONTRACT_REQUEST_CLASS: &str = "org.accordproject.helloworldstate.AmendContract";
+pub const APPROVE_AMENDMENT_REQUEST_CLASS: &str =
+    "org.accordproject.helloworldstate.ApproveAmendment";
+pub const REJECT_AMENDMENT_REQUEST_CLASS: &str =
+    "org.accordproject.helloworldstate.RejectAmendment";
+pub const AMENDMENT_RESPONSE_CLASS: &str = "org.accordproject.helloworldstate.AmendmentResponse";
+pub const CONTRACT_AMENDED_EVENT_CLASS: &str =
+    "org.accordproject.helloworldstate.ContractAmendedEvent";
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct AmendContract {
+    #[serde(rename = "$class")]
+    pub _class: String,
+
+    // The new `HelloWorldClause`, for the same contract and clause.
+    pub data: Value,
+
+    // When the new data takes effect. Defaults to as soon as the amendment is approved.
+    #[serde(
+        rename = "effectiveFrom",
+        default,
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_datetime_option",
+        deserialize_with = "deserialize_datetime_option"
+    )]
+    pub effective_from: Option<DateTime<Utc>>,
+
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+impl Personal for AmendContract {
+    const DATA_COPIES: &'static [&'static str] = &["data"];
+}
+
+// Sent by a party to approve or reject a proposed amendment.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct AmendmentDecision {
+    #[serde(rename = "$class")]
+    pub _class: String,
+
+    #[serde(rename = "amendmentId")]
+    pub amendment_id: String,
+
+    // The `$identifier` of the deciding party.
+    pub party: String,
+}
+
+impl Personal for AmendmentDecision {
+    const PERSONAL_FIELDS: &'static [&'static str] = &["party"];
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct AmendmentResponse {
+    #[serde(rename = "$class")]
+    pub _class: String,
+
+    #[serde(rename = "amendmentId")]
+    pub amendment_id: String,
+
+    pub status: AmendmentStatus,
+
+    // The data version created by the amendment, once it is approved.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<u32>,
+}
+
+//
+// Proposes new `{data}` for the contract. The amendment is recorded in the `{state}` until the
+// parties have approved or rejected it; a contract without parties is amended straight away.
+//
+pub struct AmendContractHandler {
+    policy: ApprovalPolicy,
+}
+
+impl AmendContractHandler {
+    pub fn from_env() -> Self {
+        Self {
+            policy: ApprovalPolicy::from_env(),
+        }
+    }
+}
+
+#[async_trait]
+impl ClauseHandler for AmendContractHandler {
+    type Request = AmendContract;
+    type Response = AmendmentResponse;
+
+    fn request_class(&self) -> &'static str {
+        AMEND_CONTRACT_REQUEST_CLASS
+    }
+
+    async fn handle(
+        &self,
+        context: ClauseContext<'_>,
+        request: AmendContract,
+    ) -> Result<ClauseOutput<AmendmentResponse>, ContractError> {
+        if context.state.status == ContractStatus::Terminated {
+            return Err(ContractError::Terminated);
+        }
+
+        let data: HelloWorldClause = serde_json::from_value(request.data.clone())
+            .map_err(|e| ContractError::InvalidRequest(e.to_string()))?;
+        if data._class != HELLO_WORLD_CLAUSE_CLASS
+            || data._identifier != context.data._identifier
+            || data.clause_id != context.data.clause_id
+        {
+            return Err(ContractError::InvalidRequest(
+                "an amendment must keep the $class, $identifier and clauseId of the contract"
+                    .to_string(),
+            ));
+        }
+
+        if request
+            .effective_from
+            .is_some_and(|effective_from| effective_from < context.now)
+        {
+            return Err(ContractError::InvalidRequest(
+                "an amendment cannot take effect in the past".to_string(),
+            ));
+        }
+
+        let amendment_id = context.ids.next_id();
+        let mut state = context.state;
+        state.amendments.push(Amendment {
+            amendment_id: amendment_id.clone(),
+            status: AmendmentStatus::Proposed,
+            data: request.data,
+            effective_from: request.effective_from,
+            reason: request.reason,
+            proposed_by: context.caller.map(|caller| caller.subject.clone()),
+            proposed_at: context.now,
+            approvals: vec![],
+            rejected_by: None,
+        });
+
+        let (response, emit) = settle(
+            &mut state,
+            &amendment_id,
+            self.policy,
+            context.data,
+            &context.now,
+        )?;
+
+        Ok(ClauseOutput {
+            response,
+            state,
+            emit,
+            outbox: vec![],
+            erase: None,
+        })
+    }
+}
+
+//
+// Records a party's approval of a proposed amendment, and applies the amendment once it has
+// enough approvals.
+//
+pub struct ApproveAmendmentHandler {
+    policy: ApprovalPolicy,
+}
+
+impl ApproveAmendmentHandler {
+    pub fn from_env() -> Self {
+        Self {
+            policy: ApprovalPolicy::from_env(),
+        }
+    }
+}
+
+#[async_trait]
+impl ClauseHandler for ApproveAmendmentHandler {
+    type Request = AmendmentDecision;
+    type Response = AmendmentResponse;
+
+    fn request_class(&self) -> &'static str {
+        APPROVE_AMENDMENT_REQUEST_CLASS
+    }
+
+    async fn handle(
+        &self,
+        context: ClauseContext<'_>,
+        request: AmendmentDecision,
+    ) -> Result<ClauseOutput<AmendmentResponse>, ContractError> {
+        let mut state = context.state;
+        let amendment = proposed_amendment(&mut state, context.data, context.caller, &request)?;
+        if !amendment.approvals.contains(&request.party) {
+            amendment.approvals.push(request.party.clone());
+        }
+
+        let (response, emit) = settle(
+            &mut state,
+            &request.amendment_id,
+            self.policy,
+            context.data,
+            &context.now,
+        )?;
+
+        Ok(ClauseOutput {
+            response,
+            state,
+            emit,
+            outbox: vec![],
+            erase: None,
+        })
+    }
+}
+
+//
+// Rejects a proposed amendment. Any one party can reject it.
+//
+pub struct RejectAmendmentHandler;
+
+#[async_trait]
+impl ClauseHandler for RejectAmendmentHandler {
+    type Request = AmendmentDecision;
+    type Response = AmendmentResponse;
+
+    fn request_class(&self) -> &'static str {
+        REJECT_AMENDMENT_REQUEST_CLASS
+    }
+
+    async fn handle(
+        &self,
+        context: ClauseContext<'_>,
+        request: AmendmentDecision,
+    ) -> Result<ClauseOutput<AmendmentResponse>, ContractError> {
+        let mut state = context.state;
+        let amendment = proposed_amendment(&mut state, context.data, context.caller, &request)?;
+        amendment.status = AmendmentStatus::Rejected;
+        amendment.rejected_by = Some(request.party.clone());
+
+        Ok(ClauseOutput {
+            response: AmendmentResponse {
+                _class: AMENDMENT_RESPONSE_CLASS.to_string(),
+                amendment_id: request.amendment_id,
+                status: AmendmentStatus::Rejected,
+                version: None,
+            },
+            state,
+            emit: vec![],
+            outbox: vec![],
+            erase: None,
+        })
+    }
+}
+
+//
+// Function proposed_amendment
+//
+// The amendment a party decides on, after checking that the party may decide on it. The amendments
+// of a terminated contract can no longer be decided on.
+//
+fn proposed_amendment<'a>(
+    state: &'a mut ContractState,
+    data: &HelloWorldClause,
+    caller: Option<&Caller>,
+    request: &AmendmentDecision,
+) -> Result<&'a mut Amendment, ContractError> {
+    if state.status == ContractStatus::Terminated {
+        return Err(ContractError::Terminated);
+    }
+    if let Some(caller) = caller {
+        if caller.subject != request.party {
+            return Err(ContractError::Forbidden {
+                subject: caller.subject.clone(),
+                request: format!("{} for {}", request._class, request.party),
+            });
+        }
+    }
+    let is_party = data
+        .parties
+        .iter()
+        .flatten()
+        .any(|party| party._identifier == request.party);
+    if !is_party {
+        return Err(ContractError::InvalidRequest(format!(
+            "{} is not a party to the contract",
+            request.party
+        )));
+    }
+
+    let amendment = state
+        .amendment_mut(&request.amendment_id)
+        .ok_or_else(|| ContractError::UnknownAmendment(request.amendment_id.clone()))?;
+    if amendment.status != AmendmentStatus::Proposed {
+        return Err(ContractError::InvalidRequest(format!(
+            "amendment {} has already been {:?}",
+            amendment.amendment_id, amendment.status
+        )));
+    }
+
+    Ok(amendment)
+}
+
+//
+// Function settle
+//
+// Applies the amendment if the current parties' approvals satisfy the policy: it becomes the next
+// data version, and a `ContractAmendedEvent` is emitted. The first amendment also records the data
+// the contract was initialised with as version 1.
+//
+fn settle(
+    state: &mut ContractState,
+    amendment_id: &str,
+    policy: ApprovalPolicy,
+    current: &HelloWorldClause,
+    now: &DateTime<Utc>,
+) -> Result<(AmendmentResponse, Vec<Value>), ContractError> {
+    let parties = current.parties.as_deref().unwrap_or_default();
+    let next_version = state
+        .data_versions
+        .iter()
+        .map(|version| version.version)
+        .max()
+        .unwrap_or(1)
+        + 1;
+    let first = state.data_versions.is_empty();
+
+    let amendment = state
+        .amendment_mut(amendment_id)
+        .ok_or_else(|| ContractError::UnknownAmendment(amendment_id.to_string()))?;
+    let approvals = parties
+        .iter()
+        .filter(|party| amendment.approvals.contains(&party._identifier))
+        .count();
+
+    let mut response = AmendmentResponse {
+        _class: AMENDMENT_RESPONSE_CLASS.to_string(),
+        amendment_id: amendment_id.to_string(),
+        status: amendment.status,
+        version: None,
+    };
+    if !policy.is_satisfied(approvals, parties.len()) {
+        return Ok((response, vec![]));
+    }
+
+    amendment.status = AmendmentStatus::Approved;
+    let effective_from = amendment
+        .effective_from
+        .map_or(*now, |effective_from| effective_from.max(*now));
+    let version = DataVersion {
+        version: next_version,
+        effective_from: Some(effective_from),
+        amendment_id: Some(amendment_id.to_string()),
+        data: amendment.data.clone(),
+    };
+    let event = json!({
+        "$class": CONTRACT_AMENDED_EVENT_CLASS,
+        "amendmentId": amendment_id,
+        "version": next_version,
+        "effectiveFrom": serialize_datetime(&effective_from, serde_json::value::Serializer)
+            .map_err(|e| ContractError::InvalidResponse(e.to_string()))?,
+        "$timestamp": serialize_datetime(now, serde_json::value::Serializer)
+            .map_err(|e| ContractError::InvalidResponse(e.to_string()))?,
+    });
+
+    if first {
+        state.data_versions.push(DataVersion {
+            version: 1,
+            effective_from: None,
+            amendment_id: None,
+            data: serde_json::to_value(current)
+                .map_err(|e| ContractError::InvalidState(e.to_string()))?,
+        });
+    }
+    state.data_versions.push(version);
+
+    response.status = AmendmentStatus::Approved;
+    response.version = Some(next_version);
+    Ok((response, vec![event]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ids::IdGenerator;
+    use crate::test_support::{at, clause, party, state, CONTRACT_ID};
+
+    fn context<'a>(
+        data: &'a HelloWorldClause,
+        state: ContractState,
+        now: &str,
+    ) -> ClauseContext<'a> {
+        ClauseContext {
+            data,
+            initial: data,
+            state,
+            now: at(now),
+            ids: IdGenerator::new(CONTRACT_ID, &json!({}), &json!({}), at(now)),
+            caller: None,
+            internal: false,
+        }
+    }
+
+    fn amend(name: &str, effective_from: &str) -> AmendContract {
+        let mut data = serde_json::to_value(clause()).unwrap();
+        data["name"] = json!(name);
+
+        AmendContract {
+            _class: AMEND_CONTRACT_REQUEST_CLASS.to_string(),
+            data,
+            effective_from: Some(at(effective_from)),
+            reason: None,
+        }
+    }
+
+    fn handler() -> AmendContractHandler {
+        AmendContractHandler {
+            policy: ApprovalPolicy::All,
+        }
+    }
+
+    #[tokio::test]
+    async fn versions_take_effect_from_their_effective_date() {
+        let data = clause();
+        let output = handler()
+            .handle(
+                context(&data, state(ContractStatus::Active), "2024-01-01T00:00:00Z"),
+                amend("Jane Doe", "2024-02-01T00:00:00Z"),
+            )
+            .await
+            .unwrap();
+        let output = handler()
+            .handle(
+                context(&data, output.state, "2024-01-02T00:00:00Z"),
+                amend("Joe Bloggs", "2024-03-01T00:00:00Z"),
+            )
+            .await
+            .unwrap();
+        let state = output.state;
+
+        let name_at = |at_: &str| state.data_as_of(clause(), at(at_)).unwrap().name;
+        assert_eq!(name_at("2024-01-15T00:00:00Z"), "Fred Bloggs");
+        assert_eq!(name_at("2024-02-01T00:00:00Z"), "Jane Doe");
+        assert_eq!(name_at("2024-02-29T23:59:59Z"), "Jane Doe");
+        assert_eq!(name_at("2024-03-01T00:00:00Z"), "Joe Bloggs");
+    }
+
+    #[tokio::test]
+    async fn the_amendments_of_a_terminated_contract_cannot_be_decided() {
+        let mut data = clause();
+        data.parties = Some(vec![
+            party("auth0|fred", &["owner"]),
+            party("auth0|jane", &["party"]),
+        ]);
+        let proposed = handler()
+            .handle(
+                context(&data, state(ContractStatus::Active), "2024-01-01T00:00:00Z"),
+                amend("Jane Doe", "2024-02-01T00:00:00Z"),
+            )
+            .await
+            .unwrap();
+        let mut terminated = proposed.state;
+        terminated.status = ContractStatus::Terminated;
+        let decision = |class: &str| AmendmentDecision {
+            _class: class.to_string(),
+            amendment_id: proposed.response.amendment_id.clone(),
+            party: "auth0|jane".to_string(),
+        };
+
+        let approve = ApproveAmendmentHandler {
+            policy: ApprovalPolicy::All,
+        };
+        let approved = approve
+            .handle(
+                context(&data, snapshot(&terminated), "2024-01-02T00:00:00Z"),
+                decision(APPROVE_AMENDMENT_REQUEST_CLASS),
+            )
+            .await;
+        assert!(matches!(approved, Err(ContractError::Terminated)));
+
+        let rejected = RejectAmendmentHandler
+            .handle(
+                context(&data, terminated, "2024-01-02T00:00:00Z"),
+                decision(REJECT_AMENDMENT_REQUEST_CLASS),
+            )
+            .await;
+        assert!(matches!(rejected, Err(ContractError::Terminated)));
+    }
+
+    fn snapshot(state: &ContractState) -> ContractState {
+        serde_json::from_value(serde_json::to_value(state).unwrap()).unwrap()
+    }
+}
@@ -0,0 +1,114 @@
+use super::{ClauseContext, ClauseHandler, ClauseOutput};
+use crate::amendment::{version_as_of, Amendment, AmendmentStatus};
+use crate::error::ContractError;
+use crate::redact::Personal;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use lib::utils::{deserialize_datetime_option, serialize_datetime_option};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+pub const CONTRACT_DATA_REQUEST_CLASS: &str =
+    "org.accordproject.helloworldstate.GetContractDataRequest";
+pub const CONTRACT_DATA_RESPONSE_CLASS: &str =
+    "org.accordproject.helloworldstate.ContractDataResponse";
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct GetContractDataRequest {
+    #[serde(rename = "$class")]
+    pub _class: String,
+
+    // Return the data in effect at this time instead of now.
+    #[serde(
+        rename = "asOf",
+        default,
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_datetime_option",
+        deserialize_with = "deserialize_datetime_option"
+    )]
+    pub as_of: Option<DateTime<Utc>>,
+}
+
+impl Personal for GetContractDataRequest {}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct ContractDataResponse {
+    #[serde(rename = "$class")]
+    pub _class: String,
+
+    pub version: u32,
+
+    #[serde(
+        rename = "effectiveFrom",
+        default,
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_datetime_option",
+        deserialize_with = "deserialize_datetime_option"
+    )]
+    pub effective_from: Option<DateTime<Utc>>,
+
+    pub data: Value,
+
+    // Amendments waiting to be approved or rejected.
+    #[serde(rename = "pendingAmendments")]
+    pub pending_amendments: Vec<Amendment>,
+}
+
+pub struct ContractDataHandler;
+
+//
+// Returns the `{data}` in effect now or at the time given by `asOf`, with the amendments that have
+// not been decided yet.
+//
+#[async_trait]
+impl ClauseHandler for ContractDataHandler {
+    type Request = GetContractDataRequest;
+    type Response = ContractDataResponse;
+
+    fn request_class(&self) -> &'static str {
+        CONTRACT_DATA_REQUEST_CLASS
+    }
+
+    async fn handle(
+        &self,
+        context: ClauseContext<'_>,
+        request: GetContractDataRequest,
+    ) -> Result<ClauseOutput<ContractDataResponse>, ContractError> {
+        let at = request.as_of.unwrap_or(context.now);
+        let (version, effective_from, data) = match version_as_of(&context.state.data_versions, at)
+        {
+            Some(version) => (
+                version.version,
+                version.effective_from,
+                version.data.clone(),
+            ),
+            None => (
+                1,
+                None,
+                serde_json::to_value(context.data)
+                    .map_err(|e| ContractError::InvalidResponse(e.to_string()))?,
+            ),
+        };
+        let pending_amendments = context
+            .state
+            .amendments
+            .iter()
+            .filter(|amendment| amendment.status == AmendmentStatus::Proposed)
+            .cloned()
+            .collect();
+
+        Ok(ClauseOutput {
+            response: ContractDataResponse {
+                _class: CONTRACT_DATA_RESPONSE_CLASS.to_string(),
+                version,
+                effective_from,
+                data,
+                pending_amendments,
+            },
+            state: context.state,
+            emit: vec![],
+            outbox: vec![],
+            erase: None,
+        })
+    }
+}
@@ -0,0 +1,147 @@
+use super::{ClauseContext, ClauseHandler, ClauseOutput};
+use crate::erasure::{data_copies, erase, proofs, tombstones, Erase, Erasure};
+use crate::error::ContractError;
+use crate::redact::Personal;
+use crate::state::ContractState;
+use async_trait::async_trait;
+use lib::org_accordproject_helloworldstate::*;
+use lib::utils::serialize_datetime;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+pub const ERASE_PERSONAL_DATA_REQUEST_CLASS: &str =
+    "org.accordproject.helloworldstate.ErasePersonalData";
+pub const ERASE_PERSONAL_DATA_RESPONSE_CLASS: &str =
+    "org.accordproject.helloworldstate.ErasePersonalDataResponse";
+pub const PERSONAL_DATA_ERASED_EVENT_CLASS: &str =
+    "org.accordproject.helloworldstate.PersonalDataErasedEvent";
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct ErasePersonalData {
+    #[serde(rename = "$class")]
+    pub _class: String,
+
+    // The `$identifier` of the party whose data is erased.
+    pub party: String,
+
+    // Other values identifying the same person, such as their email address or name.
+    #[serde(default)]
+    pub identifiers: Vec<String>,
+
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+impl Personal for ErasePersonalData {
+    const PERSONAL_FIELDS: &'static [&'static str] = &["party", "identifiers"];
+}
+
+impl ErasePersonalData {
+    // Every value to erase.
+    pub fn values(&self) -> Vec<&str> {
+        std::iter::once(self.party.as_str())
+            .chain(self.identifiers.iter().map(String::as_str))
+            .filter(|value| !value.is_empty())
+            .collect()
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct ErasePersonalDataResponse {
+    #[serde(rename = "$class")]
+    pub _class: String,
+
+    #[serde(rename = "erasureId")]
+    pub erasure_id: String,
+
+    // How many fields of the `{data}` and the `{state}` were replaced with tombstones.
+    pub erased: usize,
+}
+
+pub struct ErasePersonalDataHandler;
+
+//
+// Erases a person's personal data from the `{data}` and the `{state}` (see `erasure`), whatever
+// the status of the contract. The erased `{data}` and the tombstones to erase the values with from
+// the outbox messages and stored responses are returned with the new `{state}`, to be saved with
+// it.
+//
+#[async_trait]
+impl ClauseHandler for ErasePersonalDataHandler {
+    type Request = ErasePersonalData;
+    type Response = ErasePersonalDataResponse;
+
+    fn request_class(&self) -> &'static str {
+        ERASE_PERSONAL_DATA_REQUEST_CLASS
+    }
+
+    async fn handle(
+        &self,
+        context: ClauseContext<'_>,
+        request: ErasePersonalData,
+    ) -> Result<ClauseOutput<ErasePersonalDataResponse>, ContractError> {
+        let values = request.values();
+        let tombstones = tombstones(&values);
+        let mut data = serde_json::to_value(context.initial)
+            .map_err(|e| ContractError::InvalidState(e.to_string()))?;
+        let mut record = serde_json::to_value(&context.state)
+            .map_err(|e| ContractError::InvalidState(e.to_string()))?;
+
+        let before = data_copies(&data, &record)?;
+        let fields = erase(&mut data, &HelloWorldClause::personal_fields(), &tombstones)
+            + erase(&mut record, &ContractState::personal_fields(), &tombstones);
+        let proofs = proofs(
+            &before,
+            &data_copies(&data, &record)?,
+            &context.state.signatures,
+            &context.state.erasures,
+        )?;
+
+        let erasure = Erasure {
+            erasure_id: context.ids.next_id(),
+            erased_at: context.now,
+            requested_by: context.caller.map(|caller| {
+                tombstones
+                    .get(&caller.subject)
+                    .unwrap_or(&caller.subject)
+                    .clone()
+            }),
+            reason: request.reason.clone(),
+            tombstones: values
+                .iter()
+                .map(|value| tombstones[*value].clone())
+                .collect(),
+            fields,
+            proofs,
+        };
+        let event = json!({
+            "$class": PERSONAL_DATA_ERASED_EVENT_CLASS,
+            "erasureId": erasure.erasure_id,
+            "tombstones": erasure.tombstones,
+            "fields": fields,
+            "$timestamp": serialize_datetime(&context.now, serde_json::value::Serializer)
+                .map_err(|e| ContractError::InvalidResponse(e.to_string()))?,
+        });
+
+        let mut state: ContractState = serde_json::from_value(record)
+            .map_err(|e| ContractError::InvalidState(e.to_string()))?;
+        let erasure_id = erasure.erasure_id.clone();
+        state.erasures.push(erasure);
+
+        Ok(ClauseOutput {
+            response: ErasePersonalDataResponse {
+                _class: ERASE_PERSONAL_DATA_RESPONSE_CLASS.to_string(),
+                erasure_id,
+                erased: fields,
+            },
+            state,
+            emit: vec![event],
+            outbox: vec![],
+            erase: Some(Erase {
+                data: serde_json::from_value(data)
+                    .map_err(|e| ContractError::InvalidState(e.to_string()))?,
+                tombstones,
+            }),
+        })
+    }
+}
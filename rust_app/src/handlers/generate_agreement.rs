@@ -0,0 +1,375 @@
+use super::agreement_callback::{callback_secret_from_env, callback_token};
+use super::{ClauseContext, ClauseHandler, ClauseOutput};
+use crate::error::ContractError;
+use crate::generation::{GenerationRequest, OutputFormat};
+use crate::outbox::{Outbound, OutboxMessage};
+use crate::recipient::{validate_recipients, Recipient, RecipientRole};
+use crate::redact::Personal;
+use crate::state::{AgreementJob, AgreementStatus};
+use async_trait::async_trait;
+use lib::org_accordproject_ergo_options::Options;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::env;
+
+pub const GENERATE_AGREEMENT_REQUEST_CLASS: &str =
+    "org.accordproject.helloworldstate.GenerateAgreementAsPDFRequest";
+pub const GENERATE_AGREEMENT_RESPONSE_CLASS: &str =
+    "org.accordproject.helloworldstate.GenerateAgreementAsPDFResponse";
+pub const OPTIONS_CLASS: &str = "org.accordproject.ergo.options.Options";
+
+const MAX_WATERMARK_LENGTH: usize = 40;
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct GenerateAgreementAsPDFRequest {
+    #[serde(rename = "$class")]
+    pub _class: String,
+
+    // A single party to send the agreement to. Kept for existing callers; use `recipients`.
+    #[serde(
+        rename = "notifyTo",
+        alias = "notify_to",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub notify_to: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub recipients: Vec<Recipient>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub options: Option<Options>,
+
+    // `AGREEMENT_FORMAT` when not given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<OutputFormat>,
+
+    // A BCP 47 language tag such as `en` or `en-GB`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub locale: Option<String>,
+
+    // Text printed across every page, such as `DRAFT`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub watermark: Option<String>,
+}
+
+impl Personal for GenerateAgreementAsPDFRequest {
+    const PERSONAL_FIELDS: &'static [&'static str] =
+        &["notifyTo", "recipients.email", "recipients.name"];
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct GenerateAgreementAsPDFResponse {
+    #[serde(rename = "$class")]
+    pub _class: String,
+
+    pub message: String,
+
+    pub status: AgreementStatus,
+
+    // Identifies the generation job, for `GetAgreementStatusRequest`.
+    #[serde(rename = "jobId")]
+    pub job_id: String,
+}
+
+impl GenerateAgreementAsPDFRequest {
+    // Everyone the agreement is sent to, starting with `notifyTo`.
+    pub fn recipients(&self) -> Vec<Recipient> {
+        let mut recipients = vec![];
+        if let Some(notify_to) = &self.notify_to {
+            if !self
+                .recipients
+                .iter()
+                .any(|recipient| recipient.email.eq_ignore_ascii_case(notify_to))
+            {
+                recipients.push(Recipient::party(notify_to));
+            }
+        }
+        recipients.extend(self.recipients.iter().cloned());
+        recipients
+    }
+}
+
+pub struct GenerateAgreementHandler {
+    template: String,
+    callback_url: Option<String>,
+    callback_secret: Option<Vec<u8>>,
+    format: OutputFormat,
+}
+
+impl GenerateAgreementHandler {
+    //
+    // Agreements are generated from the template named by `TEMPLATE_NAME`. The generation service
+    // reports the outcome of queued jobs by sending an `AgreementGenerationCallback` request to
+    // `AGREEMENT_CALLBACK_URL`, when it is set, with the token derived from
+    // `AGREEMENT_CALLBACK_SECRET` for the job. Agreements are generated in the `AGREEMENT_FORMAT`
+    // unless the request names a format.
+    //
+    pub fn from_env() -> Self {
+        let template = env::var("TEMPLATE_NAME").expect("TEMPLATE_NAME must be set");
+        let callback_url = env::var("AGREEMENT_CALLBACK_URL")
+            .ok()
+            .filter(|url| !url.is_empty());
+        let callback_secret = callback_secret_from_env();
+        if callback_url.is_some() && callback_secret.is_none() {
+            panic!("AGREEMENT_CALLBACK_SECRET must be set with AGREEMENT_CALLBACK_URL");
+        }
+
+        Self {
+            template,
+            callback_url,
+            callback_secret,
+            format: OutputFormat::from_env(),
+        }
+    }
+}
+
+//
+// Queues a job to generate the agreement from its `{data}`. The agreement generation service
+// renders the agreement as a PDF and sends it to the recipients; the local backend renders the
+// agreement itself.
+//
+// The job is recorded in the `{state}` and its id returned straight away, and the call to the
+// generation backend is placed in the outbox to be made once the `{state}` is saved. The outcome
+// is recorded by an `AgreementGenerationCallback`, either sent by the generation service or made
+// by the dispatcher when the backend completes the job straight away.
+//
+#[async_trait]
+impl ClauseHandler for GenerateAgreementHandler {
+    type Request = GenerateAgreementAsPDFRequest;
+    type Response = GenerateAgreementAsPDFResponse;
+
+    fn request_class(&self) -> &'static str {
+        GENERATE_AGREEMENT_REQUEST_CLASS
+    }
+
+    async fn handle(
+        &self,
+        context: ClauseContext<'_>,
+        request: GenerateAgreementAsPDFRequest,
+    ) -> Result<ClauseOutput<GenerateAgreementAsPDFResponse>, ContractError> {
+        validate_request(&request)?;
+
+        let recipients = request.recipients();
+        validate_recipients(&recipients)?;
+        let notify_to = recipients
+            .iter()
+            .find(|recipient| recipient.role == RecipientRole::Party)
+            .map(|recipient| recipient.email.clone())
+            .unwrap_or_default();
+
+        let data = serde_json::to_value(context.data)
+            .map_err(|e| ContractError::InvalidState(e.to_string()))?;
+
+        let job_id = context.ids.next_id();
+
+        let options = match &request.options {
+            Some(options) => serde_json::to_value(options)
+                .map_err(|e| ContractError::InvalidRequest(e.to_string()))?,
+            None => json!({}),
+        };
+
+        let callback_token = self
+            .callback_url
+            .as_ref()
+            .zip(self.callback_secret.as_deref())
+            .map(|(_, secret)| callback_token(secret, &context.data._identifier, &job_id));
+
+        let generation_request = GenerationRequest {
+            job_id: job_id.clone(),
+            callback_url: self.callback_url.clone(),
+            callback_token,
+            data,
+            notify_to: notify_to.clone(),
+            recipients: recipients.clone(),
+            template: self.template.clone(),
+            options,
+            format: request.format.unwrap_or(self.format),
+            locale: request.locale.clone(),
+            watermark: request.watermark.clone(),
+        };
+        tracing::info!(
+            job_id = %job_id,
+            template = %generation_request.template,
+            format = ?generation_request.format,
+            recipients = recipients.len(),
+            "queued agreement generation"
+        );
+
+        let addresses = recipients
+            .iter()
+            .map(|recipient| recipient.email.as_str())
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        let mut state = context.state;
+        state.agreements.push(AgreementJob {
+            job_id: job_id.clone(),
+            status: AgreementStatus::Queued,
+            notify_to,
+            recipients,
+            requested_by: context.caller.map(|caller| caller.subject.clone()),
+            requested_at: context.now,
+            updated_at: context.now,
+            document_url: None,
+            document_hash: None,
+            error: None,
+        });
+
+        Ok(ClauseOutput {
+            response: GenerateAgreementAsPDFResponse {
+                _class: GENERATE_AGREEMENT_RESPONSE_CLASS.to_string(),
+                message: format!(
+                    "Agreement generation has been queued and will be sent to {}",
+                    addresses
+                ),
+                status: AgreementStatus::Queued,
+                job_id: job_id.clone(),
+            },
+            state,
+            emit: vec![],
+            outbox: vec![OutboxMessage::new(
+                format!("generate:{}", job_id),
+                Outbound::GenerateAgreement(generation_request),
+                context.now,
+            )],
+            erase: None,
+        })
+    }
+}
+
+//
+// Function validate_request
+//
+// Checks the generation options before anything is sent to the backend.
+//
+fn validate_request(request: &GenerateAgreementAsPDFRequest) -> Result<(), ContractError> {
+    if let Some(options) = &request.options {
+        if options._class != OPTIONS_CLASS {
+            return Err(ContractError::InvalidRequest(format!(
+                "options must be an {}, found {}",
+                OPTIONS_CLASS, options._class
+            )));
+        }
+    }
+
+    if let Some(locale) = &request.locale {
+        if !is_language_tag(locale) {
+            return Err(ContractError::InvalidRequest(format!(
+                "locale must be a language tag such as en or en-GB, found '{}'",
+                locale
+            )));
+        }
+    }
+
+    if let Some(watermark) = &request.watermark {
+        if watermark.trim().is_empty()
+            || watermark.chars().count() > MAX_WATERMARK_LENGTH
+            || watermark.chars().any(char::is_control)
+        {
+            return Err(ContractError::InvalidRequest(format!(
+                "watermark must be a single line of 1 to {} characters",
+                MAX_WATERMARK_LENGTH
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+// A language subtag of 2 to 8 letters followed by subtags of 1 to 8 letters or digits.
+fn is_language_tag(tag: &str) -> bool {
+    let mut subtags = tag.split('-');
+    let language = subtags.next().unwrap_or_default();
+
+    (2..=8).contains(&language.len())
+        && language.chars().all(|c| c.is_ascii_alphabetic())
+        && subtags.all(|subtag| {
+            (1..=8).contains(&subtag.len()) && subtag.chars().all(|c| c.is_ascii_alphanumeric())
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    fn request(fields: Value) -> GenerateAgreementAsPDFRequest {
+        let mut request = json!({
+            "$class": GENERATE_AGREEMENT_REQUEST_CLASS,
+            "recipients": [{ "email": "fred@example.com", "role": "party" }]
+        });
+        request
+            .as_object_mut()
+            .unwrap()
+            .extend(fields.as_object().unwrap().clone());
+
+        serde_json::from_value(request).unwrap()
+    }
+
+    fn is_valid(fields: Value) -> bool {
+        validate_request(&request(fields)).is_ok()
+    }
+
+    #[test]
+    fn locales_are_language_tags() {
+        for locale in ["en", "en-GB", "zh-Hant-TW", "es-419", "de-CH-1901"] {
+            assert!(is_valid(json!({ "locale": locale })), "{}", locale);
+        }
+        for locale in [
+            "",
+            "e",
+            "en_GB",
+            "en-",
+            "-GB",
+            "languages-GB",
+            "en-GB!",
+            "日本",
+        ] {
+            assert!(!is_valid(json!({ "locale": locale })), "{}", locale);
+        }
+    }
+
+    #[test]
+    fn watermarks_are_short_single_lines() {
+        assert!(is_valid(json!({ "watermark": "DRAFT" })));
+        assert!(is_valid(
+            json!({ "watermark": "É".repeat(MAX_WATERMARK_LENGTH) })
+        ));
+
+        assert!(!is_valid(json!({ "watermark": "" })));
+        assert!(!is_valid(json!({ "watermark": "   " })));
+        assert!(!is_valid(
+            json!({ "watermark": "x".repeat(MAX_WATERMARK_LENGTH + 1) })
+        ));
+        assert!(!is_valid(json!({ "watermark": "DRAFT\nCOPY" })));
+        assert!(!is_valid(json!({ "watermark": "DRAFT\u{7}" })));
+    }
+
+    #[test]
+    fn options_must_be_ergo_options() {
+        let options = |class: &str| json!({ "options": { "$class": class, "wrapVariables": false, "template": false } });
+
+        assert!(is_valid(options(OPTIONS_CLASS)));
+        assert!(matches!(
+            validate_request(&request(options("org.example.Options"))),
+            Err(ContractError::InvalidRequest(e)) if e.contains("org.example.Options")
+        ));
+    }
+
+    #[test]
+    fn notify_to_is_the_first_recipient_unless_already_listed() {
+        let added = request(json!({ "notifyTo": "alice@example.com" }));
+        let listed = request(json!({ "notifyTo": "FRED@example.com" }));
+
+        assert_eq!(
+            added
+                .recipients()
+                .iter()
+                .map(|recipient| recipient.email.as_str())
+                .collect::<Vec<_>>(),
+            ["alice@example.com", "fred@example.com"]
+        );
+        assert_eq!(listed.recipients().len(), 1);
+    }
+}
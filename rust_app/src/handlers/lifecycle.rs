@@ -0,0 +1,243 @@
+use super::{ClauseContext, ClauseHandler, ClauseOutput};
+use crate::error::ContractError;
+use crate::lifecycle::{transition, ContractStatus, Transition};
+use crate::redact::Personal;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use lib::utils::serialize_datetime;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+pub const ACTIVATE_CONTRACT_REQUEST_CLASS: &str =
+    "org.accordproject.helloworldstate.ActivateContract";
+pub const SUSPEND_CONTRACT_REQUEST_CLASS: &str =
+    "org.accordproject.helloworldstate.SuspendContract";
+pub const RESUME_CONTRACT_REQUEST_CLASS: &str = "org.accordproject.helloworldstate.ResumeContract";
+pub const TERMINATE_CONTRACT_REQUEST_CLASS: &str =
+    "org.accordproject.helloworldstate.TerminateContract";
+pub const CONTRACT_STATUS_RESPONSE_CLASS: &str =
+    "org.accordproject.helloworldstate.ContractStatusResponse";
+pub const CONTRACT_STATUS_CHANGED_EVENT_CLASS: &str =
+    "org.accordproject.helloworldstate.ContractStatusChangedEvent";
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct LifecycleRequest {
+    #[serde(rename = "$class")]
+    pub _class: String,
+
+    // Why the status is changed, recorded in the emitted event.
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+impl Personal for LifecycleRequest {}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct ContractStatusResponse {
+    #[serde(rename = "$class")]
+    pub _class: String,
+
+    #[serde(rename = "previousStatus")]
+    pub previous_status: ContractStatus,
+
+    pub status: ContractStatus,
+}
+
+//
+// Moves the contract through its lifecycle. One handler is registered for each transition request,
+// and emits a `ContractStatusChangedEvent` when the transition is allowed.
+//
+pub struct LifecycleHandler {
+    request_class: &'static str,
+    transition: Transition,
+}
+
+impl LifecycleHandler {
+    pub fn activate() -> Self {
+        Self {
+            request_class: ACTIVATE_CONTRACT_REQUEST_CLASS,
+            transition: Transition::Activate,
+        }
+    }
+
+    pub fn suspend() -> Self {
+        Self {
+            request_class: SUSPEND_CONTRACT_REQUEST_CLASS,
+            transition: Transition::Suspend,
+        }
+    }
+
+    pub fn resume() -> Self {
+        Self {
+            request_class: RESUME_CONTRACT_REQUEST_CLASS,
+            transition: Transition::Resume,
+        }
+    }
+
+    pub fn terminate() -> Self {
+        Self {
+            request_class: TERMINATE_CONTRACT_REQUEST_CLASS,
+            transition: Transition::Terminate,
+        }
+    }
+}
+
+#[async_trait]
+impl ClauseHandler for LifecycleHandler {
+    type Request = LifecycleRequest;
+    type Response = ContractStatusResponse;
+
+    fn request_class(&self) -> &'static str {
+        self.request_class
+    }
+
+    async fn handle(
+        &self,
+        context: ClauseContext<'_>,
+        request: LifecycleRequest,
+    ) -> Result<ClauseOutput<ContractStatusResponse>, ContractError> {
+        let has_parties = context
+            .data
+            .parties
+            .as_ref()
+            .is_some_and(|parties| !parties.is_empty());
+
+        let mut state = context.state;
+        let previous_status = state.status;
+        state.status = transition(previous_status, self.transition, has_parties)?;
+
+        let event = status_changed_event(
+            previous_status,
+            state.status,
+            request.reason.as_deref(),
+            &context.now,
+        )?;
+
+        Ok(ClauseOutput {
+            response: ContractStatusResponse {
+                _class: CONTRACT_STATUS_RESPONSE_CLASS.to_string(),
+                previous_status,
+                status: state.status,
+            },
+            state,
+            emit: vec![event],
+            outbox: vec![],
+            erase: None,
+        })
+    }
+}
+
+pub fn status_changed_event(
+    from: ContractStatus,
+    to: ContractStatus,
+    reason: Option<&str>,
+    now: &DateTime<Utc>,
+) -> Result<Value, ContractError> {
+    Ok(json!({
+        "$class": CONTRACT_STATUS_CHANGED_EVENT_CLASS,
+        "previousStatus": from,
+        "status": to,
+        "reason": reason,
+        "$timestamp": serialize_datetime(now, serde_json::value::Serializer)
+            .map_err(|e| ContractError::InvalidResponse(e.to_string()))?,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ids::IdGenerator;
+    use crate::test_support::{at, clause, party, state};
+
+    async fn send(
+        handler: LifecycleHandler,
+        status: ContractStatus,
+        parties: bool,
+    ) -> Result<ClauseOutput<ContractStatusResponse>, ContractError> {
+        let mut data = clause();
+        if parties {
+            data.parties = Some(vec![party("auth0|fred", &["buyer"])]);
+        }
+        let now = at("2024-01-01T00:00:00Z");
+
+        handler
+            .handle(
+                ClauseContext {
+                    data: &data,
+                    initial: &data,
+                    state: state(status),
+                    now,
+                    ids: IdGenerator::new(&data._identifier, &json!({}), &json!({}), now),
+                    caller: None,
+                    internal: false,
+                },
+                LifecycleRequest {
+                    _class: handler.request_class.to_string(),
+                    reason: Some("as agreed".to_string()),
+                },
+            )
+            .await
+    }
+
+    #[tokio::test]
+    async fn a_draft_without_parties_is_activated() {
+        let output = send(LifecycleHandler::activate(), ContractStatus::Draft, false)
+            .await
+            .unwrap();
+
+        assert_eq!(output.response.previous_status, ContractStatus::Draft);
+        assert_eq!(output.state.status, ContractStatus::Active);
+        assert_eq!(
+            output.emit[0]["$class"],
+            CONTRACT_STATUS_CHANGED_EVENT_CLASS
+        );
+        assert_eq!(output.emit[0]["previousStatus"], "draft");
+        assert_eq!(output.emit[0]["status"], "active");
+        assert_eq!(output.emit[0]["reason"], "as agreed");
+    }
+
+    #[tokio::test]
+    async fn a_draft_with_parties_is_not_activated() {
+        let result = send(LifecycleHandler::activate(), ContractStatus::Draft, true).await;
+
+        assert!(matches!(
+            result,
+            Err(ContractError::InvalidTransition {
+                from: ContractStatus::Draft,
+                transition: Transition::Activate
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn a_suspended_contract_is_resumed() {
+        let suspended = send(LifecycleHandler::suspend(), ContractStatus::Active, true)
+            .await
+            .unwrap();
+        assert_eq!(suspended.state.status, ContractStatus::Suspended);
+
+        let resumed = send(LifecycleHandler::resume(), suspended.state.status, true)
+            .await
+            .unwrap();
+        assert_eq!(resumed.state.status, ContractStatus::Active);
+    }
+
+    #[tokio::test]
+    async fn a_terminated_contract_stays_terminated() {
+        for handler in [
+            LifecycleHandler::activate(),
+            LifecycleHandler::suspend(),
+            LifecycleHandler::resume(),
+            LifecycleHandler::terminate(),
+        ] {
+            let result = send(handler, ContractStatus::Terminated, false).await;
+            assert!(matches!(
+                result,
+                Err(ContractError::InvalidTransition {
+                    from: ContractStatus::Terminated,
+                    ..
+                })
+            ));
+        }
+    }
+}
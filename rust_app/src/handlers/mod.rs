@@ -0,0 +1,259 @@
+//
+// Clause Handlers
+//
+// Every request type the contract accepts is implemented by a `ClauseHandler`. A handler receives
+// the contract `{data}`, the current `{state}` and the request, and returns its response together
+// with the new `{state}` and any emitted events.
+//
+// To add a new request type, implement `ClauseHandler` in a module of its own and register it in
+// `registry()`. `function_handler` dispatches incoming requests to handlers by their `$class`.
+//
+
+use crate::auth::Caller;
+use crate::erasure::Erase;
+use crate::error::ContractError;
+use crate::ids::IdGenerator;
+use crate::outbox::OutboxMessage;
+use crate::redact::{self, Personal};
+use crate::state::ContractState;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use lib::org_accordproject_helloworldstate::*;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+
+pub mod agreement_callback;
+pub mod agreement_status;
+pub mod amendment;
+pub mod contract_data;
+pub mod erase_personal_data;
+pub mod generate_agreement;
+pub mod lifecycle;
+pub mod my_request;
+pub mod sign_agreement;
+
+pub struct ClauseContext<'a> {
+    // The `{data}` in effect.
+    pub data: &'a HelloWorldClause,
+
+    // The `{data}` the contract was initialised with, before any amendment.
+    pub initial: &'a HelloWorldClause,
+
+    pub state: ContractState,
+
+    // The logical execution time, to be used for every timestamp the handler produces.
+    pub now: DateTime<Utc>,
+
+    // To be used for every id the handler produces.
+    pub ids: IdGenerator,
+
+    // Who sent the request. `None` when authentication is disabled, and for requests the contract
+    // makes itself, such as the outcome of an outbox message.
+    pub caller: Option<&'a Caller>,
+
+    // Whether the contract made the request itself, rather than receiving it.
+    pub internal: bool,
+}
+
+pub struct ClauseOutput<R> {
+    pub response: R,
+    pub state: ContractState,
+    pub emit: Vec<Value>,
+
+    // Calls to other services, made once the new `{state}` is saved.
+    pub outbox: Vec<OutboxMessage>,
+
+    // Personal data to erase from what is kept outside the `{state}`.
+    pub erase: Option<Erase>,
+}
+
+#[async_trait]
+pub trait ClauseHandler: Send + Sync {
+    type Request: DeserializeOwned + Personal + Send;
+    type Response: Serialize;
+
+    // The `$class` of the requests handled by this handler.
+    fn request_class(&self) -> &'static str;
+
+    async fn handle(
+        &self,
+        context: ClauseContext<'_>,
+        request: Self::Request,
+    ) -> Result<ClauseOutput<Self::Response>, ContractError>;
+}
+
+//
+// Type-erased `ClauseHandler`, so handlers with different request and response types can be
+// stored in the same `Registry`.
+//
+#[async_trait]
+trait DynClauseHandler: Send + Sync {
+    async fn handle_value(
+        &self,
+        context: ClauseContext<'_>,
+        request: Value,
+    ) -> Result<ClauseOutput<Value>, ContractError>;
+}
+
+#[async_trait]
+impl<H: ClauseHandler> DynClauseHandler for H {
+    async fn handle_value(
+        &self,
+        context: ClauseContext<'_>,
+        request: Value,
+    ) -> Result<ClauseOutput<Value>, ContractError> {
+        redact::register_personal(context.data);
+        redact::register(&request, &H::Request::personal_fields());
+
+        let request = serde_json::from_value(request)
+            .map_err(|e| ContractError::InvalidRequest(e.to_string()))?;
+        let output = self.handle(context, request).await?;
+
+        Ok(ClauseOutput {
+            response: serde_json::to_value(output.response)
+                .map_err(|e| ContractError::InvalidResponse(e.to_string()))?,
+            state: output.state,
+            emit: output.emit,
+            outbox: output.outbox,
+            erase: output.erase,
+        })
+    }
+}
+
+#[derive(Default)]
+pub struct Registry {
+    handlers: HashMap<&'static str, Box<dyn DynClauseHandler>>,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register<H: ClauseHandler + 'static>(mut self, handler: H) -> Self {
+        self.handlers
+            .insert(handler.request_class(), Box::new(handler));
+        self
+    }
+
+    // The registered `$class` equal to `class`, if there is one.
+    pub fn class(&self, class: &str) -> Option<&'static str> {
+        self.handlers.get_key_value(class).map(|(class, _)| *class)
+    }
+
+    // The `$class` of every registered request type.
+    #[cfg(test)]
+    pub fn classes(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.handlers.keys().copied()
+    }
+
+    pub async fn dispatch(
+        &self,
+        context: ClauseContext<'_>,
+        request: Value,
+    ) -> Result<ClauseOutput<Value>, ContractError> {
+        let class = request_class(&request)
+            .ok_or_else(|| ContractError::InvalidRequest("missing $class".to_string()))?;
+        let handler = self
+            .handlers
+            .get(class)
+            .ok_or_else(|| ContractError::UnknownRequest(class.to_string()))?;
+
+        handler.handle_value(context, request).await
+    }
+}
+
+//
+// Function registry
+//
+// All the request types supported by this contract.
+//
+pub fn registry() -> Registry {
+    Registry::new()
+        .register(my_request::MyRequestHandler)
+        .register(generate_agreement::GenerateAgreementHandler::from_env())
+        .register(agreement_callback::AgreementCallbackHandler::from_env())
+        .register(agreement_status::AgreementStatusHandler)
+        .register(sign_agreement::SignAgreementHandler)
+        .register(lifecycle::LifecycleHandler::activate())
+        .register(lifecycle::LifecycleHandler::suspend())
+        .register(lifecycle::LifecycleHandler::resume())
+        .register(lifecycle::LifecycleHandler::terminate())
+        .register(amendment::AmendContractHandler::from_env())
+        .register(amendment::ApproveAmendmentHandler::from_env())
+        .register(amendment::RejectAmendmentHandler)
+        .register(contract_data::ContractDataHandler)
+        .register(erase_personal_data::ErasePersonalDataHandler)
+}
+
+pub fn request_class(request: &Value) -> Option<&str> {
+    request.get("$class").and_then(Value::as_str)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lifecycle::ContractStatus;
+    use crate::test_support::{at, clause, registry, state};
+    use my_request::{MY_REQUEST_CLASS, MY_RESPONSE_CLASS};
+    use serde_json::json;
+
+    async fn dispatch(request: Value) -> Result<ClauseOutput<Value>, ContractError> {
+        let data = clause();
+        let now = at("2024-01-01T00:00:00Z");
+        let context = ClauseContext {
+            data: &data,
+            initial: &data,
+            state: state(ContractStatus::Active),
+            now,
+            ids: IdGenerator::new(&data._identifier, &json!({}), &request, now),
+            caller: None,
+            internal: false,
+        };
+
+        registry().dispatch(context, request).await
+    }
+
+    #[tokio::test]
+    async fn requests_are_dispatched_by_their_class() {
+        let output = dispatch(json!({
+            "$class": MY_REQUEST_CLASS,
+            "input": "Hi",
+            "$timestamp": "2024-01-01T00:00:00.000Z"
+        }))
+        .await
+        .unwrap();
+
+        assert_eq!(output.response["$class"], MY_RESPONSE_CLASS);
+        assert_eq!(
+            output.response["output"],
+            "Hello Fred Bloggs - Hi - counter: 1"
+        );
+        assert_eq!(output.state.clause.counter, 1.0);
+    }
+
+    #[tokio::test]
+    async fn unregistered_classes_are_unknown_requests() {
+        let result = dispatch(json!({ "$class": "org.example.Unregistered" })).await;
+
+        assert!(matches!(
+            result,
+            Err(ContractError::UnknownRequest(class)) if class == "org.example.Unregistered"
+        ));
+        assert_eq!(registry().class("org.example.Unregistered"), None);
+        assert_eq!(registry().class(MY_REQUEST_CLASS), Some(MY_REQUEST_CLASS));
+    }
+
+    #[tokio::test]
+    async fn requests_need_a_class_and_the_fields_of_their_type() {
+        assert!(matches!(
+            dispatch(json!({ "input": "Hi" })).await,
+            Err(ContractError::InvalidRequest(_))
+        ));
+        assert!(matches!(
+            dispatch(json!({ "$class": MY_REQUEST_CLASS })).await,
+            Err(ContractError::InvalidRequest(_))
+        ));
+    }
+}
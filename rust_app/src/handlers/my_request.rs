@@ -0,0 +1,150 @@
+use super::{ClauseContext, ClauseHandler, ClauseOutput};
+use crate::error::ContractError;
+use crate::lifecycle::require_active;
+use crate::metrics::{self, Unit};
+use crate::redact::Personal;
+use async_trait::async_trait;
+use lib::org_accordproject_helloworldstate::*;
+
+pub const MY_REQUEST_CLASS: &str = "org.accordproject.helloworldstate.MyRequest";
+pub const MY_RESPONSE_CLASS: &str = "org.accordproject.helloworldstate.MyResponse";
+
+// Largest whole number `n` for which `n + 1` is still exactly representable as an `f64` (2^53 - 1).
+const MAX_SAFE_COUNTER: f64 = 9_007_199_254_740_991.0;
+
+pub struct MyRequestHandler;
+
+impl Personal for MyRequest {}
+
+//
+// Clause Function
+//
+// Clause logic for the `MyRequest` clause. Greets the party named in the `{data}` and increments
+// the `{state}` counter. Only an active contract accepts it.
+//
+#[async_trait]
+impl ClauseHandler for MyRequestHandler {
+    type Request = MyRequest;
+    type Response = MyResponse;
+
+    fn request_class(&self) -> &'static str {
+        MY_REQUEST_CLASS
+    }
+
+    async fn handle(
+        &self,
+        context: ClauseContext<'_>,
+        my_request: MyRequest,
+    ) -> Result<ClauseOutput<MyResponse>, ContractError> {
+        require_active(context.state.status)?;
+
+        let mut state = context.state;
+        let counter = next_counter(state.clause.counter)?;
+        state.clause.counter = counter;
+        metrics::record("Counter", Unit::None, counter, vec![]);
+
+        let response = MyResponse {
+            _class: MY_RESPONSE_CLASS.to_string(),
+            output: format!(
+                "Hello {} - {} - counter: {}",
+                context.data.name, my_request.input, counter
+            ),
+            _timestamp: context.now,
+        };
+
+        Ok(ClauseOutput {
+            response,
+            state,
+            emit: vec![],
+            outbox: vec![],
+            erase: None,
+        })
+    }
+}
+
+//
+// Function next_counter
+//
+// The model declares `counter` as a Concerto `Double`, so it is stored and exchanged as an `f64`.
+// It is only ever used as a whole number though, so a stored counter must be a finite, non-negative
+// integer, and it can only be incremented while the result is still exactly representable.
+//
+fn next_counter(counter: f64) -> Result<f64, ContractError> {
+    if !counter.is_finite() || counter < 0.0 || counter.fract() != 0.0 {
+        return Err(ContractError::InvalidState(format!(
+            "counter must be a non-negative whole number, found {}",
+            counter
+        )));
+    }
+    if counter >= MAX_SAFE_COUNTER {
+        return Err(ContractError::CounterOverflow(counter));
+    }
+
+    Ok(counter + 1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ids::IdGenerator;
+    use crate::lifecycle::ContractStatus;
+    use crate::test_support::{at, clause, state};
+    use serde_json::json;
+
+    #[test]
+    fn the_counter_counts_up_to_the_largest_exact_integer() {
+        assert_eq!(next_counter(0.0).unwrap(), 1.0);
+        assert_eq!(
+            next_counter(MAX_SAFE_COUNTER - 1.0).unwrap(),
+            MAX_SAFE_COUNTER
+        );
+        assert!(matches!(
+            next_counter(MAX_SAFE_COUNTER),
+            Err(ContractError::CounterOverflow(counter)) if counter == MAX_SAFE_COUNTER
+        ));
+        assert!(matches!(
+            next_counter(f64::MAX),
+            Err(ContractError::CounterOverflow(_))
+        ));
+    }
+
+    #[test]
+    fn a_counter_that_is_not_a_whole_number_is_invalid() {
+        for counter in [-1.0, 1.5, f64::NAN, f64::INFINITY] {
+            assert!(
+                matches!(next_counter(counter), Err(ContractError::InvalidState(_))),
+                "{}",
+                counter
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn a_request_that_would_overflow_the_counter_fails() {
+        let data = clause();
+        let mut state = state(ContractStatus::Active);
+        state.clause.counter = MAX_SAFE_COUNTER;
+        let now = at("2024-01-01T00:00:00Z");
+
+        let result = MyRequestHandler
+            .handle(
+                ClauseContext {
+                    data: &data,
+                    initial: &data,
+                    state,
+                    now,
+                    ids: IdGenerator::new(&data._identifier, &json!({}), &json!({}), now),
+                    caller: None,
+                    internal: false,
+                },
+                MyRequest {
+                    _class: MY_REQUEST_CLASS.to_string(),
+                    input: "Hi".to_string(),
+                    _timestamp: now,
+                },
+            )
+            .await;
+
+        assert!(matches!(result, Err(ContractError::CounterOverflow(_))));
+    }
+}
@@ -0,0 +1,232 @@
+use super::lifecycle::status_changed_event;
+use super::{ClauseContext, ClauseHandler, ClauseOutput};
+use crate::error::ContractError;
+use crate::hash::{contract_data_hash, HASH_VERSION};
+use crate::lifecycle::{transition, ContractStatus, Transition};
+use crate::redact::Personal;
+use crate::signature::verify_party;
+use crate::state::PartySignature;
+use async_trait::async_trait;
+use lib::utils::serialize_datetime;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+pub const SIGN_AGREEMENT_REQUEST_CLASS: &str = "org.accordproject.helloworldstate.SignAgreement";
+pub const SIGN_AGREEMENT_RESPONSE_CLASS: &str =
+    "org.accordproject.helloworldstate.SignAgreementResponse";
+pub const AGREEMENT_EXECUTED_EVENT_CLASS: &str =
+    "org.accordproject.helloworldstate.AgreementExecutedEvent";
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct SignAgreement {
+    #[serde(rename = "$class")]
+    pub _class: String,
+
+    // The `$identifier` of the signing party.
+    pub party: String,
+
+    // The party's signature over the hex-encoded data hash.
+    pub signature: String,
+}
+
+impl Personal for SignAgreement {
+    const PERSONAL_FIELDS: &'static [&'static str] = &["party"];
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct SignAgreementResponse {
+    #[serde(rename = "$class")]
+    pub _class: String,
+
+    pub party: String,
+
+    #[serde(rename = "dataHash")]
+    pub data_hash: String,
+
+    #[serde(rename = "hashVersion")]
+    pub hash_version: u32,
+
+    // Whether every party has now signed.
+    pub executed: bool,
+}
+
+pub struct SignAgreementHandler;
+
+//
+// Records a party's signature over the hash of the `{data}`. Once every party has signed the same
+// data the agreement is executed, an `AgreementExecutedEvent` is emitted and the contract moves
+// from `draft` to `signed`. Signing again replaces the party's earlier signature. Only a draft
+// contract can be signed.
+//
+#[async_trait]
+impl ClauseHandler for SignAgreementHandler {
+    type Request = SignAgreement;
+    type Response = SignAgreementResponse;
+
+    fn request_class(&self) -> &'static str {
+        SIGN_AGREEMENT_REQUEST_CLASS
+    }
+
+    async fn handle(
+        &self,
+        context: ClauseContext<'_>,
+        request: SignAgreement,
+    ) -> Result<ClauseOutput<SignAgreementResponse>, ContractError> {
+        if let Some(caller) = context.caller {
+            if caller.subject != request.party {
+                return Err(ContractError::Forbidden {
+                    subject: caller.subject.clone(),
+                    request: format!("{} for {}", SIGN_AGREEMENT_REQUEST_CLASS, request.party),
+                });
+            }
+        }
+
+        if context.state.status != ContractStatus::Draft {
+            return Err(ContractError::InvalidTransition {
+                from: context.state.status,
+                transition: Transition::Sign,
+            });
+        }
+
+        let parties = context.data.parties.as_deref().unwrap_or_default();
+        if parties.is_empty() {
+            return Err(ContractError::InvalidRequest(
+                "the contract does not declare any parties".to_string(),
+            ));
+        }
+
+        let data = serde_json::to_value(context.data)
+            .map_err(|e| ContractError::InvalidRequest(e.to_string()))?;
+        let hash = contract_data_hash(&data, HASH_VERSION)?;
+        verify_party(
+            context.data,
+            &request.party,
+            hash.as_bytes(),
+            &request.signature,
+        )?;
+
+        let mut state = context.state;
+        state.signatures.retain(|s| s.party != request.party);
+        state.signatures.push(PartySignature {
+            party: request.party.clone(),
+            data_hash: hash.clone(),
+            hash_version: HASH_VERSION,
+            signature: request.signature,
+            signed_at: context.now,
+        });
+
+        // Signatures made with an earlier hash version still count, if they are over this data.
+        let mut executed = true;
+        for party in parties {
+            let mut signed = false;
+            for signature in state
+                .signatures
+                .iter()
+                .filter(|s| s.party == party._identifier)
+            {
+                signed |= contract_data_hash(&data, signature.hash_version)? == signature.data_hash;
+            }
+            executed &= signed;
+        }
+
+        let mut emit = vec![];
+        if executed && state.executed_at.is_none() {
+            let previous_status = state.status;
+            state.status = transition(previous_status, Transition::Sign, true)?;
+            state.executed_at = Some(context.now);
+            emit.push(status_changed_event(
+                previous_status,
+                state.status,
+                None,
+                &context.now,
+            )?);
+            emit.push(json!({
+                "$class": AGREEMENT_EXECUTED_EVENT_CLASS,
+                "dataHash": hash,
+                "parties": parties.iter().map(|p| &p._identifier).collect::<Vec<_>>(),
+                "$timestamp": serialize_datetime(&context.now, serde_json::value::Serializer)
+                    .map_err(|e| ContractError::InvalidResponse(e.to_string()))?,
+            }));
+        }
+
+        Ok(ClauseOutput {
+            response: SignAgreementResponse {
+                _class: SIGN_AGREEMENT_RESPONSE_CLASS.to_string(),
+                party: request.party,
+                data_hash: hash,
+                hash_version: HASH_VERSION,
+                executed,
+            },
+            state,
+            emit,
+            outbox: vec![],
+            erase: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ids::IdGenerator;
+    use crate::test_support::{at, clause, party, state};
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use ed25519_dalek::{Signer, SigningKey};
+    use lib::org_accordproject_helloworldstate::*;
+
+    fn signing_key(seed: u8) -> SigningKey {
+        SigningKey::from_bytes(&[seed; 32])
+    }
+
+    fn signatory(identifier: &str, seed: u8) -> Party {
+        let mut party = party(identifier, &["party"]);
+        party.public_key = Some(STANDARD.encode(signing_key(seed).verifying_key().as_bytes()));
+        party
+    }
+
+    fn sign(seed: u8, hash: &str) -> String {
+        STANDARD.encode(signing_key(seed).sign(hash.as_bytes()).to_bytes())
+    }
+
+    #[tokio::test]
+    async fn signatures_recorded_without_a_hash_version_still_count() {
+        let mut data = clause();
+        data.parties = Some(vec![signatory("auth0|fred", 1), signatory("auth0|jane", 2)]);
+        let hash = contract_data_hash(&serde_json::to_value(&data).unwrap(), 1).unwrap();
+
+        let mut state = state(ContractStatus::Draft);
+        state.signatures = vec![serde_json::from_value(json!({
+            "party": "auth0|fred",
+            "dataHash": hash,
+            "signature": sign(1, &hash),
+            "signedAt": "2024-01-01T00:00:00.000Z"
+        }))
+        .unwrap()];
+        let now = at("2024-01-02T00:00:00Z");
+
+        let output = SignAgreementHandler
+            .handle(
+                ClauseContext {
+                    data: &data,
+                    initial: &data,
+                    state,
+                    now,
+                    ids: IdGenerator::new(&data._identifier, &json!({}), &json!({}), now),
+                    caller: None,
+                    internal: false,
+                },
+                SignAgreement {
+                    _class: SIGN_AGREEMENT_REQUEST_CLASS.to_string(),
+                    party: "auth0|jane".to_string(),
+                    signature: sign(2, &hash),
+                },
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(output.state.signatures[0].hash_version, 1);
+        assert_eq!(output.response.hash_version, HASH_VERSION);
+        assert!(output.response.executed);
+        assert_eq!(output.state.status, ContractStatus::Signed);
+    }
+}
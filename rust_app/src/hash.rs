@@ -0,0 +1,68 @@
+//
+// Hashing
+//
+// Hashes identify the exact contract `{data}` an agreement was generated from. They are computed
+// over the canonical JSON of the data: object keys sorted, no insignificant whitespace, so the
+// same data always has the same hash regardless of how it was serialized.
+//
+// The hash of the contract data that parties sign is versioned, and every signature records the
+// version it was made with, so a signature keeps verifying if the way the hash is computed changes.
+//
+
+use crate::error::ContractError;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+pub fn canonical_json(value: &Value) -> String {
+    match value {
+        Value::Object(map) => {
+            let mut entries = map.iter().collect::<Vec<_>>();
+            entries.sort_by_key(|(key, _)| *key);
+
+            let fields = entries
+                .into_iter()
+                .map(|(key, value)| {
+                    format!("{}:{}", Value::String(key.clone()), canonical_json(value))
+                })
+                .collect::<Vec<_>>();
+            format!("{{{}}}", fields.join(","))
+        }
+        Value::Array(values) => {
+            let values = values.iter().map(canonical_json).collect::<Vec<_>>();
+            format!("[{}]", values.join(","))
+        }
+        other => other.to_string(),
+    }
+}
+
+//
+// Function data_hash
+//
+// The hex-encoded SHA-256 hash of the canonical JSON of `value`.
+//
+pub fn data_hash(value: &Value) -> String {
+    sha256_hex(canonical_json(value).as_bytes())
+}
+
+// The version of `contract_data_hash` new signatures and documents use.
+pub const HASH_VERSION: u32 = 1;
+
+//
+// Function contract_data_hash
+//
+// The hash of a `HelloWorldClause` as computed by hash version `version`. Version 1 is the
+// `data_hash` of the data.
+//
+pub fn contract_data_hash(data: &Value, version: u32) -> Result<String, ContractError> {
+    match version {
+        1 => Ok(data_hash(data)),
+        version => Err(ContractError::InvalidState(format!(
+            "unknown data hash version {}",
+            version
+        ))),
+    }
+}
+
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    hex::encode(Sha256::digest(bytes))
+}
@@ -0,0 +1,169 @@
+//
+// Idempotency Keys
+//
+// A client that retries a request after a timeout cannot tell whether the first attempt was
+// executed. Requests can therefore carry an idempotency key, sent as the `Idempotency-Key` header.
+// The response to the first request with a key is stored with the `{state}` it produced, and
+// returned again for any request with the same key, without executing it, for as long as
+// `IDEMPOTENCY_WINDOW_SECONDS`. Reusing a key for a different request is an error.
+//
+
+use crate::error::ContractError;
+use crate::hash::data_hash;
+use crate::redact::Personal;
+use chrono::{DateTime, Duration, Utc};
+use lib::utils::{deserialize_datetime, serialize_datetime};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::env;
+
+const DEFAULT_WINDOW_SECONDS: i64 = 24 * 60 * 60;
+const MAX_KEY_LENGTH: usize = 255;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IdempotencyRecord {
+    #[serde(rename = "idempotencyKey")]
+    pub key: String,
+
+    // The hash of the request, to recognise a key reused for a different request.
+    #[serde(rename = "payloadHash")]
+    pub payload_hash: String,
+
+    #[serde(rename = "response")]
+    pub response: Value,
+
+    #[serde(
+        rename = "createdAt",
+        serialize_with = "serialize_datetime",
+        deserialize_with = "deserialize_datetime"
+    )]
+    pub created_at: DateTime<Utc>,
+
+    // When the record stops being replayed, in seconds since the epoch. This is also the table's
+    // time to live attribute, so DynamoDB deletes expired records.
+    #[serde(rename = "expiresAt")]
+    pub expires_at: i64,
+}
+
+// A response can hold any of the contract's personal data, so all of it is personal.
+impl Personal for IdempotencyRecord {
+    const PERSONAL_FIELDS: &'static [&'static str] = &["response"];
+}
+
+impl IdempotencyRecord {
+    pub fn new(key: &str, request: &Value, response: &Value, now: DateTime<Utc>) -> Self {
+        Self {
+            key: key.to_string(),
+            payload_hash: data_hash(request),
+            response: response.clone(),
+            created_at: now,
+            expires_at: (now + idempotency_window()).timestamp(),
+        }
+    }
+
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        now.timestamp() >= self.expires_at
+    }
+
+    //
+    // Function replay
+    //
+    // The stored response, provided `request` is the request it was stored for.
+    //
+    pub fn replay(&self, request: &Value) -> Result<Value, ContractError> {
+        if data_hash(request) != self.payload_hash {
+            return Err(ContractError::IdempotencyConflict(self.key.clone()));
+        }
+
+        tracing::info!(idempotency_key = %self.key, "replaying the stored response");
+        Ok(self.response.clone())
+    }
+}
+
+//
+// Function validate_key
+//
+// Keys are between 1 and 255 printable ASCII characters.
+//
+pub fn validate_key(key: &str) -> Result<(), ContractError> {
+    if key.is_empty() || key.len() > MAX_KEY_LENGTH || !key.chars().all(|c| c.is_ascii_graphic()) {
+        return Err(ContractError::InvalidRequest(format!(
+            "idempotency key must be 1 to {} printable ASCII characters",
+            MAX_KEY_LENGTH
+        )));
+    }
+
+    Ok(())
+}
+
+//
+// Function idempotency_window
+//
+// How long responses are replayed for, from `IDEMPOTENCY_WINDOW_SECONDS`. Defaults to a day.
+//
+pub fn idempotency_window() -> Duration {
+    let seconds = env::var("IDEMPOTENCY_WINDOW_SECONDS")
+        .map(|value| {
+            value
+                .parse::<i64>()
+                .expect("IDEMPOTENCY_WINDOW_SECONDS must be a whole number of seconds")
+        })
+        .unwrap_or(DEFAULT_WINDOW_SECONDS);
+
+    Duration::seconds(seconds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::at;
+    use serde_json::json;
+
+    fn record() -> IdempotencyRecord {
+        IdempotencyRecord::new(
+            "retry-1",
+            &json!({ "$class": "org.accordproject.helloworldstate.MyRequest", "input": "Hi" }),
+            &json!({ "output": "Hello Fred Bloggs Hi" }),
+            at("2024-01-01T00:00:00Z"),
+        )
+    }
+
+    #[test]
+    fn records_expire_after_the_window() {
+        let record = record();
+
+        assert_eq!(record.expires_at, at("2024-01-02T00:00:00Z").timestamp());
+        assert!(!record.is_expired(at("2024-01-01T23:59:59Z")));
+        assert!(record.is_expired(at("2024-01-02T00:00:00Z")));
+    }
+
+    #[test]
+    fn only_the_same_request_is_replayed() {
+        let record = record();
+
+        let request =
+            json!({ "input": "Hi", "$class": "org.accordproject.helloworldstate.MyRequest" });
+        assert_eq!(
+            record.replay(&request).unwrap(),
+            json!({ "output": "Hello Fred Bloggs Hi" })
+        );
+
+        let other =
+            json!({ "$class": "org.accordproject.helloworldstate.MyRequest", "input": "Bye" });
+        assert!(matches!(
+            record.replay(&other),
+            Err(ContractError::IdempotencyConflict(key)) if key == "retry-1"
+        ));
+    }
+
+    #[test]
+    fn keys_are_short_printable_ascii() {
+        assert!(validate_key("retry-1").is_ok());
+        assert!(validate_key(&"k".repeat(MAX_KEY_LENGTH)).is_ok());
+
+        assert!(validate_key("").is_err());
+        assert!(validate_key(&"k".repeat(MAX_KEY_LENGTH + 1)).is_err());
+        assert!(validate_key("retry 1").is_err());
+        assert!(validate_key("réessai").is_err());
+    }
+}
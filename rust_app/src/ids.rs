@@ -0,0 +1,98 @@
+//
+// Identifiers
+//
+// The ids the contract gives to what it creates, such as agreement jobs and amendments, come from
+// an `IdGenerator`, as timestamps come from the `Clock`. The ids are derived from the request and
+// what it was executed against, so executing the same request against the same `{state}` at the
+// same logical time always produces the same ids, and a replay or a stateless execution gives the
+// same result as the original.
+//
+// Ids are version 8 UUIDs holding a SHA-256 hash of the contract id, the `{state}`, the request,
+// the execution time and how many ids were generated before.
+//
+
+use crate::hash::canonical_json;
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::sync::atomic::{AtomicU32, Ordering};
+use uuid::Builder;
+
+pub struct IdGenerator {
+    seed: [u8; 32],
+    issued: AtomicU32,
+}
+
+impl IdGenerator {
+    pub fn new(contract_id: &str, state: &Value, request: &Value, now: DateTime<Utc>) -> Self {
+        let mut hash = Sha256::new();
+        for input in [
+            contract_id.to_string(),
+            canonical_json(state),
+            canonical_json(request),
+            now.to_rfc3339_opts(SecondsFormat::Nanos, true),
+        ] {
+            hash.update((input.len() as u64).to_be_bytes());
+            hash.update(input.as_bytes());
+        }
+
+        Self {
+            seed: hash.finalize().into(),
+            issued: AtomicU32::new(0),
+        }
+    }
+
+    pub fn next_id(&self) -> String {
+        let count = self.issued.fetch_add(1, Ordering::Relaxed);
+        let hash = Sha256::new()
+            .chain_update(self.seed)
+            .chain_update(count.to_be_bytes())
+            .finalize();
+
+        let mut bytes = [0; 16];
+        bytes.copy_from_slice(&hash[..16]);
+        Builder::from_custom_bytes(bytes).into_uuid().to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{at, CONTRACT_ID};
+    use serde_json::json;
+
+    fn generator(request: Value) -> IdGenerator {
+        IdGenerator::new(
+            CONTRACT_ID,
+            &json!({ "counter": 1 }),
+            &request,
+            at("2024-01-01T00:00:00Z"),
+        )
+    }
+
+    #[test]
+    fn the_same_inputs_give_the_same_ids() {
+        let first = generator(json!({ "input": "Fred" }));
+        let second = generator(json!({ "input": "Fred" }));
+
+        assert_eq!(first.next_id(), second.next_id());
+        assert_eq!(first.next_id(), second.next_id());
+    }
+
+    #[test]
+    fn ids_differ_with_the_inputs_and_within_a_request() {
+        let first = generator(json!({ "input": "Fred" }));
+        let second = generator(json!({ "input": "Jane" }));
+
+        let id = first.next_id();
+        assert_ne!(id, second.next_id());
+        assert_ne!(id, first.next_id());
+    }
+
+    #[test]
+    fn ids_are_version_8_uuids() {
+        let id = generator(json!({})).next_id();
+
+        assert_eq!(uuid::Uuid::parse_str(&id).unwrap().get_version_num(), 8);
+    }
+}
@@ -9,4 +9,6 @@ pub mod org_accordproject_contract;
 #[allow(unused_imports)]
 pub mod org_accordproject_runtime;
 #[allow(unused_imports)]
+pub mod org_accordproject_ergo_options;
+#[allow(unused_imports)]
 pub mod utils;
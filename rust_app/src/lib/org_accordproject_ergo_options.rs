@@ -0,0 +1,24 @@
+use serde::{ Deserialize, Serialize };
+use chrono::{ DateTime, TimeZone, Utc };
+   
+use crate::concerto_1_0_0::*;
+use crate::utils::*;
+   
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Options {
+   #[serde(
+      rename = "$class",
+   )]
+   pub _class: String,
+   
+   #[serde(
+      rename = "wrapVariables",
+   )]
+   pub wrap_variables: bool,
+   
+   #[serde(
+      rename = "template",
+   )]
+   pub template: bool,
+}
+
@@ -64,6 +64,30 @@ pub struct HelloWorldState {
    pub _identifier: String,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Party {
+   #[serde(
+      rename = "$class",
+   )]
+   pub _class: String,
+   
+   #[serde(
+      rename = "roles",
+   )]
+   pub roles: Vec<String>,
+   
+   #[serde(
+      rename = "publicKey",
+      skip_serializing_if = "Option::is_none",
+   )]
+   pub public_key: Option<String>,
+   
+   #[serde(
+      rename = "$identifier",
+   )]
+   pub _identifier: String,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct HelloWorldClause {
    #[serde(
@@ -76,6 +100,12 @@ pub struct HelloWorldClause {
    )]
    pub name: String,
    
+   #[serde(
+      rename = "parties",
+      skip_serializing_if = "Option::is_none",
+   )]
+   pub parties: Option<Vec<Party>>,
+   
    #[serde(
       rename = "clauseId",
    )]
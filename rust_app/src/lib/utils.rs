@@ -1,4 +1,4 @@
-use chrono::{DateTime, TimeZone, Utc};
+use chrono::{DateTime, NaiveDateTime, Utc};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 pub fn serialize_datetime_option<S>(
@@ -31,7 +31,8 @@ where
     D: Deserializer<'de>,
 {
     let datetime_str = String::deserialize(deserializer)?;
-    Utc.datetime_from_str(&datetime_str, "%Y-%m-%dT%H:%M:%S%.3f%Z")
+    NaiveDateTime::parse_from_str(&datetime_str, "%Y-%m-%dT%H:%M:%S%.3f%Z")
+        .map(|datetime| datetime.and_utc())
         .map_err(serde::de::Error::custom)
 }
 
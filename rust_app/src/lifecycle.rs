@@ -0,0 +1,171 @@
+//
+// Contract Lifecycle
+//
+// A contract starts as a `draft` when it is constructed. It is `signed` once every party has signed
+// its `{data}`, and `active` once it is activated; only an active contract accepts `MyRequest`. An
+// active contract can be suspended and resumed, and any contract can be terminated, after which
+// nothing can change its status.
+//
+//     draft --sign--> signed --activate--> active <--suspend/resume--> suspended
+//       |                                    ^
+//       +--activate (no parties to sign)-----+
+//
+//     draft, signed, active, suspended --terminate--> terminated
+//
+
+use crate::error::ContractError;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ContractStatus {
+    Draft,
+    Signed,
+    Active,
+    Suspended,
+    Terminated,
+}
+
+impl ContractStatus {
+    // States stored before the lifecycle was introduced belong to contracts that were already in
+    // use, so they are active.
+    pub fn legacy() -> Self {
+        ContractStatus::Active
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transition {
+    Sign,
+    Activate,
+    Suspend,
+    Resume,
+    Terminate,
+}
+
+//
+// Function transition
+//
+// The status a contract with status `from` moves to on `transition`. `has_parties` is whether the
+// contract declares parties that have to sign it before it can be activated.
+//
+pub fn transition(
+    from: ContractStatus,
+    transition: Transition,
+    has_parties: bool,
+) -> Result<ContractStatus, ContractError> {
+    use ContractStatus::*;
+
+    let to = match (from, transition) {
+        (Draft, Transition::Sign) => Signed,
+        (Signed, Transition::Activate) => Active,
+        (Draft, Transition::Activate) if !has_parties => Active,
+        (Active, Transition::Suspend) => Suspended,
+        (Suspended, Transition::Resume) => Active,
+        (Draft | Signed | Active | Suspended, Transition::Terminate) => Terminated,
+        _ => return Err(ContractError::InvalidTransition { from, transition }),
+    };
+
+    Ok(to)
+}
+
+//
+// Function require_active
+//
+// Fails unless the contract is active.
+//
+pub fn require_active(status: ContractStatus) -> Result<(), ContractError> {
+    if status == ContractStatus::Active {
+        Ok(())
+    } else {
+        Err(ContractError::NotActive(status))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ContractStatus::*;
+
+    const STATUSES: [ContractStatus; 5] = [Draft, Signed, Active, Suspended, Terminated];
+    const TRANSITIONS: [Transition; 5] = [
+        Transition::Sign,
+        Transition::Activate,
+        Transition::Suspend,
+        Transition::Resume,
+        Transition::Terminate,
+    ];
+
+    // Every allowed transition, as `(from, transition, has_parties, to)`. Everything else is refused.
+    const ALLOWED: &[(ContractStatus, Transition, bool, ContractStatus)] = &[
+        (Draft, Transition::Sign, false, Signed),
+        (Draft, Transition::Sign, true, Signed),
+        (Draft, Transition::Activate, false, Active),
+        (Signed, Transition::Activate, false, Active),
+        (Signed, Transition::Activate, true, Active),
+        (Active, Transition::Suspend, false, Suspended),
+        (Active, Transition::Suspend, true, Suspended),
+        (Suspended, Transition::Resume, false, Active),
+        (Suspended, Transition::Resume, true, Active),
+        (Draft, Transition::Terminate, false, Terminated),
+        (Draft, Transition::Terminate, true, Terminated),
+        (Signed, Transition::Terminate, false, Terminated),
+        (Signed, Transition::Terminate, true, Terminated),
+        (Active, Transition::Terminate, false, Terminated),
+        (Active, Transition::Terminate, true, Terminated),
+        (Suspended, Transition::Terminate, false, Terminated),
+        (Suspended, Transition::Terminate, true, Terminated),
+    ];
+
+    #[test]
+    fn only_the_listed_transitions_are_allowed() {
+        for from in STATUSES {
+            for action in TRANSITIONS {
+                for has_parties in [false, true] {
+                    let expected = ALLOWED
+                        .iter()
+                        .find(|(f, t, p, _)| (*f, *t, *p) == (from, action, has_parties))
+                        .map(|(_, _, _, to)| *to);
+
+                    match (transition(from, action, has_parties), expected) {
+                        (Ok(to), Some(expected)) => assert_eq!(to, expected),
+                        (Err(ContractError::InvalidTransition { .. }), None) => {}
+                        (result, expected) => panic!(
+                            "{:?} {:?} (parties: {}) gave {:?}, expected {:?}",
+                            from, action, has_parties, result, expected
+                        ),
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn a_draft_with_parties_must_be_signed_before_it_is_activated() {
+        assert!(transition(Draft, Transition::Activate, true).is_err());
+        assert_eq!(
+            transition(Draft, Transition::Activate, false).unwrap(),
+            Active
+        );
+    }
+
+    #[test]
+    fn terminated_is_final() {
+        for action in TRANSITIONS {
+            for has_parties in [false, true] {
+                assert!(transition(Terminated, action, has_parties).is_err());
+            }
+        }
+    }
+
+    #[test]
+    fn only_active_contracts_take_requests() {
+        assert!(require_active(Active).is_ok());
+        for status in [Draft, Signed, Suspended, Terminated] {
+            assert!(matches!(
+                require_active(status),
+                Err(ContractError::NotActive(s)) if s == status
+            ));
+        }
+    }
+}
@@ -12,234 +12,1310 @@
  * limitations under the License.
  */
 
-use aws_sdk_dynamodb::types::AttributeValue;
-use chrono::Utc;
+use auth::{Authenticator, Caller};
+use chrono::{DateTime, Utc};
+use clock::{clock_from_env, use_request_timestamp, Clock};
+use handlers::agreement_callback::AGREEMENT_CALLBACK_REQUEST_CLASS;
+use handlers::{registry, request_class, ClauseContext, Registry};
 use lambda_runtime::{run, service_fn, Error, LambdaEvent};
 use lib::org_accordproject_helloworldstate::*;
-use reqwest::Client;
+use opentelemetry_sdk::trace::Tracer;
 use serde::{Deserialize, Serialize};
-use serde_json::json;
-use serde_json::{Map, Value};
-
+use serde_json::{json, Value};
+use std::collections::{HashMap, VecDeque};
 use std::env;
-use utils::{add_data_to_database, add_state_to_database, get_data, increment_counter};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::Instrument;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer};
+
+use encryption::FieldEncryption;
+use error::ContractError;
+use idempotency::{validate_key, IdempotencyRecord};
+use ids::IdGenerator;
+use lifecycle::ContractStatus;
+use metrics::MetricsEmitter;
+use outbox::{Dispatcher, OutboxMessage, OutboxStatus};
+use policy::Policy;
+use redact::{Personal, RedactingStdout};
+use signature::{verify_request, RequestSignature};
+use state::ContractState;
+use utils::{
+    add_data_to_database, add_idempotency_record, add_nonce_record, add_state_to_database,
+    load_data, load_idempotency_record, load_idempotency_records, load_outbox, load_pending_outbox,
+    load_state, mark_outbox_sent, record_outbox_failure, replace_idempotency_response,
+    replace_outbox_payload, save_state,
+};
 
+mod amendment;
+mod attribute_value;
+mod auth;
+mod clock;
+mod delivery;
+mod encryption;
+mod erasure;
+mod error;
+mod generation;
+mod handlers;
+mod hash;
+mod idempotency;
+mod ids;
+mod lifecycle;
+mod metrics;
+mod outbox;
+mod pdf;
+mod policy;
+mod recipient;
+mod redact;
+mod render;
+mod signature;
+mod state;
+mod telemetry;
+#[cfg(test)]
+mod test_support;
 mod utils;
 
+const MODEL_NAMESPACE: &str = "org.accordproject.helloworldstate";
+const HELLO_WORLD_CLAUSE_CLASS: &str = "org.accordproject.helloworldstate.HelloWorldClause";
+const HELLO_WORLD_STATE_CLASS: &str = "org.accordproject.helloworldstate.HelloWorldState";
+
+// The request type of invocations whose request is not one this contract handles.
+const UNKNOWN_REQUEST_TYPE: &str = "Unknown";
+
+//
+// The request envelope. When `contract` is present the request is executed statelessly: the
+// caller supplies the `{data}` and `{state}` and receives the new `{state}` back, and nothing is
+// read from or written to DynamoDB.
+//
+// The scheduled invocation sets `dispatchOutbox` instead, to send the outbox messages that have
+// not been sent yet.
+//
+// `idempotencyKey` and `authorization` are taken from the `Idempotency-Key` and `Authorization`
+// headers by the API Gateway mapping template. `signature` is a party's signature over the
+// `request`, or over the `contract` when a stateless request has no `request`.
+//
 #[derive(Deserialize, Serialize, Debug)]
-pub struct GenerateAgreementAsPDFRequest {
-    notify_to: String,
+struct Request {
+    #[serde(default)]
+    request: Option<Value>,
+
+    #[serde(default)]
+    contract: Option<HelloWorldClause>,
+
+    #[serde(default)]
+    state: Option<ContractState>,
+
+    #[serde(rename = "dispatchOutbox", default)]
+    dispatch_outbox: bool,
+
+    #[serde(rename = "idempotencyKey", default)]
+    idempotency_key: Option<String>,
+
+    #[serde(default)]
+    authorization: Option<String>,
+
+    #[serde(default)]
+    signature: Option<RequestSignature>,
 }
 
-#[derive(Deserialize, Serialize, Debug)]
-pub struct GenerateAgreementAsPDFResponse {
-    message: String,
+struct App {
+    registry: Registry,
+    clock: Box<dyn Clock>,
+    dispatcher: Dispatcher,
+
+    // `None` when authentication is disabled.
+    authenticator: Option<Authenticator>,
+    policy: Policy,
+
+    // `None` when field encryption is disabled.
+    encryption: Option<FieldEncryption>,
+
+    // `None` when metrics are disabled.
+    metrics: Option<Arc<dyn MetricsEmitter>>,
 }
 
+//
+// The result of a stateless execution, in the shape returned by the Accord Project runtime.
+//
 #[derive(Deserialize, Serialize, Debug)]
-pub enum RequestType {
-    MyRequest(MyRequest),
-    HelloWorldClause(HelloWorldClause),
-    GenerateAgreementAsPDFRequest(GenerateAgreementAsPDFRequest), // Add other request types here
+struct TriggerResponse {
+    response: Value,
+
+    // The `{data}` with personal data erased, for the caller to keep instead of their own copy.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    contract: Option<HelloWorldClause>,
+
+    state: ContractState,
+    emit: Vec<Value>,
+
+    // The calls to other services the request placed in the outbox, for the caller to make.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    outbox: Vec<OutboxMessage>,
 }
 
-#[derive(Deserialize, Serialize, Debug)]
-pub enum ResponseType {
-    MyResponse(MyResponse),
-    HelloWorldClause(HelloWorldClause),
-    GenerateAgreementAsPDFResponse(GenerateAgreementAsPDFResponse), // Add other response types here
+//
+// Function initial_state
+//
+// The `{state}` of a newly constructed agreement.
+//
+fn initial_state(hello_world_clause: &HelloWorldClause) -> ContractState {
+    ContractState {
+        clause: HelloWorldState {
+            _class: HELLO_WORLD_STATE_CLASS.to_string(),
+            counter: 0.0,
+            _identifier: hello_world_clause._identifier.clone(),
+        },
+        status: ContractStatus::Draft,
+        agreements: vec![],
+        amendments: vec![],
+        data_versions: vec![],
+        signatures: vec![],
+        executed_at: None,
+        erasures: vec![],
+    }
 }
 
-#[derive(Deserialize, Serialize, Debug)]
-struct Request {
-    request: RequestType,
-}
-
-async fn generate_agreement_as_pdf(
-    request: GenerateAgreementAsPDFRequest,
-) -> Result<GenerateAgreementAsPDFResponse, Box<dyn std::error::Error>> {
-    //
-    // Get the `{data}` from DynamoDB
-    //
-    let result = get_data("data").await;
-    println!("result: {:?}", result);
-
-    //
-    // Generate the response depending on the result of the DynamoDB query
-    //
-    match result {
-        Ok(Some(item)) => {
-            let mut data_map = Map::new();
-            if let Some(AttributeValue::S(name)) = item.get("name") {
-                data_map.insert("name".to_string(), Value::String(name.to_string()));
-            }
+//
+// Constructor
+//
+// The constructor takes in the `{data}` payload and populates the DynamoDB database.
+// The constructor also initiates the `{state}` of the agreement.
+//
+async fn new(
+    app: &App,
+    hello_world_clause: HelloWorldClause,
+) -> Result<HelloWorldClause, ContractError> {
+    let encryption = app.encryption.as_ref();
+    add_data_to_database(&hello_world_clause, encryption).await?;
+    add_state_to_database(&initial_state(&hello_world_clause), encryption).await?;
 
-            if let Some(AttributeValue::S(class)) = item.get("_class") {
-                data_map.insert("$class".to_string(), Value::String(class.to_string()));
-            }
+    Ok(HelloWorldClause {
+        _class: hello_world_clause._class,
+        clause_id: hello_world_clause.clause_id,
+        _identifier: hello_world_clause._identifier,
+        name: hello_world_clause.name,
+        parties: hello_world_clause.parties,
+    })
+}
 
-            if let Some(AttributeValue::S(clause_id)) = item.get("clause_id") {
-                data_map.insert("clauseId".to_string(), Value::String(clause_id.to_string()));
-            }
+//
+// Function construct
+//
+// Runs the constructor, unless a request with the same idempotency key already did.
+//
+// The caller must be allowed to initialise the contract by the parties it declares or, when it is
+// already initialised, by the parties it currently declares. A contract can only be initialised
+// again while nothing has happened to it: its `{state}` would otherwise be replaced, losing its
+// signatures, amendments, agreements and erasures, and erased data could be brought back.
+//
+async fn construct(
+    app: &App,
+    request: Value,
+    idempotency_key: Option<&str>,
+    caller: Option<&Caller>,
+    signature: Option<&RequestSignature>,
+) -> Result<Value, ContractError> {
+    let hello_world_clause: HelloWorldClause = serde_json::from_value(request.clone())
+        .map_err(|e| ContractError::InvalidRequest(e.to_string()))?;
+    redact::register_personal(&hello_world_clause);
+    let existing = match load_data(app.encryption.as_ref()).await {
+        Ok(initial) => {
+            let (state, _) = load_state(app.encryption.as_ref()).await?;
+            Some((state.data_as_of(initial, app.clock.now())?, state))
+        }
+        Err(ContractError::NotInitialized) => None,
+        Err(e) => return Err(e),
+    };
+    authorize(
+        app,
+        existing
+            .as_ref()
+            .map_or(&hello_world_clause, |(data, _)| data),
+        caller,
+        &request,
+        signature,
+    )?;
 
-            if let Some(AttributeValue::S(identifier)) = item.get("_identifier") {
-                data_map.insert(
-                    "$identifier".to_string(),
-                    Value::String(identifier.to_string()),
-                );
-            }
+    let now = app.clock.now();
+    if let Some(response) = replay(app, idempotency_key, &request, now).await? {
+        return Ok(response);
+    }
+    if let Some(signature) = signature {
+        add_nonce_record(signature, now).await?;
+    }
+    match &existing {
+        Some((_, state)) if state.status == ContractStatus::Terminated => {
+            return Err(ContractError::Terminated)
+        }
+        Some((_, state)) if state.has_history() => return Err(ContractError::AlreadyInitialized),
+        _ => {}
+    }
 
-            let data = Value::Object(data_map);
-            println!("data: {:?}", data);
+    let clause = new(app, hello_world_clause).await?;
+    let response =
+        serde_json::to_value(clause).map_err(|e| ContractError::InvalidResponse(e.to_string()))?;
 
-            let template = env::var("TEMPLATE_NAME").expect("TEMPLATE_NAME must be set");
-            println!("template: {:?}", template);
+    if let Some(key) = idempotency_key {
+        add_idempotency_record(
+            &IdempotencyRecord::new(key, &request, &response, now),
+            app.encryption.as_ref(),
+        )
+        .await?;
+    }
 
-            let body = json!({
-                "data": data,
-                "notifyTo": request.notify_to.to_string(),
-                "template": template,
-                "options": json!({})
-            });
-            println!("body: {:?}", body);
+    Ok(response)
+}
+
+//
+// Function replay
+//
+// The stored response to an earlier request with the same idempotency key, if there is one that
+// has not expired.
+//
+async fn replay(
+    app: &App,
+    idempotency_key: Option<&str>,
+    request: &Value,
+    now: DateTime<Utc>,
+) -> Result<Option<Value>, ContractError> {
+    let Some(key) = idempotency_key else {
+        return Ok(None);
+    };
+
+    match load_idempotency_record(key, app.encryption.as_ref()).await? {
+        Some(record) if !record.is_expired(now) => Ok(Some(record.replay(request)?)),
+        _ => Ok(None),
+    }
+}
 
-            let request_url =
-                env::var("GENERATE_AGREEMENT_URL").expect("GENERATE_AGREEMENT_URL must be set");
-            println!("request_url: {:?}", request_url);
+//
+// Function process
+//
+// Executes the request. The messages it placed in the outbox are sent by the scheduled
+// `dispatchOutbox` invocation, so the response never waits for another service.
+//
+async fn process(
+    app: &App,
+    request: Value,
+    idempotency_key: Option<&str>,
+    caller: Option<&Caller>,
+    signature: Option<&RequestSignature>,
+) -> Result<Value, ContractError> {
+    let (response, _) = execute(app, request, idempotency_key, caller, signature, false).await?;
 
-            let response = Client::new().post(request_url).json(&body).send().await?;
-            println!("response: {:?}", response);
+    Ok(response)
+}
 
-            Ok(GenerateAgreementAsPDFResponse {
-                message: format!("Agreement has been sent to {}", request.notify_to),
-            })
+//
+// Function execute
+//
+// Loads the `{data}` in effect and the `{state}` from DynamoDB, dispatches the request to its clause handler and
+// saves the new `{state}`, together with the messages the handler placed in the outbox, if the
+// handler changed anything. Returns the response and the saved outbox messages.
+//
+// With an idempotency key, a repeated request returns the stored response instead, and the
+// response of a new request is stored together with its `{state}`. Either way the caller must be
+// allowed to send the request, and its signature must be valid. The nonce of the signature of a
+// new request is used up before it is executed. `internal` requests are made by
+// the contract itself, such as the outcome of an outbox message.
+//
+async fn execute(
+    app: &App,
+    request: Value,
+    idempotency_key: Option<&str>,
+    caller: Option<&Caller>,
+    signature: Option<&RequestSignature>,
+    internal: bool,
+) -> Result<(Value, Vec<OutboxMessage>), ContractError> {
+    let initial = load_data(app.encryption.as_ref()).await?;
+    let (state, version) = load_state(app.encryption.as_ref()).await?;
+    let at = execution_time(app.clock.as_ref(), &request);
+    let amended = state.amended_data_as_of(at)?;
+    let hello_world_clause = amended.as_ref().unwrap_or(&initial);
+    authorize(app, hello_world_clause, caller, &request, signature)?;
+
+    let now = app.clock.now();
+    if let Some(response) = replay(app, idempotency_key, &request, now).await? {
+        return Ok((response, vec![]));
+    }
+    if let Some(signature) = signature {
+        add_nonce_record(signature, now).await?;
+    }
+    let original_request = idempotency_key.map(|_| request.clone());
+
+    let previous_state =
+        serde_json::to_value(&state).map_err(|e| ContractError::InvalidState(e.to_string()))?;
+
+    let context = ClauseContext {
+        data: hello_world_clause,
+        initial: &initial,
+        state,
+        now: at,
+        ids: IdGenerator::new(
+            &hello_world_clause._identifier,
+            &previous_state,
+            &request,
+            at,
+        ),
+        caller,
+        internal,
+    };
+    let output = app.registry.dispatch(context, request).await?;
+
+    let new_state = serde_json::to_value(&output.state)
+        .map_err(|e| ContractError::InvalidState(e.to_string()))?;
+    if new_state != previous_state || !output.outbox.is_empty() {
+        // Sent messages are kept, so they are erased as well as pending ones. They, and the stored
+        // responses, are erased before the `{state}` recording the erasure is saved, so an
+        // interrupted erasure is completed by sending it again.
+        if let Some(erase) = &output.erase {
+            erase_outbox(app, &erase.tombstones).await?;
+            erase_responses(app, &erase.tombstones).await?;
+        }
+
+        let record = idempotency_key
+            .zip(original_request)
+            .map(|(key, request)| IdempotencyRecord::new(key, &request, &output.response, now));
+        save_state(
+            &output.state,
+            version,
+            &output.outbox,
+            record.as_ref(),
+            output.erase.as_ref().map(|erase| &erase.data),
+            app.encryption.as_ref(),
+        )
+        .await?;
+    }
+
+    for event in &output.emit {
+        tracing::info!(event = %event, "emitted an event");
+    }
+
+    Ok((output.response, output.outbox))
+}
+
+//
+// Function erase_outbox
+//
+// Replaces the erased values in every outbox message with their `tombstones`.
+//
+async fn erase_outbox(
+    app: &App,
+    tombstones: &HashMap<String, String>,
+) -> Result<(), ContractError> {
+    for message in load_outbox(app.encryption.as_ref()).await? {
+        let mut record = serde_json::to_value(&message)
+            .map_err(|e| ContractError::InvalidState(e.to_string()))?;
+        if erasure::erase(&mut record, &OutboxMessage::personal_fields(), tombstones) > 0 {
+            let message: OutboxMessage = serde_json::from_value(record)
+                .map_err(|e| ContractError::InvalidState(e.to_string()))?;
+            replace_outbox_payload(&message, app.encryption.as_ref()).await?;
         }
-        Ok(None) => Err(Box::new(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            "Contract is not initialized",
-        ))),
-        Err(error) => {
-            println!("Error: {:?}", error);
-            Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                format!("AWS SDK error: {:?}", error),
-            )))
+    }
+
+    Ok(())
+}
+
+//
+// Function erase_responses
+//
+// Replaces the erased values in the stored responses of idempotent requests with their
+// `tombstones`.
+//
+async fn erase_responses(
+    app: &App,
+    tombstones: &HashMap<String, String>,
+) -> Result<(), ContractError> {
+    for mut record in load_idempotency_records(app.encryption.as_ref()).await? {
+        if erasure::erase_text(&mut record.response, tombstones) > 0 {
+            replace_idempotency_response(&record, app.encryption.as_ref()).await?;
         }
     }
+
+    Ok(())
 }
 
 //
-// Clause Function
+// Function dispatch
 //
-// Function to handle the `MyRequest` clause
+// Sends outbox messages, oldest first. When a call has an outcome to record, the request recording
+// it is executed before the message is marked as sent, and any messages it places in the outbox are
+// sent after the ones already waiting. A message that cannot be sent, or whose outcome cannot be recorded, stays in the outbox
+// until it has failed `OUTBOX_MAX_ATTEMPTS` times. Returns the number of messages sent.
 //
-async fn handle_my_request(
-    my_request: MyRequest,
-) -> Result<MyResponse, Box<dyn std::error::Error>> {
-    //
-    // Increment the `{state}` counter held in DynamoDB.
-    //
-    let counter = match increment_counter().await {
-        Ok(counter) => counter,
-        Err(err) => {
-            println!("Failed to increment counter: {}", err);
-            return Err(err);
+// No message is started once the invocation is within `OUTBOX_DISPATCH_RESERVE_MS` of its
+// `deadline`, so a message is never cut off half way. The messages left are sent by the next
+// scheduled invocation.
+//
+async fn dispatch(app: &App, outbox: Vec<OutboxMessage>, deadline: SystemTime) -> usize {
+    let mut queue = VecDeque::from(outbox);
+    let mut sent = 0;
+
+    while let Some(mut message) = queue.pop_front() {
+        if SystemTime::now() + app.dispatcher.reserve() > deadline {
+            tracing::warn!(
+                remaining = queue.len() + 1,
+                "stopped dispatching before the invocation times out"
+            );
+            break;
         }
-    };
 
-    //
-    // Get the `{data}` from DynamoDB
-    //
-    let result = get_data("data").await;
-
-    //
-    // Generate the response depending on the result of the DynamoDB query
-    //
-    match result {
-        Ok(Some(item)) => {
-            // Extract the item from the response, if present
-            if let Some(AttributeValue::S(name)) = item.get("name") {
-                return Ok(MyResponse {
-                    _class: my_request._class,
-                    output: format!(
-                        "Hello {} - {} - counter: {}",
-                        name, my_request.input, counter
-                    ),
-                    _timestamp: Utc::now(),
-                });
+        let span = tracing::info_span!("outbox", dedup_key = %message.dedup_key);
+        let outbox = async {
+            let result = match app.dispatcher.send(&mut message).await {
+                Ok(Some(outcome)) => execute(app, outcome, None, None, None, true)
+                    .await
+                    .map(|(_, outbox)| outbox),
+                Ok(None) => Ok(vec![]),
+                Err(e) => Err(e),
+            };
+
+            match result {
+                Ok(outbox) => {
+                    if let Err(e) = mark_outbox_sent(&message.dedup_key, app.clock.now()).await {
+                        tracing::warn!(error = %e, "failed to mark the message as sent");
+                    }
+                    sent += 1;
+                    outbox
+                }
+                Err(e) => {
+                    tracing::warn!(error = %e, "failed to send the message");
+                    give_up_if_exhausted(app, message, &e.to_string()).await
+                }
             }
-            Err("Item not found or 'name' field missing".into())
         }
-        Ok(None) => Err("Item not found".into()),
-        Err(error) => {
-            println!("Error: {:?}", error);
-            Err(format!("AWS SDK error: {:?}", error).into())
+        .instrument(span)
+        .await;
+        queue.extend(outbox);
+    }
+
+    sent
+}
+
+//
+// Function give_up_if_exhausted
+//
+// Records a failed attempt to send `message`. When it was the last attempt, the failure is
+// recorded by the contract, and any messages that places in the outbox are returned.
+//
+async fn give_up_if_exhausted(
+    app: &App,
+    mut message: OutboxMessage,
+    error: &str,
+) -> Vec<OutboxMessage> {
+    let status = match record_outbox_failure(&message, error, app.dispatcher.max_attempts()).await {
+        Ok(status) => status,
+        Err(e) => {
+            tracing::error!(error = %e, "failed to record the failure to send the message");
+            return vec![];
         }
+    };
+    if status != OutboxStatus::Failed {
+        return vec![];
+    }
+
+    message.last_error = Some(error.to_string());
+    match app.dispatcher.give_up(&message) {
+        Some(failure) => match execute(app, failure, None, None, None, true).await {
+            Ok((_, outbox)) => outbox,
+            Err(e) => {
+                tracing::error!(error = %e, "failed to record giving up on the message");
+                vec![]
+            }
+        },
+        None => vec![],
     }
 }
 
 //
-// Constructor
+// Function dispatch_pending
 //
-// The constructor takes in the `{data}` payload and populates the DynamoDB database.
-// The constructor also initiates the `{state}` of the agreement.
+// Sends the outbox messages that have not been sent yet, until the invocation nears its
+// `deadline`. Run on a schedule.
 //
-async fn new(
-    hello_world_clause: HelloWorldClause,
-) -> Result<HelloWorldClause, Box<dyn std::error::Error>> {
-    add_data_to_database(&hello_world_clause)
-        .await
-        .map_err(|e| -> Box<dyn std::error::Error> { e.into() })?;
-    add_state_to_database(&hello_world_clause)
-        .await
-        .map_err(|e| -> Box<dyn std::error::Error> { e.into() })?;
+async fn dispatch_pending(app: &App, deadline: SystemTime) -> Result<Value, ContractError> {
+    let pending = load_pending_outbox(app.encryption.as_ref()).await?;
+    let count = pending.len();
+    let sent = dispatch(app, pending, deadline).await;
 
-    Ok(HelloWorldClause {
-        _class: hello_world_clause._class,
-        clause_id: hello_world_clause.clause_id,
-        _identifier: hello_world_clause._identifier,
-        name: hello_world_clause.name,
-    })
+    Ok(json!({ "pending": count, "sent": sent }))
 }
 
 //
-// Main Function Handler
+// Function authorize
 //
-// This is the function that handles all incoming requests and determines which clause function to call.
+// Checks that the caller may send the request to the contract with the `{data}` `data`, and that
+// the request is signed by a party when it has to be or a signature is supplied.
 //
-async fn function_handler(event: LambdaEvent<Request>) -> Result<ResponseType, Error> {
-    let response = match event.payload.request {
-        RequestType::MyRequest(my_request) => {
-            let my_response = handle_my_request(my_request)
-                .await
-                .map_err(|e| lambda_runtime::Error::from(format!("Error: {:?}", e)))?;
-            ResponseType::MyResponse(my_response)
+fn authorize(
+    app: &App,
+    data: &HelloWorldClause,
+    caller: Option<&Caller>,
+    request: &Value,
+    signature: Option<&RequestSignature>,
+) -> Result<(), ContractError> {
+    let Some(class) = request_class(request) else {
+        return Ok(());
+    };
+    app.policy.authorize(data, caller, class)?;
+
+    match signature {
+        Some(signature) => {
+            verify_request(data, caller, request, signature, app.clock.now())?;
+            tracing::info!(signer = %signature.signer, "verified the request signature");
+        }
+        None if app.policy.requires_signature(class) => {
+            return Err(ContractError::InvalidSignature(format!(
+                "{} must be signed by a party",
+                class
+            )));
         }
-        RequestType::HelloWorldClause(hello_world_clause) => {
-            let clause = new(hello_world_clause)
-                .await
-                .map_err(|e| lambda_runtime::Error::from(format!("Error: {:?}", e)))?;
-            ResponseType::HelloWorldClause(clause)
+        None => {}
+    }
+
+    Ok(())
+}
+
+//
+// Function execution_time
+//
+// The logical time at which a request is executed: the request's `$timestamp` when
+// `USE_REQUEST_TIMESTAMP` is enabled and the request has one, the clock otherwise.
+//
+fn execution_time(clock: &dyn Clock, request: &Value) -> DateTime<Utc> {
+    if use_request_timestamp() {
+        if let Some(timestamp) = request.get("$timestamp") {
+            if let Ok(timestamp) = lib::utils::deserialize_datetime(timestamp.clone()) {
+                return timestamp;
+            }
         }
+    }
+
+    clock.now()
+}
+
+//
+// Function trigger
+//
+// Stateless execution. Without a `request` this is the constructor and returns the initial
+// `{state}`; otherwise the request is dispatched to its clause handler with the supplied `{state}`
+// and the `{data}` in effect, which is the supplied `{data}` until the contract is amended. The
+// new `{state}` is returned to the caller instead of being saved.
+//
+// There is nowhere to keep an outbox, so the messages the request placed in it are returned to the
+// caller, who makes the calls and sends their outcomes back, such as an
+// `AgreementGenerationCallback`.
+//
+async fn trigger(
+    app: &App,
+    contract: HelloWorldClause,
+    request: Option<Value>,
+    state: Option<ContractState>,
+    caller: Option<&Caller>,
+    signature: Option<&RequestSignature>,
+) -> Result<TriggerResponse, ContractError> {
+    let request = match request {
+        Some(request) => request,
+        None => {
+            let signed = serde_json::to_value(&contract)
+                .map_err(|e| ContractError::InvalidRequest(e.to_string()))?;
+            authorize(app, &contract, caller, &signed, signature)?;
 
-        RequestType::GenerateAgreementAsPDFRequest(request) => {
-            let response = generate_agreement_as_pdf(request)
-                .await
-                .map_err(|e| lambda_runtime::Error::from(format!("Error: {:?}", e)))?;
-            ResponseType::GenerateAgreementAsPDFResponse(response)
+            return Ok(TriggerResponse {
+                response: Value::Null,
+                contract: None,
+                state: initial_state(&contract),
+                emit: vec![],
+                outbox: vec![],
+            });
         }
     };
+    let state = state.ok_or_else(|| {
+        ContractError::InvalidRequest("state is required with contract and request".to_string())
+    })?;
+
+    let at = execution_time(app.clock.as_ref(), &request);
+    let amended = state.amended_data_as_of(at)?;
+    let data = amended.as_ref().unwrap_or(&contract);
+    authorize(app, data, caller, &request, signature)?;
+
+    let ids = IdGenerator::new(
+        &data._identifier,
+        &serde_json::to_value(&state).map_err(|e| ContractError::InvalidState(e.to_string()))?,
+        &request,
+        at,
+    );
+    let context = ClauseContext {
+        data,
+        initial: &contract,
+        state,
+        now: at,
+        ids,
+        caller,
+        internal: false,
+    };
+    let output = app.registry.dispatch(context, request).await?;
+
+    Ok(TriggerResponse {
+        response: output.response,
+        contract: output.erase.map(|erase| erase.data),
+        state: output.state,
+        emit: output.emit,
+        outbox: output.outbox,
+    })
+}
+
+//
+// Function authenticate
+//
+// The caller identified by the bearer token of `request`, or `None` when authentication is
+// disabled. The scheduled `dispatchOutbox` invocation has no caller. The generation service may
+// send its callbacks without a bearer token, since they carry the callback token of their job
+// instead, which is checked by the handler.
+//
+async fn authenticate(
+    authenticator: Option<&Authenticator>,
+    request: &Request,
+) -> Result<Option<Caller>, ContractError> {
+    let Some(authenticator) = authenticator else {
+        return Ok(None);
+    };
+    if request.dispatch_outbox {
+        return Ok(None);
+    }
+
+    let authorization = request
+        .authorization
+        .as_deref()
+        .filter(|header| !header.is_empty());
+    let callback =
+        request.request.as_ref().and_then(request_class) == Some(AGREEMENT_CALLBACK_REQUEST_CLASS);
+    if authorization.is_none() && callback {
+        return Ok(None);
+    }
+
+    authenticator.authenticate(authorization).await.map(Some)
+}
+
+//
+// Function handle
+//
+// Handles a single request. Requests carrying the `$class` of the contract data go to the
+// constructor, everything else is dispatched to its clause handler.
+//
+async fn handle(app: &App, request: Request, deadline: SystemTime) -> Result<Value, Error> {
+    let caller = authenticate(app.authenticator.as_ref(), &request)
+        .await
+        .map_err(failure)?;
+    if let Some(caller) = &caller {
+        redact::register_personal(caller);
+    }
+
+    let Request {
+        request,
+        contract,
+        state,
+        dispatch_outbox,
+        idempotency_key,
+        signature,
+        ..
+    } = request;
+
+    // Only the scheduled invocation sets `dispatchOutbox`, the mapping template never does.
+    if dispatch_outbox {
+        return dispatch_pending(app, deadline).await.map_err(failure);
+    }
+
+    // The mapping template passes an empty key when the header is missing.
+    let idempotency_key = idempotency_key.filter(|key| !key.is_empty());
+    if let Some(key) = &idempotency_key {
+        validate_key(key).map_err(failure)?;
+    }
+
+    let response = match (contract, request) {
+        (Some(contract), request) => {
+            let result = trigger(
+                app,
+                contract,
+                request,
+                state,
+                caller.as_ref(),
+                signature.as_ref(),
+            )
+            .await
+            .map_err(failure)?;
+            serde_json::to_value(result)?
+        }
+        (None, Some(request)) if request_class(&request) == Some(HELLO_WORLD_CLAUSE_CLASS) => {
+            construct(
+                app,
+                request,
+                idempotency_key.as_deref(),
+                caller.as_ref(),
+                signature.as_ref(),
+            )
+            .await
+            .map_err(failure)?
+        }
+        (None, Some(request)) => process(
+            app,
+            request,
+            idempotency_key.as_deref(),
+            caller.as_ref(),
+            signature.as_ref(),
+        )
+        .await
+        .map_err(failure)?,
+        (None, None) => return Err("Error: request is required".into()),
+    };
 
     Ok(response)
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Error> {
-    tracing_subscriber::fmt()
-        .with_max_level(tracing::Level::INFO)
+//
+// Function failure
+//
+// The error returned for a failed request, without personal data.
+//
+fn failure(e: ContractError) -> Error {
+    metrics::record_error(&e);
+    Error::from(redact::mask(&format!("Error: {:?}", e)))
+}
+
+//
+// Function request_type
+//
+// The type of request of an invocation, for the logs and metrics. A `$class` no handler is
+// registered for is `Unknown`, so callers cannot add values to the `RequestType` dimension.
+//
+fn request_type(app: &App, payload: &Request) -> &'static str {
+    if payload.dispatch_outbox {
+        return "dispatchOutbox";
+    }
+
+    match (&payload.request, &payload.contract) {
+        (Some(request), _) => request_class(request)
+            .and_then(|class| app.registry.class(class))
+            .unwrap_or(UNKNOWN_REQUEST_TYPE),
+        (None, Some(_)) => HELLO_WORLD_CLAUSE_CLASS,
+        (None, None) => UNKNOWN_REQUEST_TYPE,
+    }
+}
+
+//
+// Function unwrap_legacy_request
+//
+// Requests used to be wrapped in an object named after their type, as in
+// `{ "MyRequest": { ... } }`, and `$class` could be left out. Such a request is unwrapped, taking
+// its `$class` from the name when it has none. Returns whether it was wrapped, so the response can
+// be wrapped in the same way. Stateless requests never were.
+//
+fn unwrap_legacy_request(payload: &mut Request) -> bool {
+    if payload.contract.is_some() {
+        return false;
+    }
+    let Some(Value::Object(request)) = &mut payload.request else {
+        return false;
+    };
+    if request.len() != 1 || request.contains_key("$class") {
+        return false;
+    }
+    let Some((name, Value::Object(inner))) = request.iter_mut().next() else {
+        return false;
+    };
+
+    inner
+        .entry("$class")
+        .or_insert_with(|| json!(format!("{}.{}", MODEL_NAMESPACE, name)));
+    let inner = Value::Object(std::mem::take(inner));
+    payload.request = Some(inner);
+    true
+}
+
+//
+// Function wrap_legacy_response
+//
+// The response to a wrapped request, wrapped in an object named after its type.
+//
+fn wrap_legacy_response(response: Value) -> Value {
+    let name = response
+        .get("$class")
+        .and_then(Value::as_str)
+        .and_then(|class| class.rsplit('.').next())
+        .map(str::to_string);
+
+    match name {
+        Some(name) => json!({ name: response }),
+        None => response,
+    }
+}
+
+//
+// Main Function Handler
+//
+// This is the function that handles all incoming requests. Everything logged while handling a
+// request is in a span carrying the Lambda request id, the contract id and the request type.
+//
+async fn function_handler(app: &App, event: LambdaEvent<Request>) -> Result<Value, Error> {
+    let LambdaEvent {
+        mut payload,
+        context,
+    } = event;
+    let deadline = UNIX_EPOCH + Duration::from_millis(context.deadline);
+    let legacy = unwrap_legacy_request(&mut payload);
+
+    let contract_id = match &payload.contract {
+        Some(contract) => contract._identifier.clone(),
+        None => env::var("TABLE_NAME").unwrap_or_default(),
+    };
+    let request_type = request_type(app, &payload);
+    let span = tracing::info_span!(
+        "invocation",
+        request_id = %context.request_id,
+        contract_id = %contract_id,
+        request_type = %request_type,
+        otel.status_code = tracing::field::Empty,
+    );
+
+    let result = metrics::scope(app.metrics.clone(), request_type, async {
+        redact::clear();
+        let result = handle(app, payload, deadline)
+            .await
+            .map(|response| match legacy {
+                true => wrap_legacy_response(response),
+                false => response,
+            });
+        match &result {
+            Ok(_) => tracing::info!("request completed"),
+            Err(e) => {
+                tracing::error!(error = %e, "request failed");
+                telemetry::record_error(&tracing::Span::current());
+            }
+        }
+        result
+    })
+    .instrument(span)
+    .await;
+
+    // After the invocation span has ended, so it is exported with the others.
+    telemetry::flush().await;
+    result
+}
+
+//
+// Function init_logging
+//
+// Logs are written as JSON, one object per line, for CloudWatch Logs Insights to query, or as text
+// when `LOG_FORMAT` is `text`. `LOG_LEVEL` takes a level (`debug`) or filter directives
+// (`info,aws_smithy_http=warn`), and defaults to `info`. Personal data is masked, see `redact`.
+// Spans are also exported through `tracer`, when there is one, see `telemetry`.
+//
+fn init_logging(tracer: Option<Tracer>) {
+    let filter = EnvFilter::try_from_env("LOG_LEVEL").unwrap_or_else(|_| EnvFilter::new("info"));
+    let logs = tracing_subscriber::fmt::layer()
         .with_target(false)
         .without_time()
+        .with_writer(RedactingStdout);
+
+    let logs = if env::var("LOG_FORMAT").as_deref() == Ok("text") {
+        logs.boxed()
+    } else {
+        logs.json()
+            .flatten_event(true)
+            .with_current_span(true)
+            .with_span_list(false)
+            .boxed()
+    };
+
+    tracing_subscriber::registry()
+        .with(logs.with_filter(filter))
+        .with(tracer.map(telemetry::layer))
         .init();
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    init_logging(telemetry::from_env());
+
+    let app = App {
+        registry: registry(),
+        clock: clock_from_env(),
+        dispatcher: Dispatcher::from_env(),
+        authenticator: Authenticator::from_env(),
+        policy: Policy::from_env(),
+        encryption: FieldEncryption::from_env().await,
+        metrics: metrics::from_env(),
+    };
+    run(service_fn(|event| function_handler(&app, event))).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::FixedClock;
+    use crate::delivery::Courier;
+    use crate::generation::{GenerationBackend, GenerationRequest, GenerationResult};
+    use crate::handlers::erase_personal_data::ERASE_PERSONAL_DATA_REQUEST_CLASS;
+    use crate::handlers::generate_agreement::{
+        GENERATE_AGREEMENT_REQUEST_CLASS, GENERATE_AGREEMENT_RESPONSE_CLASS,
+    };
+    use crate::handlers::my_request::MY_REQUEST_CLASS;
+    use crate::metrics::{MemoryEmitter, Metric};
+    use crate::state::AgreementStatus;
+    use crate::test_support::{at, clause, registry, state};
+    use async_trait::async_trait;
+    use jsonwebtoken::jwk::JwkSet;
+
+    struct Unreachable;
+
+    #[async_trait]
+    impl GenerationBackend for Unreachable {
+        async fn generate(
+            &self,
+            _request: &GenerationRequest,
+        ) -> Result<GenerationResult, ContractError> {
+            panic!("the generation service must not be called")
+        }
+    }
+
+    fn app() -> App {
+        App {
+            registry: registry(),
+            clock: Box::new(FixedClock::new(
+                at("2024-01-01T00:00:00Z"),
+                chrono::Duration::zero(),
+            )),
+            dispatcher: Dispatcher::new(
+                Box::new(Unreachable),
+                Courier::new(vec![]),
+                10,
+                Duration::from_secs(10),
+            ),
+            authenticator: None,
+            policy: Policy::default(),
+            encryption: None,
+            metrics: None,
+        }
+    }
+
+    fn authenticator() -> Authenticator {
+        Authenticator::new(
+            JwkSet { keys: vec![] },
+            None,
+            Duration::ZERO,
+            "https://issuer.example.com/".to_string(),
+            "contract-api".to_string(),
+            0,
+        )
+    }
+
+    fn request(envelope: Value) -> Request {
+        serde_json::from_value(envelope).unwrap()
+    }
+
+    #[tokio::test]
+    async fn the_scheduled_dispatch_needs_no_token() {
+        let dispatch = request(json!({ "dispatchOutbox": true }));
+
+        let caller = authenticate(Some(&authenticator()), &dispatch).await;
+        assert!(matches!(caller, Ok(None)));
+    }
+
+    #[tokio::test]
+    async fn callbacks_need_no_bearer_token() {
+        let callback = request(json!({
+            "request": {
+                "$class": AGREEMENT_CALLBACK_REQUEST_CLASS,
+                "jobId": "job-1",
+                "status": "completed",
+                "callbackToken": "00"
+            },
+            "authorization": ""
+        }));
+
+        let caller = authenticate(Some(&authenticator()), &callback).await;
+        assert!(matches!(caller, Ok(None)));
+    }
+
+    #[tokio::test]
+    async fn other_requests_need_a_bearer_token() {
+        let my_request = request(json!({
+            "request": { "$class": "org.accordproject.helloworldstate.MyRequest", "input": "Fred" },
+            "authorization": ""
+        }));
+
+        let caller = authenticate(Some(&authenticator()), &my_request).await;
+        assert!(matches!(caller, Err(ContractError::Unauthenticated(_))));
+    }
 
-    run(service_fn(function_handler)).await
+    #[tokio::test]
+    async fn a_bearer_token_sent_with_a_callback_is_checked() {
+        let callback = request(json!({
+            "request": { "$class": AGREEMENT_CALLBACK_REQUEST_CLASS, "jobId": "job-1" },
+            "authorization": "Bearer not-a-token"
+        }));
+
+        let caller = authenticate(Some(&authenticator()), &callback).await;
+        assert!(matches!(caller, Err(ContractError::Unauthenticated(_))));
+    }
+
+    #[tokio::test]
+    async fn stateless_requests_return_their_outbox_unsent() {
+        let request = json!({
+            "$class": GENERATE_AGREEMENT_REQUEST_CLASS,
+            "recipients": [{ "email": "fred@example.com", "role": "party" }]
+        });
+
+        let result = trigger(
+            &app(),
+            clause(),
+            Some(request),
+            Some(state(ContractStatus::Active)),
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(result.state.agreements[0].status, AgreementStatus::Queued);
+        assert_eq!(result.outbox.len(), 1);
+        assert_eq!(
+            result.outbox[0].dedup_key,
+            format!("generate:{}", result.state.agreements[0].job_id)
+        );
+    }
+
+    #[tokio::test]
+    async fn the_same_stateless_request_queues_the_same_job() {
+        let request = json!({
+            "$class": GENERATE_AGREEMENT_REQUEST_CLASS,
+            "recipients": [{ "email": "fred@example.com", "role": "party" }]
+        });
+
+        let app = app();
+        let mut job_ids = vec![];
+        for _ in 0..2 {
+            let result = trigger(
+                &app,
+                clause(),
+                Some(request.clone()),
+                Some(state(ContractStatus::Active)),
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+            job_ids.push(result.state.agreements[0].job_id.clone());
+        }
+
+        assert_eq!(job_ids[0], job_ids[1]);
+    }
+
+    #[tokio::test]
+    async fn stateless_erasures_return_the_erased_contract() {
+        let request = json!({
+            "$class": ERASE_PERSONAL_DATA_REQUEST_CLASS,
+            "party": "auth0|fred",
+            "identifiers": ["Fred Bloggs"]
+        });
+
+        let app = app();
+        let mut erasure_ids = vec![];
+        for _ in 0..2 {
+            let result = trigger(
+                &app,
+                clause(),
+                Some(request.clone()),
+                Some(state(ContractStatus::Active)),
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+
+            let contract = result.contract.unwrap();
+            assert!(contract.name.starts_with("$erased:"));
+            assert_eq!(result.response["erased"], 1);
+            assert_eq!(result.emit.len(), 1);
+            erasure_ids.push(result.state.erasures[0].erasure_id.clone());
+        }
+
+        assert_eq!(erasure_ids[0], erasure_ids[1]);
+    }
+
+    #[test]
+    fn unregistered_request_classes_are_reported_as_unknown() {
+        let app = app();
+        let request_type_of = |payload: Value| {
+            request_type(&app, &serde_json::from_value::<Request>(payload).unwrap())
+        };
+
+        assert_eq!(
+            request_type_of(json!({ "request": { "$class": GENERATE_AGREEMENT_REQUEST_CLASS } })),
+            GENERATE_AGREEMENT_REQUEST_CLASS
+        );
+        assert_eq!(
+            request_type_of(json!({ "request": { "$class": "attacker.chosen.Class" } })),
+            UNKNOWN_REQUEST_TYPE
+        );
+        assert_eq!(
+            request_type_of(json!({ "request": { "counter": 1 } })),
+            UNKNOWN_REQUEST_TYPE
+        );
+        assert_eq!(
+            request_type_of(json!({ "dispatchOutbox": true })),
+            "dispatchOutbox"
+        );
+    }
+
+    #[test]
+    fn requests_wrapped_in_their_type_are_still_accepted() {
+        let mut payload: Request = serde_json::from_value(json!({
+            "request": {
+                "GenerateAgreementAsPDFRequest": { "notify_to": "fred@example.com" }
+            }
+        }))
+        .unwrap();
+
+        assert!(unwrap_legacy_request(&mut payload));
+        assert_eq!(
+            payload.request,
+            Some(json!({
+                "$class": GENERATE_AGREEMENT_REQUEST_CLASS,
+                "notify_to": "fred@example.com"
+            }))
+        );
+        assert_eq!(
+            wrap_legacy_response(json!({ "$class": GENERATE_AGREEMENT_RESPONSE_CLASS })),
+            json!({
+                "GenerateAgreementAsPDFResponse": { "$class": GENERATE_AGREEMENT_RESPONSE_CLASS }
+            })
+        );
+    }
+
+    #[test]
+    fn current_requests_are_left_as_they_are() {
+        let request = json!({ "$class": GENERATE_AGREEMENT_REQUEST_CLASS, "recipients": [] });
+        let mut payload: Request =
+            serde_json::from_value(json!({ "request": request.clone() })).unwrap();
+
+        assert!(!unwrap_legacy_request(&mut payload));
+        assert_eq!(payload.request, Some(request));
+    }
+
+    fn measured(emitter: &Arc<MemoryEmitter>) -> App {
+        App {
+            metrics: Some(emitter.clone()),
+            ..app()
+        }
+    }
+
+    fn invocation(payload: Value) -> LambdaEvent<Request> {
+        LambdaEvent::new(request(payload), lambda_runtime::Context::default())
+    }
+
+    fn values(metrics: &[Metric], name: &str) -> Vec<(f64, Vec<(&'static str, String)>)> {
+        metrics
+            .iter()
+            .filter(|metric| metric.name == name)
+            .map(|metric| (metric.value, metric.dimensions.clone()))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn invocations_and_the_counter_are_recorded() {
+        let emitter = Arc::new(MemoryEmitter::new());
+        let app = measured(&emitter);
+
+        function_handler(
+            &app,
+            invocation(json!({
+                "contract": clause(),
+                "state": state(ContractStatus::Active),
+                "request": {
+                    "$class": "org.accordproject.helloworldstate.MyRequest",
+                    "input": "Fred",
+                    "$timestamp": "2024-01-01T00:00:00.000Z"
+                }
+            })),
+        )
+        .await
+        .unwrap();
+
+        let metrics = emitter.take();
+        let request_type = ("RequestType", MY_REQUEST_CLASS.to_string());
+        assert_eq!(
+            values(&metrics, "Invocations"),
+            [(1.0, vec![request_type.clone()])]
+        );
+        assert_eq!(values(&metrics, "Counter"), [(1.0, vec![request_type])]);
+        assert!(values(&metrics, "Errors").is_empty());
+    }
+
+    #[tokio::test]
+    async fn errors_are_recorded_by_kind() {
+        let emitter = Arc::new(MemoryEmitter::new());
+        let app = measured(&emitter);
+
+        let result = function_handler(
+            &app,
+            invocation(json!({
+                "contract": clause(),
+                "state": state(ContractStatus::Terminated),
+                "request": {
+                    "$class": "org.accordproject.helloworldstate.MyRequest",
+                    "input": "Fred",
+                    "$timestamp": "2024-01-01T00:00:00.000Z"
+                }
+            })),
+        )
+        .await;
+        assert!(result.is_err());
+
+        let metrics = emitter.take();
+        assert_eq!(
+            values(&metrics, "Errors"),
+            [(
+                1.0,
+                vec![
+                    ("ErrorKind", "NotActive".to_string()),
+                    ("RequestType", MY_REQUEST_CLASS.to_string())
+                ]
+            )]
+        );
+        assert!(values(&metrics, "Counter").is_empty());
+    }
+
+    #[tokio::test]
+    async fn requests_of_unknown_types_are_recorded_as_unknown() {
+        let emitter = Arc::new(MemoryEmitter::new());
+        let app = measured(&emitter);
+
+        let result = function_handler(
+            &app,
+            invocation(json!({
+                "contract": clause(),
+                "state": state(ContractStatus::Active),
+                "request": { "$class": "org.example.Unregistered" }
+            })),
+        )
+        .await;
+        assert!(result.is_err());
+
+        let metrics = emitter.take();
+        let unknown = ("RequestType", UNKNOWN_REQUEST_TYPE.to_string());
+        assert_eq!(
+            values(&metrics, "Invocations"),
+            [(1.0, vec![unknown.clone()])]
+        );
+        assert_eq!(
+            values(&metrics, "Errors"),
+            [(
+                1.0,
+                vec![("ErrorKind", "UnknownRequest".to_string()), unknown]
+            )]
+        );
+    }
 }
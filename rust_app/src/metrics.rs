@@ -0,0 +1,395 @@
+//
+// Metrics
+//
+// The function records metrics through a `MetricsEmitter`:
+//
+// - `Invocations` and `Errors`, the latter by `ErrorKind`, the `ContractError` variant.
+// - `StoreLatency` and `StoreErrors` of every DynamoDB call, by `Operation`.
+// - `UpstreamLatency` and `UpstreamErrors`, by `ErrorKind`, of every call to the generation
+//   service.
+// - `Counter`, the value of the `{state}` counter after `MyRequest`.
+//
+// Every metric also has the `RequestType` of the invocation as a dimension, `Unknown` for requests
+// no handler is registered for. By default they are written to the logs in CloudWatch Embedded
+// Metric Format, one line for each set of dimensions at the end of every invocation, under the
+// namespace `METRICS_NAMESPACE`. Set `METRICS` to `off` to record no metrics.
+//
+// The emitter belongs to the `App`. Each invocation runs in a `scope` with it, and metrics recorded
+// outside a scope are discarded.
+//
+
+use crate::error::ContractError;
+use crate::telemetry;
+use chrono::Utc;
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use std::env;
+use std::future::Future;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tracing::Instrument;
+
+const DEFAULT_NAMESPACE: &str = "HelloWorldState";
+
+tokio::task_local! {
+    // The emitter and request type of the invocation being handled.
+    static INVOCATION: Invocation;
+}
+
+struct Invocation {
+    emitter: Arc<dyn MetricsEmitter>,
+    request_type: &'static str,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unit {
+    Count,
+    Milliseconds,
+    None,
+}
+
+impl Unit {
+    fn as_str(self) -> &'static str {
+        match self {
+            Unit::Count => "Count",
+            Unit::Milliseconds => "Milliseconds",
+            Unit::None => "None",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Metric {
+    pub name: &'static str,
+    pub unit: Unit,
+    pub value: f64,
+    pub dimensions: Vec<(&'static str, String)>,
+}
+
+pub trait MetricsEmitter: Send + Sync {
+    fn emit(&self, metric: Metric);
+
+    // Called at the end of every invocation.
+    fn flush(&self) {}
+}
+
+//
+// Keeps the metrics in memory, to be read back with `take`.
+//
+#[derive(Default)]
+pub struct MemoryEmitter {
+    metrics: Mutex<Vec<Metric>>,
+}
+
+impl MemoryEmitter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // The metrics emitted since the last call.
+    pub fn take(&self) -> Vec<Metric> {
+        std::mem::take(&mut *self.metrics.lock().unwrap())
+    }
+}
+
+impl MetricsEmitter for MemoryEmitter {
+    fn emit(&self, metric: Metric) {
+        self.metrics.lock().unwrap().push(metric);
+    }
+}
+
+//
+// Writes the metrics of an invocation to stdout in Embedded Metric Format when it ends, one
+// document for each set of dimensions.
+//
+pub struct EmfEmitter {
+    namespace: String,
+    buffer: MemoryEmitter,
+}
+
+impl EmfEmitter {
+    pub fn new(namespace: String) -> Self {
+        Self {
+            namespace,
+            buffer: MemoryEmitter::new(),
+        }
+    }
+
+    fn document(&self, dimensions: &[(&'static str, String)], metrics: &[Metric]) -> Value {
+        let mut document = Map::new();
+        let mut definitions = vec![];
+
+        for (name, value) in dimensions {
+            document.insert(name.to_string(), json!(value));
+        }
+        for metric in metrics {
+            let values = document
+                .entry(metric.name)
+                .or_insert_with(|| json!([]))
+                .as_array_mut()
+                .expect("metric values are an array");
+            if values.is_empty() {
+                definitions.push(json!({ "Name": metric.name, "Unit": metric.unit.as_str() }));
+            }
+            values.push(json!(metric.value));
+        }
+
+        document.insert(
+            "_aws".to_string(),
+            json!({
+                "Timestamp": Utc::now().timestamp_millis(),
+                "CloudWatchMetrics": [{
+                    "Namespace": self.namespace,
+                    "Dimensions": [dimensions.iter().map(|(name, _)| *name).collect::<Vec<_>>()],
+                    "Metrics": definitions,
+                }],
+            }),
+        );
+        Value::Object(document)
+    }
+}
+
+impl MetricsEmitter for EmfEmitter {
+    fn emit(&self, metric: Metric) {
+        self.buffer.emit(metric);
+    }
+
+    fn flush(&self) {
+        let mut groups: BTreeMap<Vec<(&'static str, String)>, Vec<Metric>> = BTreeMap::new();
+        for metric in self.buffer.take() {
+            let mut dimensions = metric.dimensions.clone();
+            dimensions.sort();
+            groups.entry(dimensions).or_default().push(metric);
+        }
+
+        let mut stdout = io::stdout().lock();
+        for (dimensions, metrics) in groups {
+            let _ = writeln!(stdout, "{}", self.document(&dimensions, &metrics));
+        }
+    }
+}
+
+//
+// Function from_env
+//
+// The emitter chosen by `METRICS`, or `None` when it is `off`.
+//
+pub fn from_env() -> Option<Arc<dyn MetricsEmitter>> {
+    match env::var("METRICS").as_deref() {
+        Ok("off") => None,
+        Ok("emf") | Err(_) => {
+            let namespace = env::var("METRICS_NAMESPACE")
+                .ok()
+                .filter(|namespace| !namespace.is_empty())
+                .unwrap_or_else(|| DEFAULT_NAMESPACE.to_string());
+            Some(Arc::new(EmfEmitter::new(namespace)))
+        }
+        Ok(other) => panic!("METRICS must be emf or off, not {}", other),
+    }
+}
+
+//
+// Function scope
+//
+// Runs `invocation`, an invocation of `request_type`, recording its metrics through `emitter`. The
+// invocation is counted, and its metrics are flushed when it ends. Without an emitter, its metrics
+// are discarded.
+//
+pub async fn scope<T>(
+    emitter: Option<Arc<dyn MetricsEmitter>>,
+    request_type: &'static str,
+    invocation: impl Future<Output = T>,
+) -> T {
+    let Some(emitter) = emitter else {
+        return invocation.await;
+    };
+
+    let scoped = Invocation {
+        emitter: emitter.clone(),
+        request_type,
+    };
+    let result = INVOCATION
+        .scope(scoped, async {
+            record("Invocations", Unit::Count, 1.0, vec![]);
+            invocation.await
+        })
+        .await;
+    emitter.flush();
+
+    result
+}
+
+//
+// Function record
+//
+// Emits a metric with `dimensions` and the `RequestType` of the invocation.
+//
+pub fn record(
+    name: &'static str,
+    unit: Unit,
+    value: f64,
+    mut dimensions: Vec<(&'static str, String)>,
+) {
+    let _ = INVOCATION.try_with(|invocation| {
+        dimensions.push(("RequestType", invocation.request_type.to_string()));
+        invocation.emitter.emit(Metric {
+            name,
+            unit,
+            value,
+            dimensions,
+        });
+    });
+}
+
+pub fn record_error(error: &ContractError) {
+    record(
+        "Errors",
+        Unit::Count,
+        1.0,
+        vec![("ErrorKind", error.kind().to_string())],
+    );
+}
+
+//
+// Function store_call
+//
+// Awaits the DynamoDB `operation` in its span, recording its latency and whether it failed.
+//
+pub async fn store_call<T, E>(
+    operation: &'static str,
+    call: impl Future<Output = Result<T, E>>,
+) -> Result<T, E> {
+    let span = telemetry::store_span(operation);
+    let start = Instant::now();
+    let result = call.instrument(span.clone()).await;
+    if result.is_err() {
+        telemetry::record_error(&span);
+    }
+
+    let dimensions = vec![("Operation", operation.to_string())];
+    record(
+        "StoreLatency",
+        Unit::Milliseconds,
+        elapsed_ms(start),
+        dimensions.clone(),
+    );
+    if result.is_err() {
+        record("StoreErrors", Unit::Count, 1.0, dimensions);
+    }
+
+    result
+}
+
+//
+// Function upstream_call
+//
+// Awaits a call to the generation service, recording its latency and the kind of error it failed
+// with.
+//
+pub async fn upstream_call<T>(
+    call: impl Future<Output = Result<T, ContractError>>,
+) -> Result<T, ContractError> {
+    let start = Instant::now();
+    let result = call.await;
+
+    record(
+        "UpstreamLatency",
+        Unit::Milliseconds,
+        elapsed_ms(start),
+        vec![],
+    );
+    if let Err(error) = &result {
+        record(
+            "UpstreamErrors",
+            Unit::Count,
+            1.0,
+            vec![("ErrorKind", error.kind().to_string())],
+        );
+    }
+
+    result
+}
+
+fn elapsed_ms(start: Instant) -> f64 {
+    start.elapsed().as_secs_f64() * 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metric(name: &'static str, unit: Unit, value: f64, request_type: &str) -> Metric {
+        Metric {
+            name,
+            unit,
+            value,
+            dimensions: vec![("RequestType", request_type.to_string())],
+        }
+    }
+
+    #[test]
+    fn memory_emitter_hands_each_metric_back_once() {
+        let emitter = MemoryEmitter::new();
+        emitter.emit(metric("Invocations", Unit::Count, 1.0, "MyRequest"));
+
+        assert_eq!(
+            emitter.take(),
+            vec![metric("Invocations", Unit::Count, 1.0, "MyRequest")]
+        );
+        assert!(emitter.take().is_empty());
+    }
+
+    #[test]
+    fn documents_follow_the_embedded_metric_format() {
+        let buffer = MemoryEmitter::new();
+        buffer.emit(metric("StoreLatency", Unit::Milliseconds, 12.5, "Unknown"));
+        buffer.emit(metric("StoreLatency", Unit::Milliseconds, 7.0, "Unknown"));
+        buffer.emit(metric("Invocations", Unit::Count, 1.0, "Unknown"));
+
+        let emitter = EmfEmitter::new("Contracts".to_string());
+        let document = emitter.document(&[("RequestType", "Unknown".to_string())], &buffer.take());
+
+        assert_eq!(document["RequestType"], "Unknown");
+        assert_eq!(document["StoreLatency"], json!([12.5, 7.0]));
+        assert_eq!(document["Invocations"], json!([1.0]));
+
+        let definition = &document["_aws"]["CloudWatchMetrics"][0];
+        assert_eq!(definition["Namespace"], "Contracts");
+        assert_eq!(definition["Dimensions"], json!([["RequestType"]]));
+        assert_eq!(
+            definition["Metrics"],
+            json!([
+                { "Name": "StoreLatency", "Unit": "Milliseconds" },
+                { "Name": "Invocations", "Unit": "Count" }
+            ])
+        );
+        assert!(document["_aws"]["Timestamp"].is_i64());
+    }
+
+    #[tokio::test]
+    async fn each_invocation_records_through_its_own_emitter() {
+        let first = Arc::new(MemoryEmitter::new());
+        let second = Arc::new(MemoryEmitter::new());
+
+        record("Counter", Unit::None, 1.0, vec![]);
+        tokio::join!(
+            scope(Some(first.clone()), "MyRequest", async {
+                record("Counter", Unit::None, 2.0, vec![]);
+            }),
+            scope(Some(second.clone()), "Unknown", async {}),
+        );
+
+        assert_eq!(
+            first.take(),
+            vec![
+                metric("Invocations", Unit::Count, 1.0, "MyRequest"),
+                metric("Counter", Unit::None, 2.0, "MyRequest")
+            ]
+        );
+        assert_eq!(
+            second.take(),
+            vec![metric("Invocations", Unit::Count, 1.0, "Unknown")]
+        );
+    }
+}
@@ -0,0 +1,342 @@
+//
+// Transactional Outbox
+//
+// Clause handlers never call other services themselves. Every outbound call, whether to the
+// generation service or to the recipients of an agreement, is recorded as an `OutboxMessage`
+// and stored in the same DynamoDB transaction as the `{state}` that caused it, so a call is only
+// ever made for a state change that was saved, and is never lost once it was.
+//
+// The `Dispatcher` makes the calls, from the scheduled `dispatchOutbox` invocation, which sends
+// every message that has not been sent yet, so requests never wait for another service. Delivery
+// is therefore at-least-once: every message carries a `dedupKey`, which is sent with the call so
+// the receiver can recognise repeats.
+//
+
+use crate::delivery::{Courier, Delivery};
+use crate::error::ContractError;
+use crate::generation::{
+    backend_from_env, duration_from_env, GenerationBackend, GenerationRequest,
+};
+use crate::handlers::agreement_callback::AGREEMENT_CALLBACK_REQUEST_CLASS;
+use crate::redact::Personal;
+use chrono::{DateTime, Utc};
+use lib::utils::{deserialize_datetime, serialize_datetime};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{env, time::Duration};
+
+const DEFAULT_MAX_ATTEMPTS: u32 = 10;
+const DEFAULT_RESERVE_MS: u64 = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutboxStatus {
+    Pending,
+    Sent,
+
+    // Given up on after `OUTBOX_MAX_ATTEMPTS` attempts.
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", content = "payload", rename_all = "camelCase")]
+pub enum Outbound {
+    GenerateAgreement(GenerationRequest),
+    Deliver(Delivery),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutboxMessage {
+    // Identifies the call, so it is only recorded once and its receiver can ignore repeats.
+    #[serde(rename = "dedupKey")]
+    pub dedup_key: String,
+
+    #[serde(rename = "status")]
+    pub status: OutboxStatus,
+
+    #[serde(rename = "attempts", default)]
+    pub attempts: u32,
+
+    #[serde(
+        rename = "createdAt",
+        serialize_with = "serialize_datetime",
+        deserialize_with = "deserialize_datetime"
+    )]
+    pub created_at: DateTime<Utc>,
+
+    #[serde(rename = "lastError", default, skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+
+    // The channels a delivery was already sent through, which are skipped when it is retried.
+    #[serde(
+        rename = "deliveredVia",
+        default,
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub delivered_via: Vec<String>,
+
+    #[serde(rename = "message")]
+    pub message: Outbound,
+}
+
+impl Personal for OutboxMessage {
+    const PERSONAL_FIELDS: &'static [&'static str] = &[
+        "message.payload.notifyTo",
+        "message.payload.recipients.email",
+        "message.payload.recipients.name",
+    ];
+    const DATA_COPIES: &'static [&'static str] = &["message.payload.data"];
+}
+
+impl OutboxMessage {
+    pub fn new(dedup_key: String, message: Outbound, now: DateTime<Utc>) -> Self {
+        Self {
+            dedup_key,
+            status: OutboxStatus::Pending,
+            attempts: 0,
+            created_at: now,
+            last_error: None,
+            delivered_via: vec![],
+            message,
+        }
+    }
+}
+
+pub struct Dispatcher {
+    backend: Box<dyn GenerationBackend>,
+    courier: Courier,
+    max_attempts: u32,
+
+    // How long before the end of the invocation to stop starting messages, enough for the slowest
+    // message with its retries.
+    reserve: Duration,
+}
+
+impl Dispatcher {
+    pub fn new(
+        backend: Box<dyn GenerationBackend>,
+        courier: Courier,
+        max_attempts: u32,
+        reserve: Duration,
+    ) -> Self {
+        Self {
+            backend,
+            courier,
+            max_attempts,
+            reserve,
+        }
+    }
+
+    //
+    // Function from_env
+    //
+    // Uses the generation backend named by `AGREEMENT_BACKEND` and the delivery channels named by
+    // `DELIVERY_CHANNELS`. Messages are given up on after `OUTBOX_MAX_ATTEMPTS` failed attempts, and
+    // none is started within `OUTBOX_DISPATCH_RESERVE_MS` of the end of the invocation.
+    //
+    pub fn from_env() -> Self {
+        let max_attempts = env::var("OUTBOX_MAX_ATTEMPTS")
+            .map(|value| {
+                value
+                    .parse::<u32>()
+                    .expect("OUTBOX_MAX_ATTEMPTS must be a whole number")
+            })
+            .unwrap_or(DEFAULT_MAX_ATTEMPTS);
+
+        Self::new(
+            backend_from_env(),
+            Courier::from_env(),
+            max_attempts,
+            duration_from_env("OUTBOX_DISPATCH_RESERVE_MS", DEFAULT_RESERVE_MS),
+        )
+    }
+
+    pub fn max_attempts(&self) -> u32 {
+        self.max_attempts
+    }
+
+    pub fn reserve(&self) -> Duration {
+        self.reserve
+    }
+
+    //
+    // Function send
+    //
+    // Makes the call recorded in `message`. When the call has an outcome the contract has to
+    // record, such as a generation job that completed straight away, the request recording it is
+    // returned, to be executed before the message is marked as sent. The channels a delivery is
+    // sent through are added to the message, also when another channel fails.
+    //
+    pub async fn send(&self, message: &mut OutboxMessage) -> Result<Option<Value>, ContractError> {
+        match &message.message {
+            Outbound::GenerateAgreement(request) => {
+                let result = self.backend.generate(request).await?;
+                tracing::info!(
+                    job_id = %request.job_id,
+                    status = result.status.as_deref().unwrap_or_default(),
+                    attempts = result.attempts,
+                    "sent the generation request"
+                );
+
+                match result.status.as_deref() {
+                    Some(status @ ("completed" | "failed")) => Ok(Some(json!({
+                        "$class": AGREEMENT_CALLBACK_REQUEST_CLASS,
+                        "jobId": request.job_id,
+                        "status": status,
+                        "documentUrl": result.document_url,
+                        "documentHash": result.document_hash,
+                        "error": (status == "failed").then_some(result.message).flatten(),
+                    }))),
+
+                    // The generation service reports the outcome with a callback.
+                    _ => Ok(None),
+                }
+            }
+            Outbound::Deliver(delivery) => {
+                self.courier
+                    .deliver(delivery, &mut message.delivered_via)
+                    .await?;
+                Ok(None)
+            }
+        }
+    }
+
+    //
+    // Function give_up
+    //
+    // The request recording that a message could not be sent, if the contract has to know.
+    //
+    pub fn give_up(&self, message: &OutboxMessage) -> Option<Value> {
+        match &message.message {
+            Outbound::GenerateAgreement(request) => Some(json!({
+                "$class": AGREEMENT_CALLBACK_REQUEST_CLASS,
+                "jobId": request.job_id,
+                "status": "failed",
+                "error": message.last_error,
+            })),
+            Outbound::Deliver(_) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generation::{GenerationResult, OutputFormat};
+    use crate::test_support::at;
+    use async_trait::async_trait;
+
+    // Answers every generation request with the same result.
+    struct Answering(Option<&'static str>);
+
+    #[async_trait]
+    impl GenerationBackend for Answering {
+        async fn generate(
+            &self,
+            request: &GenerationRequest,
+        ) -> Result<GenerationResult, ContractError> {
+            Ok(GenerationResult {
+                job_id: Some(request.job_id.clone()),
+                status: self.0.map(str::to_string),
+                document_url: Some("s3://agreements/job-1.pdf".to_string()),
+                document_hash: Some("abc123".to_string()),
+                message: Some("the template failed to render".to_string()),
+                attempts: 1,
+            })
+        }
+    }
+
+    fn dispatcher(status: Option<&'static str>) -> Dispatcher {
+        Dispatcher::new(
+            Box::new(Answering(status)),
+            Courier::new(vec![]),
+            3,
+            Duration::from_secs(1),
+        )
+    }
+
+    fn generation() -> Outbound {
+        Outbound::GenerateAgreement(GenerationRequest {
+            job_id: "job-1".to_string(),
+            callback_url: None,
+            callback_token: None,
+            data: json!({}),
+            notify_to: "fred@example.com".to_string(),
+            recipients: vec![],
+            template: "hello-world-state@0.15.0.cta".to_string(),
+            options: json!({}),
+            format: OutputFormat::Pdf,
+            locale: None,
+            watermark: None,
+        })
+    }
+
+    fn message(outbound: Outbound) -> OutboxMessage {
+        OutboxMessage::new(
+            "generate:job-1".to_string(),
+            outbound,
+            at("2024-01-01T00:00:00Z"),
+        )
+    }
+
+    #[tokio::test]
+    async fn a_completed_generation_is_recorded_by_the_contract() {
+        let outcome = dispatcher(Some("completed"))
+            .send(&mut message(generation()))
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(outcome["$class"], AGREEMENT_CALLBACK_REQUEST_CLASS);
+        assert_eq!(outcome["jobId"], "job-1");
+        assert_eq!(outcome["status"], "completed");
+        assert_eq!(outcome["documentUrl"], "s3://agreements/job-1.pdf");
+        assert_eq!(outcome["error"], Value::Null);
+    }
+
+    #[tokio::test]
+    async fn a_failed_generation_carries_its_error() {
+        let outcome = dispatcher(Some("failed"))
+            .send(&mut message(generation()))
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(outcome["status"], "failed");
+        assert_eq!(outcome["error"], "the template failed to render");
+    }
+
+    #[tokio::test]
+    async fn a_queued_generation_waits_for_the_callback() {
+        let outcome = dispatcher(Some("queued"))
+            .send(&mut message(generation()))
+            .await
+            .unwrap();
+
+        assert!(outcome.is_none());
+    }
+
+    #[test]
+    fn giving_up_on_a_generation_fails_the_job() {
+        let mut message = message(generation());
+        message.last_error = Some("the service is unavailable".to_string());
+
+        let outcome = dispatcher(None).give_up(&message).unwrap();
+        assert_eq!(outcome["jobId"], "job-1");
+        assert_eq!(outcome["status"], "failed");
+        assert_eq!(outcome["error"], "the service is unavailable");
+    }
+
+    #[test]
+    fn messages_round_trip_as_json() {
+        let message = message(generation());
+
+        let json = serde_json::to_value(&message).unwrap();
+        assert_eq!(json["status"], "pending");
+        assert_eq!(json["message"]["type"], "generateAgreement");
+        assert_eq!(json["message"]["payload"]["jobId"], "job-1");
+
+        let read: OutboxMessage = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(serde_json::to_value(read).unwrap(), json);
+    }
+}
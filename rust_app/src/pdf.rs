@@ -0,0 +1,536 @@
+//
+// PDF Writer
+//
+// A minimal PDF writer for agreements: A4 pages of headings, paragraphs and signature lines set in
+// the standard Helvetica fonts, with a footer on every page. Text is wrapped using the Helvetica
+// character widths. Characters outside the fonts' WinAnsi encoding are replaced with `?`, since the
+// standard fonts cannot show them without embedding a font.
+//
+// A watermark, such as `DRAFT`, can be printed diagonally in light grey behind the text of every
+// page.
+//
+
+use std::io::Write;
+
+const PAGE_WIDTH: f32 = 595.0;
+const PAGE_HEIGHT: f32 = 842.0;
+const MARGIN: f32 = 72.0;
+const TEXT_WIDTH: f32 = PAGE_WIDTH - 2.0 * MARGIN;
+const FOOTER_Y: f32 = 40.0;
+
+const HEADING_SIZE: f32 = 16.0;
+const BODY_SIZE: f32 = 11.0;
+const FOOTER_SIZE: f32 = 8.0;
+const WATERMARK_SIZE: f32 = 96.0;
+const LEADING: f32 = 1.4;
+
+// Widths of the printable ASCII characters (32 to 126) in Helvetica, in 1/1000 of the font size.
+const HELVETICA_WIDTHS: [u16; 95] = [
+    278, 278, 355, 556, 556, 889, 667, 191, 333, 333, 389, 584, 278, 333, 278, 278, 556, 556, 556,
+    556, 556, 556, 556, 556, 556, 556, 278, 278, 584, 584, 584, 556, 1015, 667, 667, 722, 722, 667,
+    611, 778, 722, 278, 500, 667, 556, 833, 722, 778, 667, 778, 722, 667, 611, 722, 667, 944, 667,
+    667, 611, 278, 278, 278, 469, 556, 333, 556, 556, 500, 556, 556, 278, 556, 556, 222, 222, 500,
+    222, 833, 556, 556, 556, 556, 333, 500, 278, 556, 500, 722, 500, 500, 500, 334, 260, 334, 584,
+];
+
+pub enum Block {
+    Heading(String),
+    Paragraph(String),
+
+    // A signature line, with the name of the signatory underneath.
+    Signature(String),
+}
+
+#[derive(Clone, Copy)]
+enum Font {
+    Regular,
+    Bold,
+}
+
+impl Font {
+    fn resource(self) -> &'static str {
+        match self {
+            Font::Regular => "F1",
+            Font::Bold => "F2",
+        }
+    }
+}
+
+//
+// Function write_pdf
+//
+// Lays out `blocks` on as many pages as needed and returns the PDF file. `footer` is printed at
+// the bottom of every page, followed by the page number. `lang` is the language of the text, as a
+// BCP 47 tag.
+//
+pub fn write_pdf(
+    blocks: &[Block],
+    footer: &str,
+    watermark: Option<&str>,
+    lang: Option<&str>,
+) -> Vec<u8> {
+    let mut layout = Layout::new();
+
+    for block in blocks {
+        match block {
+            Block::Heading(text) => {
+                layout.space(BODY_SIZE);
+                layout.paragraph(Font::Bold, HEADING_SIZE, text);
+            }
+            Block::Paragraph(text) => layout.paragraph(Font::Regular, BODY_SIZE, text),
+            Block::Signature(name) => {
+                layout.keep_together(BODY_SIZE * LEADING * 4.0 + 36.0);
+                layout.space(36.0);
+                layout.line(Font::Regular, BODY_SIZE, "________________________________");
+                layout.line(Font::Regular, BODY_SIZE, name);
+                layout.line(Font::Regular, BODY_SIZE, "Date: ________________");
+                layout.space(BODY_SIZE);
+            }
+        }
+    }
+
+    assemble(layout.finish(), footer, watermark, lang)
+}
+
+struct Layout {
+    pages: Vec<Vec<u8>>,
+    current: Vec<u8>,
+    y: f32,
+}
+
+impl Layout {
+    fn new() -> Self {
+        Self {
+            pages: vec![],
+            current: vec![],
+            y: PAGE_HEIGHT - MARGIN,
+        }
+    }
+
+    fn new_page(&mut self) {
+        self.pages.push(std::mem::take(&mut self.current));
+        self.y = PAGE_HEIGHT - MARGIN;
+    }
+
+    // Starts a new page unless `height` still fits on the current one.
+    fn keep_together(&mut self, height: f32) {
+        if self.y - height < MARGIN && !self.current.is_empty() {
+            self.new_page();
+        }
+    }
+
+    fn space(&mut self, height: f32) {
+        self.y -= height;
+    }
+
+    fn line(&mut self, font: Font, size: f32, text: &str) {
+        self.keep_together(size * LEADING);
+        self.y -= size * LEADING;
+
+        let _ = write!(
+            self.current,
+            "BT /{} {} Tf {} {} Td (",
+            font.resource(),
+            size,
+            MARGIN,
+            self.y
+        );
+        self.current.extend(encode(text));
+        self.current.extend_from_slice(b") Tj ET\n");
+    }
+
+    fn paragraph(&mut self, font: Font, size: f32, text: &str) {
+        for line in wrap(text, font, size, TEXT_WIDTH) {
+            self.line(font, size, &line);
+        }
+        self.space(size * 0.6);
+    }
+
+    fn finish(mut self) -> Vec<Vec<u8>> {
+        if !self.current.is_empty() || self.pages.is_empty() {
+            self.new_page();
+        }
+        self.pages
+    }
+}
+
+fn char_width(c: char, font: Font, size: f32) -> f32 {
+    let width = match c as u32 {
+        32..=126 => HELVETICA_WIDTHS[c as usize - 32] as f32,
+        _ => 556.0,
+    };
+    // Helvetica-Bold is slightly wider than Helvetica.
+    let scale = match font {
+        Font::Regular => 1.0,
+        Font::Bold => 1.06,
+    };
+
+    width * scale * size / 1000.0
+}
+
+fn text_width(text: &str, font: Font, size: f32) -> f32 {
+    text.chars().map(|c| char_width(c, font, size)).sum()
+}
+
+//
+// Function wrap
+//
+// Breaks `text` into lines no wider than `width`, at spaces where possible. Line breaks in the
+// text are kept.
+//
+fn wrap(text: &str, font: Font, size: f32, width: f32) -> Vec<String> {
+    let mut lines = vec![];
+
+    for input_line in text.lines() {
+        let mut line = String::new();
+
+        for word in input_line.split_whitespace() {
+            let candidate = if line.is_empty() {
+                word.to_string()
+            } else {
+                format!("{} {}", line, word)
+            };
+
+            if text_width(&candidate, font, size) <= width {
+                line = candidate;
+                continue;
+            }
+            if !line.is_empty() {
+                lines.push(std::mem::take(&mut line));
+            }
+
+            // A word wider than the line is split wherever it runs out of room.
+            for c in word.chars() {
+                if text_width(&line, font, size) + char_width(c, font, size) > width {
+                    lines.push(std::mem::take(&mut line));
+                }
+                line.push(c);
+            }
+        }
+
+        lines.push(line);
+    }
+
+    lines
+}
+
+// Encodes text as a PDF string literal in the fonts' WinAnsi encoding.
+fn encode(text: &str) -> Vec<u8> {
+    let mut output = Vec::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '(' | ')' | '\\' => {
+                output.push(b'\\');
+                output.push(c as u8);
+            }
+            c if c.is_control() => output.push(b' '),
+            c => output.push(win_ansi(c).unwrap_or(b'?')),
+        }
+    }
+    output
+}
+
+//
+// Function win_ansi
+//
+// The WinAnsi code of `c`. WinAnsi is Latin-1 with printable characters, such as curly quotes,
+// dashes and the euro sign, in place of the control characters 0x80 to 0x9F.
+//
+fn win_ansi(c: char) -> Option<u8> {
+    let code = match c {
+        '\u{20}'..='\u{7E}' | '\u{A0}'..='\u{FF}' => c as u32 as u8,
+        '€' => 0x80,
+        '‚' => 0x82,
+        'ƒ' => 0x83,
+        '„' => 0x84,
+        '…' => 0x85,
+        '†' => 0x86,
+        '‡' => 0x87,
+        'ˆ' => 0x88,
+        '‰' => 0x89,
+        'Š' => 0x8A,
+        '‹' => 0x8B,
+        'Œ' => 0x8C,
+        'Ž' => 0x8E,
+        '‘' => 0x91,
+        '’' => 0x92,
+        '“' => 0x93,
+        '”' => 0x94,
+        '•' => 0x95,
+        '–' => 0x96,
+        '—' => 0x97,
+        '˜' => 0x98,
+        '™' => 0x99,
+        'š' => 0x9A,
+        '›' => 0x9B,
+        'œ' => 0x9C,
+        'ž' => 0x9E,
+        'Ÿ' => 0x9F,
+        _ => return None,
+    };
+
+    Some(code)
+}
+
+//
+// Function draw_watermark
+//
+// Draws `text` in light grey along the diagonal of the page, centred, shrinking it to fit.
+//
+fn draw_watermark(text: &str) -> Vec<u8> {
+    let diagonal = (PAGE_WIDTH * PAGE_WIDTH + PAGE_HEIGHT * PAGE_HEIGHT).sqrt();
+    let angle = PAGE_HEIGHT.atan2(PAGE_WIDTH);
+    let (sin, cos) = angle.sin_cos();
+
+    let width_at_one = text_width(text, Font::Bold, 1.0).max(f32::EPSILON);
+    let size = WATERMARK_SIZE.min(diagonal * 0.8 / width_at_one);
+    let width = width_at_one * size;
+
+    // Start so that the middle of the baseline is at the centre of the page.
+    let x = PAGE_WIDTH / 2.0 - cos * width / 2.0 + sin * size / 3.0;
+    let y = PAGE_HEIGHT / 2.0 - sin * width / 2.0 - cos * size / 3.0;
+
+    let mut content = format!(
+        "q 0.85 g BT /F2 {} Tf {} {} {} {} {} {} Tm (",
+        size, cos, sin, -sin, cos, x, y
+    )
+    .into_bytes();
+    content.extend(encode(text));
+    content.extend_from_slice(b") Tj ET Q\n");
+    content
+}
+
+//
+// Function assemble
+//
+// Writes the PDF objects: the catalog, the page tree, the two fonts, and a page and a content
+// stream for every page, followed by the cross-reference table.
+//
+fn assemble(
+    pages: Vec<Vec<u8>>,
+    footer: &str,
+    watermark: Option<&str>,
+    lang: Option<&str>,
+) -> Vec<u8> {
+    let page_count = pages.len();
+    let mut objects: Vec<Vec<u8>> = vec![];
+
+    let kids = (0..page_count)
+        .map(|i| format!("{} 0 R", 5 + 2 * i))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let mut catalog = b"<< /Type /Catalog /Pages 2 0 R".to_vec();
+    if let Some(lang) = lang {
+        catalog.extend_from_slice(b" /Lang (");
+        catalog.extend(encode(lang));
+        catalog.push(b')');
+    }
+    catalog.extend_from_slice(b" >>");
+    objects.push(catalog);
+    objects.push(format!("<< /Type /Pages /Kids [{}] /Count {} >>", kids, page_count).into_bytes());
+    objects.push(
+        b"<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica /Encoding /WinAnsiEncoding >>"
+            .to_vec(),
+    );
+    objects.push(
+        b"<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica-Bold /Encoding /WinAnsiEncoding >>"
+            .to_vec(),
+    );
+
+    let watermark = watermark.map(draw_watermark).unwrap_or_default();
+
+    for (i, page) in pages.into_iter().enumerate() {
+        // The watermark is drawn first so the text is printed over it.
+        let mut content = watermark.clone();
+        content.extend(page);
+
+        let _ = write!(
+            content,
+            "BT /F1 {} Tf {} {} Td (",
+            FOOTER_SIZE, MARGIN, FOOTER_Y
+        );
+        content.extend(encode(&format!(
+            "{} - Page {} of {}",
+            footer,
+            i + 1,
+            page_count
+        )));
+        content.extend_from_slice(b") Tj ET\n");
+
+        objects.push(
+            format!(
+                "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {} {}] /Resources << /Font << /F1 3 0 R /F2 4 0 R >> >> /Contents {} 0 R >>",
+                PAGE_WIDTH,
+                PAGE_HEIGHT,
+                6 + 2 * i
+            )
+            .into_bytes(),
+        );
+
+        let mut stream = format!("<< /Length {} >>\nstream\n", content.len()).into_bytes();
+        stream.extend(content);
+        stream.extend_from_slice(b"\nendstream");
+        objects.push(stream);
+    }
+
+    let mut output = b"%PDF-1.4\n%\xE2\xE3\xCF\xD3\n".to_vec();
+    let mut offsets = Vec::with_capacity(objects.len());
+    for (i, object) in objects.iter().enumerate() {
+        offsets.push(output.len());
+        let _ = writeln!(output, "{} 0 obj", i + 1);
+        output.extend(object);
+        output.extend_from_slice(b"\nendobj\n");
+    }
+
+    let xref = output.len();
+    let _ = write!(
+        output,
+        "xref\n0 {}\n0000000000 65535 f \n",
+        objects.len() + 1
+    );
+    for offset in offsets {
+        let _ = writeln!(output, "{:010} 00000 n ", offset);
+    }
+    let _ = write!(
+        output,
+        "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n",
+        objects.len() + 1,
+        xref
+    );
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+        haystack
+            .windows(needle.len())
+            .any(|window| window == needle)
+    }
+
+    fn find(haystack: &[u8], needle: &[u8]) -> usize {
+        haystack
+            .windows(needle.len())
+            .position(|window| window == needle)
+            .unwrap()
+    }
+
+    #[test]
+    fn the_cross_reference_table_points_at_every_object() {
+        let blocks = (0..200)
+            .map(|i| Block::Paragraph(format!("Paragraph {}", i)))
+            .collect::<Vec<_>>();
+        let pdf = write_pdf(&blocks, "Agreement", None, None);
+        let text = String::from_utf8_lossy(&pdf);
+
+        let startxref = text.rsplit("startxref\n").next().unwrap();
+        let xref = startxref.lines().next().unwrap().parse::<usize>().unwrap();
+        assert!(pdf[xref..].starts_with(b"xref\n0 "));
+
+        let entries = String::from_utf8_lossy(&pdf[xref..])
+            .lines()
+            .skip(3)
+            .take_while(|line| line.ends_with(" n "))
+            .map(|line| line[..10].parse::<usize>().unwrap())
+            .collect::<Vec<_>>();
+        let pages = text.matches("/Type /Page ").count();
+        assert!(pages > 1);
+        assert_eq!(entries.len(), 4 + 2 * pages);
+        for (i, offset) in entries.into_iter().enumerate() {
+            assert!(
+                pdf[offset..].starts_with(format!("{} 0 obj\n", i + 1).as_bytes()),
+                "object {} is not at {}",
+                i + 1,
+                offset
+            );
+        }
+    }
+
+    #[test]
+    fn stream_lengths_match_their_content() {
+        let pdf = write_pdf(
+            &[Block::Paragraph("Hello".to_string())],
+            "Agreement",
+            Some("DRAFT"),
+            None,
+        );
+        let text = String::from_utf8_lossy(&pdf);
+
+        let length = text
+            .split("<< /Length ")
+            .nth(1)
+            .and_then(|rest| rest.split(' ').next())
+            .unwrap()
+            .parse::<usize>()
+            .unwrap();
+        let start = find(&pdf, b"stream\n") + b"stream\n".len();
+        assert_eq!(&pdf[start + length..start + length + 10], b"\nendstream");
+    }
+
+    #[test]
+    fn string_delimiters_are_escaped() {
+        assert_eq!(encode("a (b) c\\d"), b"a \\(b\\) c\\\\d");
+        assert_eq!(encode("tab\there"), b"tab here");
+    }
+
+    #[test]
+    fn text_is_encoded_as_win_ansi() {
+        assert_eq!(encode("café"), b"caf\xE9");
+        assert_eq!(encode("“€5” – ‘ok’…"), b"\x93\x805\x94 \x96 \x91ok\x92\x85");
+        assert_eq!(encode("Œuvre™"), b"\x8Cuvre\x99");
+
+        // Latin-1's control characters are not WinAnsi's printable ones.
+        assert_eq!(encode("a\u{93}b\u{80}c"), b"a b c");
+        assert_eq!(encode("日本"), b"??");
+    }
+
+    #[test]
+    fn text_is_wrapped_at_spaces_within_the_width() {
+        let text = "The quick brown fox jumps over the lazy dog. ".repeat(10);
+        let lines = wrap(&text, Font::Regular, BODY_SIZE, TEXT_WIDTH);
+
+        assert!(lines.len() > 1);
+        for line in &lines {
+            assert!(text_width(line, Font::Regular, BODY_SIZE) <= TEXT_WIDTH);
+            assert_eq!(line.trim(), line);
+        }
+        assert_eq!(lines.join(" "), text.trim_end());
+    }
+
+    #[test]
+    fn line_breaks_are_kept_and_long_words_split() {
+        let word = "x".repeat(200);
+        let lines = wrap(
+            &format!("first\nsecond {}", word),
+            Font::Regular,
+            BODY_SIZE,
+            TEXT_WIDTH,
+        );
+
+        assert_eq!(lines[0], "first");
+        assert_eq!(lines[1], "second");
+        assert!(lines.len() > 3);
+        assert_eq!(lines[2..].concat(), word);
+    }
+
+    #[test]
+    fn the_footer_numbers_every_page() {
+        let blocks = (0..200)
+            .map(|i| Block::Paragraph(format!("Paragraph {}", i)))
+            .collect::<Vec<_>>();
+        let pdf = write_pdf(&blocks, "Agreement", None, None);
+        let pages = String::from_utf8_lossy(&pdf)
+            .matches("/Type /Page ")
+            .count();
+
+        for page in 1..=pages {
+            assert!(contains(
+                &pdf,
+                format!("Agreement - Page {} of {}", page, pages).as_bytes()
+            ));
+        }
+    }
+}
@@ -0,0 +1,224 @@
+//
+// Authorisation Policy
+//
+// Decides which parties may send which requests. The contract `{data}` declares its parties, each a
+// `Party` participant identified by the subject of its bearer tokens and holding a list of roles.
+// The policy lists, for each request `$class`, the roles allowed to send it. Requests the policy
+// does not list are refused.
+//
+// The default policy can be replaced with `AUTHORIZATION_POLICY`, a JSON object mapping request
+// classes to roles:
+//
+//     { "org.accordproject.helloworldstate.MyRequest": ["buyer", "seller"] }
+//
+// `AgreementGenerationCallback` is reserved to the `service` role, held by the party the generation
+// service authenticates as, if it authenticates at all: callbacks are checked with their job's
+// callback token either way.
+//
+// `SIGNED_REQUESTS` lists, comma-separated, the request classes that must be signed by a party (see
+// `signature`). No request has to be signed by default.
+//
+// The role policy only applies to authenticated callers. Without authentication there is no caller to
+// check, and requests the contract makes itself, such as the outcome of an outbox message, are
+// never restricted. Contracts that declare no parties are not restricted either.
+//
+
+use crate::auth::Caller;
+use crate::error::ContractError;
+use crate::handlers::agreement_callback::AGREEMENT_CALLBACK_REQUEST_CLASS;
+use crate::handlers::agreement_status::AGREEMENT_STATUS_REQUEST_CLASS;
+use crate::handlers::amendment::{
+    AMEND_CONTRACT_REQUEST_CLASS, APPROVE_AMENDMENT_REQUEST_CLASS, REJECT_AMENDMENT_REQUEST_CLASS,
+};
+use crate::handlers::contract_data::CONTRACT_DATA_REQUEST_CLASS;
+use crate::handlers::erase_personal_data::ERASE_PERSONAL_DATA_REQUEST_CLASS;
+use crate::handlers::generate_agreement::GENERATE_AGREEMENT_REQUEST_CLASS;
+use crate::handlers::lifecycle::{
+    ACTIVATE_CONTRACT_REQUEST_CLASS, RESUME_CONTRACT_REQUEST_CLASS, SUSPEND_CONTRACT_REQUEST_CLASS,
+    TERMINATE_CONTRACT_REQUEST_CLASS,
+};
+use crate::handlers::my_request::MY_REQUEST_CLASS;
+use crate::handlers::sign_agreement::SIGN_AGREEMENT_REQUEST_CLASS;
+use crate::HELLO_WORLD_CLAUSE_CLASS;
+use lib::org_accordproject_helloworldstate::*;
+use std::collections::HashMap;
+use std::env;
+
+pub struct Policy {
+    roles: HashMap<String, Vec<String>>,
+    signed: Vec<String>,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        let roles = [
+            (HELLO_WORLD_CLAUSE_CLASS, vec!["owner"]),
+            (MY_REQUEST_CLASS, vec!["owner", "party"]),
+            (GENERATE_AGREEMENT_REQUEST_CLASS, vec!["owner"]),
+            (AGREEMENT_CALLBACK_REQUEST_CLASS, vec!["service"]),
+            (AGREEMENT_STATUS_REQUEST_CLASS, vec!["owner", "party"]),
+            (SIGN_AGREEMENT_REQUEST_CLASS, vec!["owner", "party"]),
+            (ACTIVATE_CONTRACT_REQUEST_CLASS, vec!["owner"]),
+            (SUSPEND_CONTRACT_REQUEST_CLASS, vec!["owner"]),
+            (RESUME_CONTRACT_REQUEST_CLASS, vec!["owner"]),
+            (TERMINATE_CONTRACT_REQUEST_CLASS, vec!["owner"]),
+            (AMEND_CONTRACT_REQUEST_CLASS, vec!["owner", "party"]),
+            (APPROVE_AMENDMENT_REQUEST_CLASS, vec!["owner", "party"]),
+            (REJECT_AMENDMENT_REQUEST_CLASS, vec!["owner", "party"]),
+            (CONTRACT_DATA_REQUEST_CLASS, vec!["owner", "party"]),
+            (ERASE_PERSONAL_DATA_REQUEST_CLASS, vec!["owner"]),
+        ];
+
+        Self {
+            roles: roles
+                .into_iter()
+                .map(|(class, roles)| {
+                    let roles = roles.into_iter().map(str::to_string).collect();
+                    (class.to_string(), roles)
+                })
+                .collect(),
+            signed: vec![],
+        }
+    }
+}
+
+impl Policy {
+    pub fn from_env() -> Self {
+        let mut policy = Self::default();
+        if let Ok(roles) = env::var("AUTHORIZATION_POLICY") {
+            if !roles.is_empty() {
+                policy.roles = serde_json::from_str(&roles)
+                    .expect("AUTHORIZATION_POLICY must map request classes to lists of roles");
+            }
+        }
+        if let Ok(signed) = env::var("SIGNED_REQUESTS") {
+            policy.signed = signed
+                .split(',')
+                .map(str::trim)
+                .filter(|class| !class.is_empty())
+                .map(str::to_string)
+                .collect();
+        }
+
+        policy
+    }
+
+    // Whether requests of class `class` must be signed by a party.
+    pub fn requires_signature(&self, class: &str) -> bool {
+        self.signed.iter().any(|signed| signed == class)
+    }
+
+    //
+    // Function authorize
+    //
+    // Checks that `caller` holds one of the roles allowed to send requests of class `class` to the
+    // contract with the `{data}` `data`.
+    //
+    pub fn authorize(
+        &self,
+        data: &HelloWorldClause,
+        caller: Option<&Caller>,
+        class: &str,
+    ) -> Result<(), ContractError> {
+        let Some(caller) = caller else {
+            return Ok(());
+        };
+        let parties = match &data.parties {
+            Some(parties) if !parties.is_empty() => parties,
+            _ => return Ok(()),
+        };
+        let allowed = self.roles.get(class).map(Vec::as_slice).unwrap_or_default();
+
+        let permitted = parties
+            .iter()
+            .filter(|party| party._identifier == caller.subject)
+            .flat_map(|party| &party.roles)
+            .any(|role| allowed.contains(role));
+        if permitted {
+            Ok(())
+        } else {
+            Err(ContractError::Forbidden {
+                subject: caller.subject.clone(),
+                request: class.to_string(),
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{clause, party, registry};
+    use serde_json::Map;
+
+    fn caller(subject: &str) -> Caller {
+        Caller {
+            subject: subject.to_string(),
+            issuer: "https://issuer.example.com/".to_string(),
+            email: None,
+            claims: Map::new(),
+        }
+    }
+
+    fn data() -> HelloWorldClause {
+        let mut data = clause();
+        data.parties = Some(vec![
+            party("auth0|fred", &["owner"]),
+            party("generation-service", &["service"]),
+        ]);
+        data
+    }
+
+    #[test]
+    fn callbacks_are_reserved_to_the_service() {
+        let policy = Policy::default();
+
+        assert!(policy
+            .authorize(
+                &data(),
+                Some(&caller("generation-service")),
+                AGREEMENT_CALLBACK_REQUEST_CLASS
+            )
+            .is_ok());
+        assert!(matches!(
+            policy.authorize(
+                &data(),
+                Some(&caller("auth0|fred")),
+                AGREEMENT_CALLBACK_REQUEST_CLASS
+            ),
+            Err(ContractError::Forbidden { .. })
+        ));
+    }
+
+    #[test]
+    fn refuses_request_classes_it_does_not_list() {
+        let policy = Policy::default();
+
+        assert!(matches!(
+            policy.authorize(
+                &data(),
+                Some(&caller("auth0|fred")),
+                "org.accordproject.helloworldstate.Unknown"
+            ),
+            Err(ContractError::Forbidden { .. })
+        ));
+    }
+
+    #[test]
+    fn lists_every_registered_request_class() {
+        let policy = Policy::default();
+
+        for class in registry().classes() {
+            assert!(policy.roles.contains_key(class), "{} is not listed", class);
+        }
+    }
+
+    #[test]
+    fn does_not_restrict_requests_made_by_the_contract() {
+        let policy = Policy::default();
+
+        assert!(policy
+            .authorize(&data(), None, AGREEMENT_CALLBACK_REQUEST_CLASS)
+            .is_ok());
+    }
+}
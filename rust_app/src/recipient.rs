@@ -0,0 +1,198 @@
+//
+// Recipients
+//
+// The people a generated agreement is sent to. Every recipient has a role: the parties to the
+// agreement, people copied in, and the parties' counsel.
+//
+
+use crate::error::ContractError;
+use serde::{Deserialize, Serialize};
+
+const MAX_EMAIL_LENGTH: usize = 254;
+const MAX_LOCAL_PART_LENGTH: usize = 64;
+const MAX_LABEL_LENGTH: usize = 63;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RecipientRole {
+    Party,
+    Cc,
+    Counsel,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Recipient {
+    pub email: String,
+
+    pub role: RecipientRole,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+impl Recipient {
+    pub fn party(email: &str) -> Self {
+        Self {
+            email: email.to_string(),
+            role: RecipientRole::Party,
+            name: None,
+        }
+    }
+}
+
+//
+// Function validate_recipients
+//
+// Checks that there is at least one party and that every email address is valid. Addresses are
+// compared ignoring case, and each may only appear once.
+//
+pub fn validate_recipients(recipients: &[Recipient]) -> Result<(), ContractError> {
+    if !recipients
+        .iter()
+        .any(|recipient| recipient.role == RecipientRole::Party)
+    {
+        return Err(ContractError::InvalidRequest(
+            "at least one recipient must be a party".to_string(),
+        ));
+    }
+
+    for (i, recipient) in recipients.iter().enumerate() {
+        if !is_valid_email(&recipient.email) {
+            return Err(ContractError::InvalidRequest(format!(
+                "invalid email address: '{}'",
+                recipient.email
+            )));
+        }
+        if recipients[..i]
+            .iter()
+            .any(|other| other.email.eq_ignore_ascii_case(&recipient.email))
+        {
+            return Err(ContractError::InvalidRequest(format!(
+                "duplicate recipient: '{}'",
+                recipient.email
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+//
+// Function is_valid_email
+//
+// Accepts addresses of the form `local@domain`, where the local part is made of the characters
+// allowed in an unquoted address and the domain is a dotted host name with a top-level domain.
+// Quoted local parts and IP address literals are not accepted.
+//
+pub fn is_valid_email(email: &str) -> bool {
+    let (local, domain) = match email.rsplit_once('@') {
+        Some(parts) => parts,
+        None => return false,
+    };
+
+    let valid_local = !local.is_empty()
+        && local.len() <= MAX_LOCAL_PART_LENGTH
+        && !local.starts_with('.')
+        && !local.ends_with('.')
+        && !local.contains("..")
+        && local
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "!#$%&'*+-/=?^_`{|}~.".contains(c));
+
+    let labels = domain.split('.').collect::<Vec<_>>();
+    let valid_domain = labels.len() >= 2
+        && labels.iter().all(|label| {
+            !label.is_empty()
+                && label.len() <= MAX_LABEL_LENGTH
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+        && labels
+            .last()
+            .is_some_and(|tld| tld.chars().all(|c| c.is_ascii_alphabetic()));
+
+    email.len() <= MAX_EMAIL_LENGTH && valid_local && valid_domain
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cc(email: &str) -> Recipient {
+        Recipient {
+            email: email.to_string(),
+            role: RecipientRole::Cc,
+            name: None,
+        }
+    }
+
+    #[test]
+    fn ordinary_addresses_are_valid() {
+        for email in [
+            "fred@example.com",
+            "fred.bloggs+contracts@mail.example.co.uk",
+            "o'brien@example-law.ie",
+        ] {
+            assert!(is_valid_email(email), "{}", email);
+        }
+    }
+
+    #[test]
+    fn malformed_addresses_are_invalid() {
+        let long_local = format!("{}@example.com", "f".repeat(MAX_LOCAL_PART_LENGTH + 1));
+        let long_label = format!("fred@{}.com", "e".repeat(MAX_LABEL_LENGTH + 1));
+        let long_email = format!("fred@{}.com", ["example"; 40].join("."));
+
+        for email in [
+            "fred",
+            "@example.com",
+            "fred@",
+            "fred@example",
+            "fred@example.c0m",
+            ".fred@example.com",
+            "fred.@example.com",
+            "fred..bloggs@example.com",
+            "fred bloggs@example.com",
+            "\"fred\"@example.com",
+            "fred@[192.168.0.1]",
+            "fred@-example.com",
+            "fred@example-.com",
+            "fred@example..com",
+            &long_local,
+            &long_label,
+            &long_email,
+        ] {
+            assert!(!is_valid_email(email), "{}", email);
+        }
+    }
+
+    #[test]
+    fn recipients_need_a_party() {
+        assert!(validate_recipients(&[cc("fred@example.com")]).is_err());
+        assert!(validate_recipients(&[]).is_err());
+        assert!(
+            validate_recipients(&[Recipient::party("fred@example.com"), cc("jo@example.com")])
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn recipients_are_unique_ignoring_case() {
+        let result =
+            validate_recipients(&[Recipient::party("fred@example.com"), cc("Fred@Example.com")]);
+
+        assert!(
+            matches!(result, Err(ContractError::InvalidRequest(message)) if message.contains("duplicate"))
+        );
+    }
+
+    #[test]
+    fn an_invalid_address_is_rejected() {
+        let result = validate_recipients(&[Recipient::party("fred@example.com"), cc("jo@")]);
+
+        assert!(
+            matches!(result, Err(ContractError::InvalidRequest(message)) if message.contains("invalid email"))
+        );
+    }
+}
@@ -0,0 +1,256 @@
+//
+// PII Redaction
+//
+// Personal data is kept out of logs and error messages. Types that hold personal data implement
+// `Personal`, listing the JSON paths of their personal fields. The personal fields of the contract
+// `{data}` are listed once, by `HelloWorldClause`, mirroring the `@pii` decorators in the model, and
+// types holding a copy of the data name where it is instead of listing them again. The same lists
+// decide what is masked here, what is encrypted at rest (see `encryption`) and what is erased (see
+// `erasure`).
+//
+// While a request is handled, the values at those paths in the contract `{data}`, the
+// request and the caller are registered, and every log line and error message is masked before it
+// leaves the function: registered values, and anything that looks like an email address, are
+// replaced with `[REDACTED]`.
+//
+// Set `REDACT_PII` to `false` to see personal data when debugging locally.
+//
+
+use lib::org_accordproject_helloworldstate::HelloWorldClause;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::env;
+use std::io::{self, Write};
+use std::sync::{Mutex, OnceLock};
+use tracing_subscriber::fmt::MakeWriter;
+
+const MASK: &str = "[REDACTED]";
+
+// Shorter values are not masked, as they would mask unrelated text.
+const MIN_LENGTH: usize = 3;
+
+static VALUES: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+
+pub trait Personal {
+    // Paths of the personal fields, with `.` between field names. Arrays are traversed, so
+    // `recipients.email` is the `email` of every recipient.
+    const PERSONAL_FIELDS: &'static [&'static str] = &[];
+
+    // Paths of the copies of the contract `{data}` held, whose personal fields are personal too.
+    const DATA_COPIES: &'static [&'static str] = &[];
+
+    // Every personal path: the personal fields, and the personal fields of each copy of the data.
+    fn personal_fields() -> Vec<String> {
+        let data_fields = HelloWorldClause::PERSONAL_FIELDS;
+        Self::PERSONAL_FIELDS
+            .iter()
+            .map(|field| field.to_string())
+            .chain(Self::DATA_COPIES.iter().flat_map(|copy| {
+                data_fields
+                    .iter()
+                    .map(move |field| format!("{}.{}", copy, field))
+            }))
+            .collect()
+    }
+}
+
+pub fn enabled() -> bool {
+    static ENABLED: OnceLock<bool> = OnceLock::new();
+    *ENABLED.get_or_init(|| env::var("REDACT_PII").map_or(true, |value| value != "false"))
+}
+
+// Forgets the values registered for the previous request.
+pub fn clear() {
+    VALUES.lock().unwrap().clear();
+}
+
+//
+// Function register
+//
+// Registers the values at `paths` in `value` as personal data.
+//
+pub fn register<S: AsRef<str>>(value: &Value, paths: &[S]) {
+    if !enabled() {
+        return;
+    }
+
+    let mut values = VALUES.lock().unwrap();
+    for path in paths {
+        collect(
+            value,
+            &path.as_ref().split('.').collect::<Vec<_>>(),
+            &mut values,
+        );
+    }
+}
+
+pub fn register_personal<T: Personal + Serialize>(value: &T) {
+    if let Ok(value) = serde_json::to_value(value) {
+        register(&value, &T::personal_fields());
+    }
+}
+
+fn collect(value: &Value, path: &[&str], values: &mut BTreeSet<String>) {
+    match (value, path.split_first()) {
+        (Value::Array(items), _) => {
+            for item in items {
+                collect(item, path, values);
+            }
+        }
+        (Value::Object(map), Some((name, rest))) => {
+            if let Some(value) = map.get(*name) {
+                collect(value, rest, values);
+            }
+        }
+        (Value::String(s), None) if s.chars().count() >= MIN_LENGTH => {
+            // Values also appear JSON-escaped in JSON logs, and escaped twice in JSON logged as a
+            // field, such as an emitted event.
+            let mut value = s.clone();
+            for _ in 0..3 {
+                let escaped = Value::String(value.clone()).to_string();
+                values.insert(value);
+                value = escaped[1..escaped.len() - 1].to_string();
+            }
+        }
+        _ => {}
+    }
+}
+
+//
+// Function mask
+//
+// `text` with the registered values and email addresses replaced.
+//
+pub fn mask(text: &str) -> String {
+    if !enabled() {
+        return text.to_string();
+    }
+
+    let mut masked = mask_emails(text);
+    let values = VALUES.lock().unwrap();
+
+    // Longest first, so a value is not left partly visible by masking a shorter one inside it.
+    let mut values = values.iter().collect::<Vec<_>>();
+    values.sort_by_key(|value| std::cmp::Reverse(value.len()));
+    for value in values {
+        if masked.contains(value.as_str()) {
+            masked = masked.replace(value.as_str(), MASK);
+        }
+    }
+
+    masked
+}
+
+fn mask_emails(text: &str) -> String {
+    let is_email_char = |c: char| c.is_ascii_alphanumeric() || "._%+-@".contains(c);
+    let mut output = String::with_capacity(text.len());
+    let mut word = String::new();
+
+    for c in text.chars().chain(std::iter::once('\0')) {
+        if is_email_char(c) {
+            word.push(c);
+            continue;
+        }
+
+        if looks_like_email(&word) {
+            output.push_str(MASK);
+        } else {
+            output.push_str(&word);
+        }
+        word.clear();
+        if c != '\0' {
+            output.push(c);
+        }
+    }
+
+    output
+}
+
+fn looks_like_email(word: &str) -> bool {
+    match word.split_once('@') {
+        Some((local, domain)) => {
+            !local.is_empty() && domain.contains('.') && !domain.starts_with('.')
+        }
+        None => false,
+    }
+}
+
+//
+// Writes log lines to stdout, masked.
+//
+pub struct RedactingStdout;
+
+impl<'a> MakeWriter<'a> for RedactingStdout {
+    type Writer = RedactingLine;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        RedactingLine(Vec::new())
+    }
+}
+
+// A log line, written out masked when it is complete.
+pub struct RedactingLine(Vec<u8>);
+
+impl Write for RedactingLine {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for RedactingLine {
+    fn drop(&mut self) {
+        let line = mask(&String::from_utf8_lossy(&self.0));
+        let _ = io::stdout().lock().write_all(line.as_bytes());
+    }
+}
+
+impl Personal for HelloWorldClause {
+    const PERSONAL_FIELDS: &'static [&'static str] = &["name", "parties.$identifier"];
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::outbox::OutboxMessage;
+    use crate::state::ContractState;
+
+    #[test]
+    fn the_contract_data_lists_every_pii_field_of_the_model() {
+        let model = include_str!("../model/model.cto");
+        let lines = model.lines().map(str::trim).collect::<Vec<_>>();
+        let pii = lines
+            .windows(2)
+            .filter(|pair| pair[0] == "@pii")
+            .filter_map(|pair| pair[1].split_whitespace().nth(2))
+            .collect::<Vec<_>>();
+
+        assert!(!pii.is_empty());
+        for field in pii {
+            assert!(
+                HelloWorldClause::PERSONAL_FIELDS.contains(&field),
+                "{}",
+                field
+            );
+        }
+    }
+
+    #[test]
+    fn copies_of_the_contract_data_share_its_personal_fields() {
+        for field in HelloWorldClause::PERSONAL_FIELDS {
+            assert!(
+                ContractState::personal_fields().contains(&format!("dataVersions.data.{}", field))
+            );
+            assert!(
+                ContractState::personal_fields().contains(&format!("amendments.data.{}", field))
+            );
+            assert!(OutboxMessage::personal_fields()
+                .contains(&format!("message.payload.data.{}", field)));
+        }
+    }
+}
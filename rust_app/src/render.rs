@@ -0,0 +1,272 @@
+//
+// Agreement Rendering
+//
+// Renders the text of the agreement from the template grammar and the contract `{data}`.
+// The grammar is TemplateMark: Markdown with `{{variable}}` placeholders, e.g.
+//
+//     Name of the person to greet: {{name}}.
+//
+// Variables are looked up in the JSON representation of the `{data}`, and nested fields can be
+// reached with a dotted path (`{{address.city}}`). Block markers such as `{{#clause greeting}}` and
+// `{{/clause}}` are rendered as their content. Formulas (`{{% ... %}}`) are not supported.
+//
+
+use crate::error::ContractError;
+use crate::pdf::Block;
+use pulldown_cmark::{html, Event, Parser, Tag};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct RenderedAgreement {
+    pub markdown: String,
+    pub html: String,
+    pub text: String,
+}
+
+pub fn render(grammar: &str, data: &Value) -> Result<RenderedAgreement, ContractError> {
+    let markdown = render_markdown(grammar, data)?;
+
+    Ok(RenderedAgreement {
+        html: to_html(&markdown),
+        text: to_text(&markdown),
+        markdown,
+    })
+}
+
+//
+// Function render_markdown
+//
+// Replaces every placeholder in the grammar with its value from `data`.
+//
+pub fn render_markdown(grammar: &str, data: &Value) -> Result<String, ContractError> {
+    let mut output = String::with_capacity(grammar.len());
+    let mut rest = grammar;
+
+    while let Some(start) = rest.find("{{") {
+        output.push_str(&rest[..start]);
+
+        let after = &rest[start + 2..];
+        let end = after
+            .find("}}")
+            .ok_or_else(|| ContractError::Render("unclosed '{{' in the grammar".to_string()))?;
+        let tag = after[..end].trim();
+        rest = &after[end + 2..];
+
+        match tag.chars().next() {
+            Some('#') | Some('/') => {}
+            Some('%') => {
+                return Err(ContractError::Render(format!(
+                    "formulas are not supported: {{{{{}}}}}",
+                    tag
+                )))
+            }
+            _ => output.push_str(&escape_markdown(&variable(data, tag)?)),
+        }
+    }
+    output.push_str(rest);
+
+    Ok(output)
+}
+
+pub fn to_html(markdown: &str) -> String {
+    let mut output = String::new();
+    html::push_html(&mut output, Parser::new(markdown));
+    output
+}
+
+pub fn to_text(markdown: &str) -> String {
+    let mut output = String::new();
+
+    for event in Parser::new(markdown) {
+        match event {
+            Event::Text(text) | Event::Code(text) => output.push_str(&text),
+            Event::SoftBreak | Event::HardBreak => output.push('\n'),
+            Event::Start(Tag::Item) => output.push_str("- "),
+            Event::End(Tag::Item) => output.push('\n'),
+            Event::End(Tag::Paragraph) | Event::End(Tag::Heading(..)) => output.push_str("\n\n"),
+            _ => {}
+        }
+    }
+
+    output.trim_end().to_string()
+}
+
+//
+// Function to_blocks
+//
+// The headings and paragraphs of the agreement, for laying out as a PDF.
+//
+pub fn to_blocks(markdown: &str) -> Vec<Block> {
+    let mut blocks = vec![];
+    let mut text = String::new();
+
+    for event in Parser::new(markdown) {
+        match event {
+            Event::Text(t) | Event::Code(t) => text.push_str(&t),
+            Event::SoftBreak => text.push(' '),
+            Event::HardBreak => text.push('\n'),
+            Event::Start(Tag::Item) => text.push_str("- "),
+            Event::End(Tag::Heading(..)) => blocks.push(Block::Heading(std::mem::take(&mut text))),
+            Event::End(Tag::Paragraph) | Event::End(Tag::Item) if !text.is_empty() => {
+                blocks.push(Block::Paragraph(std::mem::take(&mut text)));
+            }
+            _ => {}
+        }
+    }
+
+    blocks
+}
+
+fn variable(data: &Value, path: &str) -> Result<String, ContractError> {
+    let value = path
+        .split('.')
+        .try_fold(data, |value, name| value.get(name))
+        .ok_or_else(|| ContractError::Render(format!("unknown variable: {}", path)))?;
+
+    match value {
+        Value::String(s) => Ok(s.clone()),
+        Value::Number(n) => Ok(n.to_string()),
+        Value::Bool(b) => Ok(b.to_string()),
+        _ => Err(ContractError::Render(format!(
+            "variable {} cannot be rendered as text",
+            path
+        ))),
+    }
+}
+
+// Values are text, so characters that Markdown would interpret are escaped.
+fn escape_markdown(value: &str) -> String {
+    let mut output = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(
+            c,
+            '\\' | '`' | '*' | '_' | '{' | '}' | '[' | ']' | '<' | '>' | '#' | '|' | '!'
+        ) {
+            output.push('\\');
+        }
+        output.push(c);
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn data() -> Value {
+        json!({
+            "name": "Fred Bloggs",
+            "amount": 2500,
+            "signed": true,
+            "address": { "city": "London" },
+            "parties": []
+        })
+    }
+
+    #[test]
+    fn placeholders_are_replaced_with_their_values() {
+        let markdown = render_markdown(
+            "{{name}} pays {{ amount }} ({{signed}}) in {{address.city}}.",
+            &data(),
+        )
+        .unwrap();
+
+        assert_eq!(markdown, "Fred Bloggs pays 2500 (true) in London.");
+    }
+
+    #[test]
+    fn block_markers_are_rendered_as_their_content() {
+        let markdown =
+            render_markdown("{{#clause greeting}}Hello {{name}}.{{/clause}}", &data()).unwrap();
+
+        assert_eq!(markdown, "Hello Fred Bloggs.");
+    }
+
+    #[test]
+    fn unknown_and_structured_variables_are_errors() {
+        for grammar in ["{{missing}}", "{{address.street}}", "{{name.first}}"] {
+            assert!(
+                matches!(
+                    render_markdown(grammar, &data()),
+                    Err(ContractError::Render(e)) if e.starts_with("unknown variable")
+                ),
+                "{}",
+                grammar
+            );
+        }
+        assert!(matches!(
+            render_markdown("{{address}}", &data()),
+            Err(ContractError::Render(e)) if e.contains("cannot be rendered")
+        ));
+    }
+
+    #[test]
+    fn unclosed_placeholders_and_formulas_are_errors() {
+        assert!(matches!(
+            render_markdown("Hello {{name", &data()),
+            Err(ContractError::Render(e)) if e.contains("unclosed")
+        ));
+        assert!(matches!(
+            render_markdown("{{% 1 + 1 %}}", &data()),
+            Err(ContractError::Render(e)) if e.contains("formulas")
+        ));
+    }
+
+    #[test]
+    fn values_cannot_add_markdown_or_html() {
+        let data = json!({ "name": "*Fred* <script>alert(1)</script> [link](x)" });
+        let rendered = render("Hello {{name}}", &data).unwrap();
+
+        assert!(!rendered.html.contains("<script>"));
+        assert!(!rendered.html.contains("<em>"));
+        assert!(!rendered.html.contains("<a "));
+        assert!(rendered.html.contains("&lt;script&gt;"));
+        assert_eq!(
+            rendered.text,
+            "Hello *Fred* <script>alert(1)</script> [link](x)"
+        );
+    }
+
+    #[test]
+    fn the_grammar_keeps_its_markdown() {
+        let rendered = render(
+            "# Greeting\n\nHello **{{name}}**.\n\n- one\n- two\n",
+            &data(),
+        )
+        .unwrap();
+
+        assert!(rendered.html.contains("<h1>Greeting</h1>"));
+        assert!(rendered
+            .html
+            .contains("<p>Hello <strong>Fred Bloggs</strong>.</p>"));
+        assert_eq!(
+            rendered.text,
+            "Greeting\n\nHello Fred Bloggs.\n\n- one\n- two"
+        );
+    }
+
+    #[test]
+    fn headings_and_paragraphs_become_pdf_blocks() {
+        let blocks = to_blocks("# Greeting\n\nHello\nthere.\n\n- one\n- two\n");
+
+        let blocks = blocks
+            .iter()
+            .map(|block| match block {
+                Block::Heading(text) => format!("heading: {}", text),
+                Block::Paragraph(text) => format!("paragraph: {}", text),
+                Block::Signature(name) => format!("signature: {}", name),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            blocks,
+            [
+                "heading: Greeting",
+                "paragraph: Hello there.",
+                "paragraph: - one",
+                "paragraph: - two"
+            ]
+        );
+    }
+}
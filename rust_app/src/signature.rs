@@ -0,0 +1,339 @@
+//
+// Signatures
+//
+// Parties sign with Ed25519 or with ECDSA over P-256 and SHA-256. Each party registers its public
+// key in the contract `{data}` as `publicKey`, base64-encoded: the 32-byte Ed25519 key, or the
+// SEC1-encoded P-256 point (33 bytes compressed, 65 uncompressed). Signatures are base64-encoded
+// too: the 64-byte Ed25519 signature, or the ECDSA signature as 64 bytes (`r || s`) or in DER.
+//
+// Any request may be signed by one of the parties. The signature is sent alongside the request in
+// the request envelope, and is over the canonical JSON (see `hash::canonical_json`) of the
+// contract id, the request, when it was signed and a nonce. A signature is only accepted within
+// `REQUEST_SIGNATURE_WINDOW_SECONDS` of when it was made, and each nonce only once, so a signed
+// request cannot be sent to another contract, or sent again.
+//
+
+use crate::auth::Caller;
+use crate::error::ContractError;
+use crate::hash::{canonical_json, data_hash};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{DateTime, Duration, Utc};
+use lib::org_accordproject_helloworldstate::*;
+use p256::ecdsa::signature::Verifier;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::env;
+
+const DEFAULT_WINDOW_SECONDS: i64 = 5 * 60;
+const MAX_NONCE_LENGTH: usize = 255;
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct RequestSignature {
+    // The `$identifier` of the party that signed the request.
+    pub signer: String,
+
+    pub signature: String,
+
+    // When the request was signed, as an RFC 3339 timestamp. It is signed as sent.
+    #[serde(rename = "signedAt")]
+    pub signed_at: String,
+
+    // A value the signer never uses twice.
+    pub nonce: String,
+}
+
+impl RequestSignature {
+    //
+    // Function nonce_key
+    //
+    // Identifies the signer's nonce without naming the signer.
+    //
+    pub fn nonce_key(&self) -> String {
+        data_hash(&json!([self.signer, self.nonce]))
+    }
+
+    //
+    // Function expires_at
+    //
+    // The first whole second, since the epoch, at which the signature is no longer accepted and its
+    // nonce need no longer be remembered. The signature is still accepted at the very end of its
+    // window, so this is the second after it.
+    //
+    pub fn expires_at(&self) -> Result<i64, ContractError> {
+        Ok((self.signed_at()? + signature_window()).timestamp() + 1)
+    }
+
+    fn signed_at(&self) -> Result<DateTime<Utc>, ContractError> {
+        DateTime::parse_from_rfc3339(&self.signed_at)
+            .map(|signed_at| signed_at.with_timezone(&Utc))
+            .map_err(|_| {
+                ContractError::InvalidSignature(
+                    "signedAt must be an RFC 3339 timestamp".to_string(),
+                )
+            })
+    }
+}
+
+//
+// Function signed_message
+//
+// What a party signs to sign `request` for the contract `contract_id`.
+//
+pub fn signed_message(contract_id: &str, request: &Value, signature: &RequestSignature) -> String {
+    canonical_json(&json!({
+        "contractId": contract_id,
+        "request": request,
+        "signedAt": signature.signed_at,
+        "nonce": signature.nonce,
+    }))
+}
+
+//
+// Function verify_request
+//
+// Checks that `request` was signed for the contract with the `{data}` `data` by the party named in
+// `signature`, recently enough, and that the party is the caller when the caller is authenticated.
+// Whether the nonce was used before is checked when the request is executed (see
+// `utils::add_nonce_record`).
+//
+pub fn verify_request(
+    data: &HelloWorldClause,
+    caller: Option<&Caller>,
+    request: &Value,
+    signature: &RequestSignature,
+    now: DateTime<Utc>,
+) -> Result<(), ContractError> {
+    if let Some(caller) = caller {
+        if caller.subject != signature.signer {
+            return Err(ContractError::InvalidSignature(format!(
+                "the request is signed by {} but was sent by {}",
+                signature.signer, caller.subject
+            )));
+        }
+    }
+
+    let signed_at = signature.signed_at()?;
+    if (now - signed_at).abs() > signature_window() {
+        return Err(ContractError::InvalidSignature(format!(
+            "the request was signed at {}, which is too far from now",
+            signature.signed_at
+        )));
+    }
+    if signature.nonce.is_empty()
+        || signature.nonce.len() > MAX_NONCE_LENGTH
+        || !signature.nonce.chars().all(|c| c.is_ascii_graphic())
+    {
+        return Err(ContractError::InvalidSignature(format!(
+            "the nonce must be 1 to {} printable ASCII characters",
+            MAX_NONCE_LENGTH
+        )));
+    }
+
+    verify_party(
+        data,
+        &signature.signer,
+        signed_message(&data._identifier, request, signature).as_bytes(),
+        &signature.signature,
+    )
+}
+
+//
+// Function signature_window
+//
+// How far from now a request may have been signed, from `REQUEST_SIGNATURE_WINDOW_SECONDS`.
+// Defaults to five minutes.
+//
+pub fn signature_window() -> Duration {
+    let seconds = env::var("REQUEST_SIGNATURE_WINDOW_SECONDS")
+        .map(|value| {
+            value
+                .parse::<i64>()
+                .expect("REQUEST_SIGNATURE_WINDOW_SECONDS must be a whole number of seconds")
+        })
+        .unwrap_or(DEFAULT_WINDOW_SECONDS);
+
+    Duration::seconds(seconds)
+}
+
+//
+// Function verify_party
+//
+// Checks `signature` over `message` against the public key registered for `party`.
+//
+pub fn verify_party(
+    data: &HelloWorldClause,
+    party: &str,
+    message: &[u8],
+    signature: &str,
+) -> Result<(), ContractError> {
+    let public_key = data
+        .parties
+        .iter()
+        .flatten()
+        .find(|p| p._identifier == party)
+        .ok_or_else(|| ContractError::InvalidSignature(format!("{} is not a party", party)))?
+        .public_key
+        .as_deref()
+        .ok_or_else(|| {
+            ContractError::InvalidSignature(format!("{} has no registered public key", party))
+        })?;
+
+    verify(public_key, message, signature)
+}
+
+pub fn verify(public_key: &str, message: &[u8], signature: &str) -> Result<(), ContractError> {
+    let public_key = STANDARD
+        .decode(public_key)
+        .map_err(|_| ContractError::InvalidSignature("the public key is not base64".to_string()))?;
+    let signature = STANDARD
+        .decode(signature)
+        .map_err(|_| ContractError::InvalidSignature("the signature is not base64".to_string()))?;
+
+    let verified = match public_key.len() {
+        32 => {
+            let key = ed25519_dalek::VerifyingKey::from_bytes(
+                public_key.as_slice().try_into().expect("key is 32 bytes"),
+            )
+            .map_err(|e| ContractError::InvalidSignature(e.to_string()))?;
+            let signature = ed25519_dalek::Signature::from_slice(&signature)
+                .map_err(|e| ContractError::InvalidSignature(e.to_string()))?;
+
+            key.verify_strict(message, &signature).is_ok()
+        }
+        33 | 65 => {
+            let key = p256::ecdsa::VerifyingKey::from_sec1_bytes(&public_key)
+                .map_err(|e| ContractError::InvalidSignature(e.to_string()))?;
+            let signature = p256::ecdsa::Signature::from_slice(&signature)
+                .or_else(|_| p256::ecdsa::Signature::from_der(&signature))
+                .map_err(|e| ContractError::InvalidSignature(e.to_string()))?;
+
+            key.verify(message, &signature).is_ok()
+        }
+        length => {
+            return Err(ContractError::InvalidSignature(format!(
+                "unsupported public key of {} bytes",
+                length
+            )))
+        }
+    };
+
+    if verified {
+        Ok(())
+    } else {
+        Err(ContractError::InvalidSignature(
+            "the signature does not match".to_string(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{at, clause, party};
+    use ed25519_dalek::{Signer, SigningKey};
+
+    fn contract(identifier: &str) -> HelloWorldClause {
+        let key = SigningKey::from_bytes(&[1; 32]);
+        let mut signer = party("auth0|fred", &["party"]);
+        signer.public_key = Some(STANDARD.encode(key.verifying_key().as_bytes()));
+
+        let mut contract = clause();
+        contract._identifier = identifier.to_string();
+        contract.parties = Some(vec![signer]);
+        contract
+    }
+
+    fn sign(contract_id: &str, request: &Value, signed_at: &str) -> RequestSignature {
+        let mut signature = RequestSignature {
+            signer: "auth0|fred".to_string(),
+            signature: String::new(),
+            signed_at: signed_at.to_string(),
+            nonce: "4f1c2a".to_string(),
+        };
+        let message = signed_message(contract_id, request, &signature);
+        signature.signature = STANDARD.encode(
+            SigningKey::from_bytes(&[1; 32])
+                .sign(message.as_bytes())
+                .to_bytes(),
+        );
+        signature
+    }
+
+    #[test]
+    fn accepts_a_recent_signature_for_the_contract() {
+        let request = json!({ "$class": "org.accordproject.helloworldstate.MyRequest" });
+        let signature = sign("first", &request, "2024-01-01T00:00:00Z");
+
+        let result = verify_request(
+            &contract("first"),
+            None,
+            &request,
+            &signature,
+            at("2024-01-01T00:01:00Z"),
+        );
+
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn refuses_a_signature_made_for_another_contract() {
+        let request = json!({ "$class": "org.accordproject.helloworldstate.MyRequest" });
+        let signature = sign("first", &request, "2024-01-01T00:00:00Z");
+
+        let result = verify_request(
+            &contract("second"),
+            None,
+            &request,
+            &signature,
+            at("2024-01-01T00:01:00Z"),
+        );
+
+        assert!(matches!(result, Err(ContractError::InvalidSignature(_))));
+    }
+
+    #[test]
+    fn refuses_a_stale_signature() {
+        let request = json!({ "$class": "org.accordproject.helloworldstate.MyRequest" });
+        let signature = sign("first", &request, "2024-01-01T00:00:00Z");
+
+        let result = verify_request(
+            &contract("first"),
+            None,
+            &request,
+            &signature,
+            at("2024-01-01T00:06:00Z"),
+        );
+
+        assert!(matches!(result, Err(ContractError::InvalidSignature(_))));
+    }
+
+    #[test]
+    fn nonces_are_remembered_until_the_signature_expires() {
+        let request = json!({});
+        let signature = sign("first", &request, "2024-01-01T00:00:00Z");
+
+        assert!(!signature.nonce_key().contains("fred"));
+        assert_eq!(
+            signature.expires_at().unwrap(),
+            at("2024-01-01T00:05:01Z").timestamp()
+        );
+    }
+
+    #[test]
+    fn nonces_are_remembered_while_the_signature_is_accepted() {
+        let request = json!({});
+        let signature = sign("first", &request, "2024-01-01T00:00:00.500Z");
+        let verify = |now| verify_request(&contract("first"), None, &request, &signature, now);
+
+        // The last instant the signature is accepted falls within the second before it expires.
+        let last_accepted = at("2024-01-01T00:05:00.500Z");
+        assert!(verify(last_accepted).is_ok());
+        assert!(last_accepted.timestamp() < signature.expires_at().unwrap());
+
+        let expired = DateTime::from_timestamp(signature.expires_at().unwrap(), 0).unwrap();
+        assert!(matches!(
+            verify(expired),
+            Err(ContractError::InvalidSignature(_))
+        ));
+    }
+}
@@ -0,0 +1,272 @@
+//
+// Contract State
+//
+// The `{state}` stored for the contract: the template's own `HelloWorldState`, together with the
+// records the contract keeps about its lifecycle, the versions of its `{data}`, the agreements
+// generated for it and the signatures of its parties. The `HelloWorldState` fields
+// are flattened, so a plain `HelloWorldState` is also a valid `ContractState`.
+//
+
+use crate::amendment::{version_as_of, Amendment, DataVersion};
+use crate::erasure::Erasure;
+use crate::error::ContractError;
+use crate::lifecycle::ContractStatus;
+use crate::recipient::Recipient;
+use crate::redact::Personal;
+use chrono::{DateTime, Utc};
+use lib::org_accordproject_helloworldstate::*;
+use lib::utils::{
+    deserialize_datetime, deserialize_datetime_option, serialize_datetime,
+    serialize_datetime_option,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ContractState {
+    #[serde(flatten)]
+    pub clause: HelloWorldState,
+
+    #[serde(rename = "status", default = "ContractStatus::legacy")]
+    pub status: ContractStatus,
+
+    #[serde(rename = "agreements", default, skip_serializing_if = "Vec::is_empty")]
+    pub agreements: Vec<AgreementJob>,
+
+    #[serde(rename = "amendments", default, skip_serializing_if = "Vec::is_empty")]
+    pub amendments: Vec<Amendment>,
+
+    // Empty until the first amendment is approved.
+    #[serde(
+        rename = "dataVersions",
+        default,
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub data_versions: Vec<DataVersion>,
+
+    #[serde(rename = "signatures", default, skip_serializing_if = "Vec::is_empty")]
+    pub signatures: Vec<PartySignature>,
+
+    // When every party had signed the `{data}`.
+    #[serde(
+        rename = "executedAt",
+        default,
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_datetime_option",
+        deserialize_with = "deserialize_datetime_option"
+    )]
+    pub executed_at: Option<DateTime<Utc>>,
+
+    #[serde(rename = "erasures", default, skip_serializing_if = "Vec::is_empty")]
+    pub erasures: Vec<Erasure>,
+}
+
+impl Personal for ContractState {
+    const PERSONAL_FIELDS: &'static [&'static str] = &[
+        "amendments.proposedBy",
+        "amendments.approvals",
+        "amendments.rejectedBy",
+        "signatures.party",
+        "agreements.notifyTo",
+        "agreements.recipients.email",
+        "agreements.recipients.name",
+        "agreements.requestedBy",
+    ];
+    const DATA_COPIES: &'static [&'static str] = &["amendments.data", "dataVersions.data"];
+}
+
+impl ContractState {
+    pub fn agreement(&self, job_id: &str) -> Option<&AgreementJob> {
+        self.agreements.iter().find(|job| job.job_id == job_id)
+    }
+
+    pub fn agreement_mut(&mut self, job_id: &str) -> Option<&mut AgreementJob> {
+        self.agreements.iter_mut().find(|job| job.job_id == job_id)
+    }
+
+    pub fn amendment_mut(&mut self, amendment_id: &str) -> Option<&mut Amendment> {
+        self.amendments
+            .iter_mut()
+            .find(|amendment| amendment.amendment_id == amendment_id)
+    }
+
+    //
+    // Function has_history
+    //
+    // Whether anything happened to the contract since it was initialised, which initialising it
+    // again would lose.
+    //
+    pub fn has_history(&self) -> bool {
+        self.status != ContractStatus::Draft
+            || self.clause.counter != 0.0
+            || !self.agreements.is_empty()
+            || !self.amendments.is_empty()
+            || !self.data_versions.is_empty()
+            || !self.signatures.is_empty()
+            || !self.erasures.is_empty()
+    }
+
+    //
+    // Function data_as_of
+    //
+    // The `{data}` in effect at `at`. `initial` is the data the contract was initialised with,
+    // which is in effect until an amendment is.
+    //
+    pub fn data_as_of(
+        &self,
+        initial: HelloWorldClause,
+        at: DateTime<Utc>,
+    ) -> Result<HelloWorldClause, ContractError> {
+        Ok(self.amended_data_as_of(at)?.unwrap_or(initial))
+    }
+
+    //
+    // Function amended_data_as_of
+    //
+    // The `{data}` of the version in effect at `at`, or `None` while the data the contract was
+    // initialised with is.
+    //
+    pub fn amended_data_as_of(
+        &self,
+        at: DateTime<Utc>,
+    ) -> Result<Option<HelloWorldClause>, ContractError> {
+        version_as_of(&self.data_versions, at)
+            .map(|version| {
+                serde_json::from_value(version.data.clone())
+                    .map_err(|e| ContractError::InvalidState(e.to_string()))
+            })
+            .transpose()
+    }
+}
+
+//
+// A party's signature over the hash of the `{data}`, recorded by `SignAgreement`.
+//
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PartySignature {
+    #[serde(rename = "party")]
+    pub party: String,
+
+    #[serde(rename = "dataHash")]
+    pub data_hash: String,
+
+    // The version of `hash::contract_data_hash` that computed `data_hash`. Signatures recorded
+    // before hashes were versioned have none, and were made with version 1.
+    #[serde(rename = "hashVersion", default = "first_hash_version")]
+    pub hash_version: u32,
+
+    #[serde(rename = "signature")]
+    pub signature: String,
+
+    #[serde(
+        rename = "signedAt",
+        serialize_with = "serialize_datetime",
+        deserialize_with = "deserialize_datetime"
+    )]
+    pub signed_at: DateTime<Utc>,
+}
+
+fn first_hash_version() -> u32 {
+    1
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AgreementStatus {
+    Queued,
+    Completed,
+    Failed,
+}
+
+//
+// An agreement generation job, from the `GenerateAgreementAsPDFRequest` that queued it to the
+// callback from the generation service reporting its outcome.
+//
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgreementJob {
+    #[serde(rename = "jobId")]
+    pub job_id: String,
+
+    #[serde(rename = "status")]
+    pub status: AgreementStatus,
+
+    // The first party the agreement is sent to.
+    #[serde(rename = "notifyTo")]
+    pub notify_to: String,
+
+    #[serde(rename = "recipients", default, skip_serializing_if = "Vec::is_empty")]
+    pub recipients: Vec<Recipient>,
+
+    // The subject of the caller who asked for the agreement, when authentication is enabled.
+    #[serde(
+        rename = "requestedBy",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub requested_by: Option<String>,
+
+    #[serde(
+        rename = "requestedAt",
+        serialize_with = "serialize_datetime",
+        deserialize_with = "deserialize_datetime"
+    )]
+    pub requested_at: DateTime<Utc>,
+
+    #[serde(
+        rename = "updatedAt",
+        serialize_with = "serialize_datetime",
+        deserialize_with = "deserialize_datetime"
+    )]
+    pub updated_at: DateTime<Utc>,
+
+    #[serde(
+        rename = "documentUrl",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub document_url: Option<String>,
+
+    #[serde(
+        rename = "documentHash",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub document_hash: Option<String>,
+
+    #[serde(rename = "error", default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl AgreementJob {
+    // Jobs recorded before recipients were introduced only have `notifyTo`.
+    pub fn recipients(&self) -> Vec<Recipient> {
+        if self.recipients.is_empty() {
+            vec![Recipient::party(&self.notify_to)]
+        } else {
+            self.recipients.clone()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{at, state};
+
+    #[test]
+    fn only_an_untouched_state_has_no_history() {
+        assert!(!state(ContractStatus::Draft).has_history());
+        assert!(state(ContractStatus::Active).has_history());
+
+        let mut erased = state(ContractStatus::Draft);
+        erased.erasures.push(Erasure {
+            erasure_id: "erasure".to_string(),
+            erased_at: at("2024-01-01T00:00:00Z"),
+            requested_by: None,
+            reason: None,
+            tombstones: vec![],
+            fields: 0,
+            proofs: vec![],
+        });
+        assert!(erased.has_history());
+    }
+}
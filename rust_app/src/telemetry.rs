@@ -0,0 +1,244 @@
+//
+// Tracing
+//
+// The spans of an invocation can be exported with OpenTelemetry, to see where the time went: the
+// `invocation` span, a client span around every DynamoDB call (see `metrics::store_call`) and one
+// around every call to the generation service. Calls to `GENERATE_AGREEMENT_URL` carry the W3C
+// `traceparent` of their span, so the service's own spans join the same trace.
+//
+// `TRACES` chooses the exporter:
+//
+// - `off`, the default: no spans are exported.
+// - `stdout`: every span is written to the logs as a JSON object, for local debugging.
+// - `otlp`: spans are sent over OTLP/HTTP to `OTEL_EXPORTER_OTLP_TRACES_ENDPOINT`, or to
+//   `OTEL_EXPORTER_OTLP_ENDPOINT` followed by `/v1/traces`, by default the collector at
+//   `http://localhost:4318`, such as the AWS Distro for OpenTelemetry Lambda layer.
+//
+// The spans are exported at the end of every invocation, before the function is frozen. Only the
+// function's own spans are exported, without log events, so traces hold no contract data.
+//
+
+use crate::redact;
+use chrono::{DateTime, SecondsFormat, Utc};
+use opentelemetry::trace::{Status, TracerProvider as _};
+use opentelemetry::{global, KeyValue};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::export::trace::{ExportResult, SpanData, SpanExporter};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{Config, Tracer, TracerProvider};
+use opentelemetry_sdk::{runtime, Resource};
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::env;
+use std::future::Future;
+use std::io::{self, Write};
+use std::pin::Pin;
+use std::sync::OnceLock;
+use tracing::field::Empty;
+use tracing::{Span, Subscriber};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::filter::filter_fn;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+const DEFAULT_SERVICE_NAME: &str = "contract-hello-world-state";
+const DEFAULT_OTLP_ENDPOINT: &str = "http://localhost:4318";
+
+static PROVIDER: OnceLock<TracerProvider> = OnceLock::new();
+
+//
+// Function from_env
+//
+// Sets up the exporter chosen by `TRACES`, returning the tracer for `layer`, or `None` when spans
+// are not exported. The service is named by `OTEL_SERVICE_NAME`, or the function name.
+//
+pub fn from_env() -> Option<Tracer> {
+    let builder = match env::var("TRACES").as_deref() {
+        Ok("off") | Err(_) => return None,
+        Ok("stdout") => TracerProvider::builder().with_simple_exporter(StdoutExporter),
+        Ok("otlp") => {
+            TracerProvider::builder().with_batch_exporter(otlp_exporter(), runtime::Tokio)
+        }
+        Ok(other) => panic!("TRACES must be off, stdout or otlp, not {}", other),
+    };
+
+    let service_name = env::var("OTEL_SERVICE_NAME")
+        .or_else(|_| env::var("AWS_LAMBDA_FUNCTION_NAME"))
+        .unwrap_or_else(|_| DEFAULT_SERVICE_NAME.to_string());
+    let provider = builder
+        .with_config(
+            Config::default().with_resource(Resource::new(vec![KeyValue::new(
+                "service.name",
+                service_name,
+            )])),
+        )
+        .build();
+
+    let tracer = provider.tracer(DEFAULT_SERVICE_NAME);
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    if PROVIDER.set(provider).is_err() {
+        panic!("the tracer provider is already set");
+    }
+
+    Some(tracer)
+}
+
+fn otlp_exporter() -> opentelemetry_otlp::SpanExporter {
+    let endpoint = env::var("OTEL_EXPORTER_OTLP_TRACES_ENDPOINT").unwrap_or_else(|_| {
+        let base = env::var("OTEL_EXPORTER_OTLP_ENDPOINT")
+            .unwrap_or_else(|_| DEFAULT_OTLP_ENDPOINT.to_string());
+        format!("{}/v1/traces", base.trim_end_matches('/'))
+    });
+
+    opentelemetry_otlp::SpanExporterBuilder::from(
+        opentelemetry_otlp::new_exporter()
+            .http()
+            .with_env()
+            .with_endpoint(endpoint),
+    )
+    .build_span_exporter()
+    .expect("failed to build the OTLP exporter")
+}
+
+//
+// Function layer
+//
+// Exports the function's own spans through `tracer`.
+//
+pub fn layer<S>(tracer: Tracer) -> impl Layer<S>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    let crate_name = module_path!().split("::").next().unwrap_or_default();
+    tracing_opentelemetry::layer()
+        .with_tracer(tracer)
+        .with_threads(false)
+        .with_tracked_inactivity(false)
+        .with_filter(filter_fn(move |metadata| {
+            metadata.is_span() && metadata.target().starts_with(crate_name)
+        }))
+}
+
+// Exports the spans of the invocation.
+pub async fn flush() {
+    let Some(provider) = PROVIDER.get() else {
+        return;
+    };
+
+    // Flushing blocks until the spans are exported, which needs the runtime.
+    if let Ok(results) = tokio::task::spawn_blocking(|| provider.force_flush()).await {
+        for result in results {
+            if let Err(e) = result {
+                tracing::warn!(error = %e, "failed to export the spans");
+            }
+        }
+    }
+}
+
+//
+// Function store_span
+//
+// The span of a DynamoDB `operation`.
+//
+pub fn store_span(operation: &'static str) -> Span {
+    tracing::info_span!(
+        "dynamodb",
+        otel.name = %format!("DynamoDB.{}", operation),
+        otel.kind = "client",
+        otel.status_code = Empty,
+        db.system = "dynamodb",
+        db.operation = operation,
+        aws.dynamodb.table_names = %env::var("TABLE_NAME").unwrap_or_default(),
+    )
+}
+
+//
+// Function http_span
+//
+// The span of an HTTP request to `url`. The status code is recorded with `record_status`.
+//
+pub fn http_span(method: &'static str, url: &str) -> Span {
+    tracing::info_span!(
+        "http",
+        otel.name = method,
+        otel.kind = "client",
+        otel.status_code = Empty,
+        http.method = method,
+        http.url = %url,
+        http.status_code = Empty,
+    )
+}
+
+pub fn record_status(span: &Span, status: u16) {
+    span.record("http.status_code", status);
+}
+
+// Marks `span` as failed.
+pub fn record_error(span: &Span) {
+    span.record("otel.status_code", "ERROR");
+}
+
+//
+// Function trace_headers
+//
+// The W3C trace context headers of the current span, to send with a request. Empty when spans are
+// not exported.
+//
+pub fn trace_headers() -> HashMap<String, String> {
+    let mut headers = HashMap::new();
+    let context = Span::current().context();
+    global::get_text_map_propagator(|propagator| propagator.inject_context(&context, &mut headers));
+    headers.retain(|_, value| !value.is_empty());
+    headers
+}
+
+//
+// Writes every span to stdout as a JSON object, masked like the logs.
+//
+#[derive(Debug)]
+struct StdoutExporter;
+
+impl SpanExporter for StdoutExporter {
+    fn export(
+        &mut self,
+        batch: Vec<SpanData>,
+    ) -> Pin<Box<dyn Future<Output = ExportResult> + Send>> {
+        let mut stdout = io::stdout().lock();
+        for span in batch {
+            let line = redact::mask(&span_json(&span).to_string());
+            let _ = writeln!(stdout, "{}", line);
+        }
+        Box::pin(async { Ok(()) })
+    }
+}
+
+fn span_json(span: &SpanData) -> Value {
+    let attributes = span
+        .attributes
+        .iter()
+        .map(|(key, value)| (key.to_string(), json!(value.as_str())))
+        .collect::<Map<_, _>>();
+    let duration_ms = span
+        .end_time
+        .duration_since(span.start_time)
+        .unwrap_or_default()
+        .as_secs_f64()
+        * 1000.0;
+    let status = match &span.status {
+        Status::Unset => "UNSET",
+        Status::Ok => "OK",
+        Status::Error { .. } => "ERROR",
+    };
+
+    json!({
+        "traceId": span.span_context.trace_id().to_string(),
+        "spanId": span.span_context.span_id().to_string(),
+        "parentSpanId": span.parent_span_id.to_string(),
+        "name": span.name,
+        "kind": format!("{:?}", span.span_kind),
+        "startTime": DateTime::<Utc>::from(span.start_time).to_rfc3339_opts(SecondsFormat::Millis, true),
+        "durationMs": duration_ms,
+        "status": status,
+        "attributes": attributes,
+    })
+}
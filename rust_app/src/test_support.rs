@@ -0,0 +1,54 @@
+//
+// Test Support
+//
+// The contract `{data}` and `{state}` the unit tests start from, and the registry they send
+// requests to.
+//
+
+use crate::handlers::{registry as registry_from_env, Registry};
+use crate::lifecycle::ContractStatus;
+use crate::state::ContractState;
+use crate::{initial_state, HELLO_WORLD_CLAUSE_CLASS};
+use chrono::{DateTime, Utc};
+use lib::org_accordproject_helloworldstate::*;
+use std::env;
+
+pub const CONTRACT_ID: &str = "8d16efc9-96af-458e-b7f2-e3367403d37e";
+
+pub const TEMPLATE_NAME: &str = "hello-world-state@0.15.0.cta";
+
+pub fn registry() -> Registry {
+    env::set_var("TEMPLATE_NAME", TEMPLATE_NAME);
+    registry_from_env()
+}
+
+pub fn clause() -> HelloWorldClause {
+    HelloWorldClause {
+        _class: HELLO_WORLD_CLAUSE_CLASS.to_string(),
+        name: "Fred Bloggs".to_string(),
+        parties: None,
+        clause_id: CONTRACT_ID.to_string(),
+        _identifier: CONTRACT_ID.to_string(),
+    }
+}
+
+pub fn party(identifier: &str, roles: &[&str]) -> Party {
+    Party {
+        _class: "org.accordproject.helloworldstate.Party".to_string(),
+        roles: roles.iter().map(|role| role.to_string()).collect(),
+        public_key: None,
+        _identifier: identifier.to_string(),
+    }
+}
+
+pub fn state(status: ContractStatus) -> ContractState {
+    let mut state = initial_state(&clause());
+    state.status = status;
+    state
+}
+
+pub fn at(timestamp: &str) -> DateTime<Utc> {
+    DateTime::parse_from_rfc3339(timestamp)
+        .expect("test timestamps are RFC 3339")
+        .with_timezone(&Utc)
+}
@@ -1,136 +1,742 @@
 // mod.rs
 
-use aws_sdk_dynamodb::{error::SdkError, types::AttributeValue, types::ReturnValue, Client};
+use crate::attribute_value::{from_item, to_attribute_value, to_item, AttributeValueError};
+use crate::encryption::{decrypt_record, encrypt_record, FieldEncryption, ENVELOPE_FIELD};
+use crate::error::ContractError;
+use crate::idempotency::IdempotencyRecord;
+use crate::metrics::store_call;
+use crate::outbox::{OutboxMessage, OutboxStatus};
+use crate::redact::Personal;
+use crate::signature::RequestSignature;
+use crate::state::ContractState;
+use aws_sdk_dynamodb::{
+    error::SdkError,
+    types::{AttributeValue, Put, TransactWriteItem},
+    Client,
+};
+use chrono::{DateTime, SecondsFormat, Utc};
 use lib::org_accordproject_helloworldstate::*;
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
 use std::{collections::HashMap, env};
 
+pub async fn add_data_to_database(
+    hello_world_clause: &HelloWorldClause,
+    encryption: Option<&FieldEncryption>,
+) -> Result<(), ContractError> {
+    // Initialize the DynamoDB client.
+    let config = aws_config::load_from_env().await;
+    let dynamodb_client = Client::new(&config);
+    let table_name = env::var("TABLE_NAME").expect("TABLE_NAME must be set");
+
+    // Add the "data" to the database.
+    let item = data_item(hello_world_clause, encryption).await?;
+
+    store_call(
+        "PutItem",
+        dynamodb_client
+            .put_item()
+            .table_name(&table_name)
+            .set_item(Some(item))
+            .send(),
+    )
+    .await?;
+
+    tracing::info!(
+        class = %hello_world_clause._class,
+        identifier = %hello_world_clause._identifier,
+        "saved the contract data"
+    );
+
+    Ok(())
+}
+
+// The item holding the `{data}`, with its personal fields encrypted.
+async fn data_item(
+    hello_world_clause: &HelloWorldClause,
+    encryption: Option<&FieldEncryption>,
+) -> Result<HashMap<String, AttributeValue>, ContractError> {
+    let mut record = serde_json::to_value(hello_world_clause).map_err(AttributeValueError::from)?;
+    encrypt_record(
+        encryption,
+        &mut record,
+        &HelloWorldClause::personal_fields(),
+    )
+    .await?;
+    let mut item = to_item(&record)?;
+    item.insert("id".to_string(), AttributeValue::S("data".to_string()));
+
+    Ok(item)
+}
+
 //
-// Function increment_counter
+// Function add_state_to_database
 //
-// Finds the current `counter` value in the `state` object in DynamoDB, increments it by `1` and returns the result.
+// Unconditionally writes the `{state}` of the agreement, replacing any previous state.
+// Used by the constructor; clause functions use `save_state`.
 //
-pub async fn increment_counter() -> Result<i64, Box<dyn std::error::Error>> {
+pub async fn add_state_to_database(
+    state: &ContractState,
+    encryption: Option<&FieldEncryption>,
+) -> Result<(), ContractError> {
     // Initialize the DynamoDB client.
     let config = aws_config::load_from_env().await;
+    let dynamodb_client = Client::new(&config);
     let table_name = env::var("TABLE_NAME").expect("TABLE_NAME must be set");
+
+    let mut record = serde_json::to_value(state).map_err(AttributeValueError::from)?;
+    encrypt_record(encryption, &mut record, &ContractState::personal_fields()).await?;
+    let mut item = to_item(&record)?;
+    item.insert("id".to_string(), AttributeValue::S("state".to_string()));
+    item.insert("version".to_string(), AttributeValue::N("0".to_string()));
+
+    store_call(
+        "PutItem",
+        dynamodb_client
+            .put_item()
+            .table_name(&table_name)
+            .set_item(Some(item))
+            .send(),
+    )
+    .await?;
+
+    tracing::info!(
+        identifier = %state.clause._identifier,
+        counter = state.clause.counter,
+        "saved the initial state"
+    );
+
+    Ok(())
+}
+
+//
+// Function save_state
+//
+// Writes the new `{state}` returned by a clause function, together with the messages it placed in
+// the outbox and the idempotency record of the request. The stored state carries a `version` that
+// is incremented on every write, and the write only succeeds if the stored version still equals
+// `previous_version`, so concurrent requests cannot overwrite each other. A new `{data}`, such as
+// the data left after an erasure, replaces the stored one. Everything is written in one
+// transaction, so either all of it is saved or none of it is.
+//
+pub async fn save_state(
+    state: &ContractState,
+    previous_version: u64,
+    outbox: &[OutboxMessage],
+    idempotency: Option<&IdempotencyRecord>,
+    data: Option<&HelloWorldClause>,
+    encryption: Option<&FieldEncryption>,
+) -> Result<(), ContractError> {
+    // Initialize the DynamoDB client.
+    let config = aws_config::load_from_env().await;
     let dynamodb_client = Client::new(&config);
+    let table_name = env::var("TABLE_NAME").expect("TABLE_NAME must be set");
+
+    let mut record = serde_json::to_value(state).map_err(AttributeValueError::from)?;
+    encrypt_record(encryption, &mut record, &ContractState::personal_fields()).await?;
+    let mut item = to_item(&record)?;
+    item.insert("id".to_string(), AttributeValue::S("state".to_string()));
+    item.insert(
+        "version".to_string(),
+        AttributeValue::N((previous_version + 1).to_string()),
+    );
 
-    // Increment the `counter` by `1`.
-    let result = dynamodb_client
-        .update_item()
+    let state_put = Put::builder()
         .table_name(&table_name)
-        .key("id", AttributeValue::S("state".to_string()))
-        .update_expression("SET #c = #c + :inc")
-        .expression_attribute_values(":inc", AttributeValue::N("1".to_string()))
-        .expression_attribute_names("#c", "counter")
-        .return_values(ReturnValue::UpdatedNew)
-        .send()
+        .set_item(Some(item))
+        .condition_expression("attribute_not_exists(#v) OR #v = :previous")
+        .expression_attribute_names("#v", "version")
+        .expression_attribute_values(":previous", AttributeValue::N(previous_version.to_string()))
+        .build();
+    let mut transaction = dynamodb_client
+        .transact_write_items()
+        .transact_items(TransactWriteItem::builder().put(state_put).build());
+
+    if let Some(data) = data {
+        let data_put = Put::builder()
+            .table_name(&table_name)
+            .set_item(Some(data_item(data, encryption).await?))
+            .build();
+        transaction =
+            transaction.transact_items(TransactWriteItem::builder().put(data_put).build());
+    }
+
+    // A message is only ever recorded once.
+    for message in outbox {
+        let mut record = serde_json::to_value(message).map_err(AttributeValueError::from)?;
+        encrypt_record(encryption, &mut record, &OutboxMessage::personal_fields()).await?;
+        let mut item = to_item(&record)?;
+        item.insert(
+            "id".to_string(),
+            AttributeValue::S(outbox_key(&message.dedup_key)),
+        );
+
+        let message_put = Put::builder()
+            .table_name(&table_name)
+            .set_item(Some(item))
+            .condition_expression("attribute_not_exists(id)")
+            .build();
+        transaction =
+            transaction.transact_items(TransactWriteItem::builder().put(message_put).build());
+    }
+
+    // Two requests with the same key cannot both be saved, unless the first has expired.
+    if let Some(record) = idempotency {
+        let mut item = encrypted_idempotency_item(record, encryption).await?;
+        item.insert(
+            "id".to_string(),
+            AttributeValue::S(idempotency_key(&record.key)),
+        );
+
+        let record_put = Put::builder()
+            .table_name(&table_name)
+            .set_item(Some(item))
+            .condition_expression("attribute_not_exists(id) OR expiresAt <= :now")
+            .expression_attribute_values(
+                ":now",
+                AttributeValue::N(record.created_at.timestamp().to_string()),
+            )
+            .build();
+        transaction =
+            transaction.transact_items(TransactWriteItem::builder().put(record_put).build());
+    }
+
+    store_call("TransactWriteItems", transaction.send())
         .await
-        .map_err(|e| {
-            println!("Error during update_item: {:?}", e);
-            Box::<dyn std::error::Error>::from(format!("{:?}", e))
+        .map_err(|e| match &e {
+            SdkError::ServiceError(context)
+                if context.err().is_transaction_canceled_exception() =>
+            {
+                ContractError::ConcurrentModification
+            }
+            _ => ContractError::from(e),
         })?;
 
-    // Get the latest `counter`
-    let attributes = result.attributes.ok_or("No attributes returned")?;
-    let new_counter = match attributes.get("counter") {
-        Some(av) => match av {
-            AttributeValue::N(s) => s.parse::<i64>().unwrap_or_default(),
-            _ => return Err("counter is not a number".into()),
-        },
-        None => return Err("counter attribute missing".into()),
-    };
+    tracing::info!(
+        counter = state.clause.counter,
+        version = previous_version + 1,
+        outbox = outbox.len(),
+        "saved the state"
+    );
 
-    println!("Successfully incremented the counter to: {}", new_counter);
+    Ok(())
+}
 
-    Ok(new_counter)
+fn outbox_key(dedup_key: &str) -> String {
+    format!("outbox#{}", dedup_key)
 }
 
-pub async fn add_data_to_database(
-    hello_world_clause: &HelloWorldClause,
-) -> Result<(), SdkError<aws_sdk_dynamodb::operation::put_item::PutItemError>> {
+fn idempotency_key(key: &str) -> String {
+    format!("idempotency#{}", key)
+}
+
+async fn encrypted_idempotency_item(
+    record: &IdempotencyRecord,
+    encryption: Option<&FieldEncryption>,
+) -> Result<HashMap<String, AttributeValue>, ContractError> {
+    let mut record = serde_json::to_value(record).map_err(AttributeValueError::from)?;
+    encrypt_record(
+        encryption,
+        &mut record,
+        &IdempotencyRecord::personal_fields(),
+    )
+    .await?;
+
+    Ok(to_item(&record)?)
+}
+
+//
+// Function encrypted_update
+//
+// The update expression and values setting the field `name` of a stored record to its value in
+// the encrypted `record`. The data key the field was encrypted with replaces the stored one, so
+// the other fields of the record must hold no encrypted values.
+//
+fn encrypted_update(record: &Value, name: &str) -> (&'static str, HashMap<String, AttributeValue>) {
+    let mut values = HashMap::from([(
+        ":value".to_string(),
+        to_attribute_value(record.get(name).cloned().unwrap_or(Value::Null)),
+    )]);
+
+    match record.get(ENVELOPE_FIELD) {
+        Some(envelope) => {
+            values.insert(
+                ":envelope".to_string(),
+                to_attribute_value(envelope.clone()),
+            );
+            ("SET #f = :value, #e = :envelope", values)
+        }
+        None => ("SET #f = :value REMOVE #e", values),
+    }
+}
+
+//
+// Function decrypt_item
+//
+// Deserializes a stored item into `T`, decrypting its encrypted fields.
+//
+async fn decrypt_item<T: DeserializeOwned>(
+    item: HashMap<String, AttributeValue>,
+    encryption: Option<&FieldEncryption>,
+) -> Result<T, ContractError> {
+    let mut record: Value = from_item(item)?;
+    decrypt_record(encryption, &mut record).await?;
+
+    Ok(serde_json::from_value(record).map_err(AttributeValueError::from)?)
+}
+
+//
+// Function load_idempotency_record
+//
+// Gets the stored response for an idempotency key.
+//
+pub async fn load_idempotency_record(
+    key: &str,
+    encryption: Option<&FieldEncryption>,
+) -> Result<Option<IdempotencyRecord>, ContractError> {
+    match get_data(&idempotency_key(key)).await? {
+        Some(item) => Ok(Some(decrypt_item(item, encryption).await?)),
+        None => Ok(None),
+    }
+}
+
+//
+// Function add_idempotency_record
+//
+// Unconditionally writes the idempotency record of a request that does not save a `{state}`
+// through `save_state`, such as the constructor.
+//
+pub async fn add_idempotency_record(
+    record: &IdempotencyRecord,
+    encryption: Option<&FieldEncryption>,
+) -> Result<(), ContractError> {
     // Initialize the DynamoDB client.
     let config = aws_config::load_from_env().await;
     let dynamodb_client = Client::new(&config);
     let table_name = env::var("TABLE_NAME").expect("TABLE_NAME must be set");
 
-    // Add the "data" to the database.
-    dynamodb_client
-        .put_item()
-        .table_name(&table_name)
-        .item("id", AttributeValue::S("data".to_string()))
-        .item(
-            "_identifier",
-            AttributeValue::S(hello_world_clause._identifier.clone()),
-        )
-        .item(
-            "clause_id",
-            AttributeValue::S(hello_world_clause.clause_id.clone()),
-        )
-        .item(
-            "_class",
-            AttributeValue::S(hello_world_clause._class.clone()),
-        )
-        .item("name", AttributeValue::S(hello_world_clause.name.clone()))
-        .send()
-        .await?;
-
-    println!("Successfully saved 'data' to DynamoDB: _class: {}, clause_id: {}, _identifier: {}, name: {}", hello_world_clause._class, hello_world_clause.clause_id, hello_world_clause._identifier, hello_world_clause.name);
+    let mut item = encrypted_idempotency_item(record, encryption).await?;
+    item.insert(
+        "id".to_string(),
+        AttributeValue::S(idempotency_key(&record.key)),
+    );
+
+    store_call(
+        "PutItem",
+        dynamodb_client
+            .put_item()
+            .table_name(&table_name)
+            .set_item(Some(item))
+            .send(),
+    )
+    .await?;
 
     Ok(())
 }
 
-pub async fn add_state_to_database(
-    hello_world_clause: &HelloWorldClause,
-) -> Result<(), SdkError<aws_sdk_dynamodb::operation::put_item::PutItemError>> {
+//
+// Function add_nonce_record
+//
+// Records that the nonce of a request signature was used, until the signature expires. Fails with
+// `InvalidSignature` if it was used before.
+//
+pub async fn add_nonce_record(
+    signature: &RequestSignature,
+    now: DateTime<Utc>,
+) -> Result<(), ContractError> {
     // Initialize the DynamoDB client.
     let config = aws_config::load_from_env().await;
     let dynamodb_client = Client::new(&config);
     let table_name = env::var("TABLE_NAME").expect("TABLE_NAME must be set");
 
-    dynamodb_client
-        .put_item()
-        .table_name(&table_name)
-        .item("id", AttributeValue::S("state".to_string()))
-        .item(
-            "_identifier",
-            AttributeValue::S(hello_world_clause._identifier.clone()),
-        )
-        .item("counter", AttributeValue::N("0".to_string()))
-        .item(
-            "_class",
-            AttributeValue::S(hello_world_clause._class.clone()),
-        )
-        .send()
-        .await?;
-
-    println!(
-        "Successfully saved state to DynamoDB: _class: {}, _identifier: {}, counter: 0",
-        hello_world_clause._class, hello_world_clause._identifier
-    );
+    let result = store_call(
+        "PutItem",
+        dynamodb_client
+            .put_item()
+            .table_name(&table_name)
+            .item(
+                "id",
+                AttributeValue::S(format!("nonce#{}", signature.nonce_key())),
+            )
+            .item(
+                "expiresAt",
+                AttributeValue::N(signature.expires_at()?.to_string()),
+            )
+            .condition_expression("attribute_not_exists(id) OR expiresAt <= :now")
+            .expression_attribute_values(":now", AttributeValue::N(now.timestamp().to_string()))
+            .send(),
+    )
+    .await;
+
+    match result {
+        Ok(_) => Ok(()),
+        Err(SdkError::ServiceError(context))
+            if context.err().is_conditional_check_failed_exception() =>
+        {
+            Err(ContractError::InvalidSignature(
+                "the nonce was already used".to_string(),
+            ))
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+//
+// Function load_idempotency_records
+//
+// Gets every stored idempotency record, including expired ones DynamoDB has not deleted yet.
+//
+pub async fn load_idempotency_records(
+    encryption: Option<&FieldEncryption>,
+) -> Result<Vec<IdempotencyRecord>, ContractError> {
+    // Initialize the DynamoDB client.
+    let config = aws_config::load_from_env().await;
+    let dynamodb_client = Client::new(&config);
+    let table_name = env::var("TABLE_NAME").expect("TABLE_NAME must be set");
+
+    let mut records = vec![];
+    let mut start_key = None;
+
+    loop {
+        let scan = dynamodb_client
+            .scan()
+            .table_name(&table_name)
+            .filter_expression("begins_with(id, :prefix)")
+            .expression_attribute_values(":prefix", AttributeValue::S(idempotency_key("")))
+            .set_exclusive_start_key(start_key);
+        let output = store_call("Scan", scan.send()).await?;
+
+        for item in output.items.unwrap_or_default() {
+            records.push(decrypt_item(item, encryption).await?);
+        }
+
+        start_key = output.last_evaluated_key;
+        if start_key.is_none() {
+            break;
+        }
+    }
+
+    Ok(records)
+}
+
+//
+// Function replace_idempotency_response
+//
+// Replaces the stored response of an idempotency record, leaving when it expires as it is. Used
+// to erase personal data from responses.
+//
+pub async fn replace_idempotency_response(
+    record: &IdempotencyRecord,
+    encryption: Option<&FieldEncryption>,
+) -> Result<(), ContractError> {
+    // Initialize the DynamoDB client.
+    let config = aws_config::load_from_env().await;
+    let dynamodb_client = Client::new(&config);
+    let table_name = env::var("TABLE_NAME").expect("TABLE_NAME must be set");
+
+    let mut response = json!({ "response": record.response });
+    encrypt_record(
+        encryption,
+        &mut response,
+        &IdempotencyRecord::personal_fields(),
+    )
+    .await?;
+    let (update, values) = encrypted_update(&response, "response");
+
+    let result = store_call(
+        "UpdateItem",
+        dynamodb_client
+            .update_item()
+            .table_name(&table_name)
+            .key("id", AttributeValue::S(idempotency_key(&record.key)))
+            .update_expression(update)
+            .condition_expression("attribute_exists(id)")
+            .expression_attribute_names("#f", "response")
+            .expression_attribute_names("#e", ENVELOPE_FIELD)
+            .set_expression_attribute_values(Some(values))
+            .send(),
+    )
+    .await;
+
+    // A record that expired and was deleted in the meantime has nothing left to erase.
+    match result {
+        Ok(_) => Ok(()),
+        Err(SdkError::ServiceError(context))
+            if context.err().is_conditional_check_failed_exception() =>
+        {
+            Ok(())
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+//
+// Function load_pending_outbox
+//
+// Gets every outbox message that has not been sent yet, oldest first.
+//
+pub async fn load_pending_outbox(
+    encryption: Option<&FieldEncryption>,
+) -> Result<Vec<OutboxMessage>, ContractError> {
+    scan_outbox(Some(OutboxStatus::Pending), encryption).await
+}
+
+//
+// Function load_outbox
+//
+// Gets every outbox message, whatever its status, oldest first.
+//
+pub async fn load_outbox(
+    encryption: Option<&FieldEncryption>,
+) -> Result<Vec<OutboxMessage>, ContractError> {
+    scan_outbox(None, encryption).await
+}
+
+async fn scan_outbox(
+    status: Option<OutboxStatus>,
+    encryption: Option<&FieldEncryption>,
+) -> Result<Vec<OutboxMessage>, ContractError> {
+    // Initialize the DynamoDB client.
+    let config = aws_config::load_from_env().await;
+    let dynamodb_client = Client::new(&config);
+    let table_name = env::var("TABLE_NAME").expect("TABLE_NAME must be set");
+
+    let mut messages: Vec<OutboxMessage> = vec![];
+    let mut start_key = None;
+
+    loop {
+        let mut scan = dynamodb_client
+            .scan()
+            .table_name(&table_name)
+            .expression_attribute_values(":prefix", AttributeValue::S(outbox_key("")))
+            .set_exclusive_start_key(start_key);
+        scan = match status {
+            Some(status) => scan
+                .filter_expression("begins_with(id, :prefix) AND #s = :status")
+                .expression_attribute_names("#s", "status")
+                .expression_attribute_values(":status", to_attribute_value(json!(status))),
+            None => scan.filter_expression("begins_with(id, :prefix)"),
+        };
+        let output = store_call("Scan", scan.send()).await?;
+
+        for item in output.items.unwrap_or_default() {
+            messages.push(decrypt_item(item, encryption).await?);
+        }
+
+        start_key = output.last_evaluated_key;
+        if start_key.is_none() {
+            break;
+        }
+    }
+
+    messages.sort_by_key(|message| message.created_at);
+    Ok(messages)
+}
+
+//
+// Function replace_outbox_payload
+//
+// Replaces what an outbox message sends, leaving its status and attempts as they are. Used to
+// erase personal data from messages.
+//
+pub async fn replace_outbox_payload(
+    message: &OutboxMessage,
+    encryption: Option<&FieldEncryption>,
+) -> Result<(), ContractError> {
+    // Initialize the DynamoDB client.
+    let config = aws_config::load_from_env().await;
+    let dynamodb_client = Client::new(&config);
+    let table_name = env::var("TABLE_NAME").expect("TABLE_NAME must be set");
+
+    let mut record = serde_json::to_value(message).map_err(AttributeValueError::from)?;
+    encrypt_record(encryption, &mut record, &OutboxMessage::personal_fields()).await?;
+    let (update, values) = encrypted_update(&record, "message");
+
+    store_call(
+        "UpdateItem",
+        dynamodb_client
+            .update_item()
+            .table_name(&table_name)
+            .key("id", AttributeValue::S(outbox_key(&message.dedup_key)))
+            .update_expression(update)
+            .condition_expression("attribute_exists(id)")
+            .expression_attribute_names("#f", "message")
+            .expression_attribute_names("#e", ENVELOPE_FIELD)
+            .set_expression_attribute_values(Some(values))
+            .send(),
+    )
+    .await?;
 
     Ok(())
 }
 
+//
+// Function mark_outbox_sent
+//
+// Records that the message was sent. A message that was already sent by another dispatcher is
+// left as it is.
+//
+pub async fn mark_outbox_sent(dedup_key: &str, now: DateTime<Utc>) -> Result<(), ContractError> {
+    // Initialize the DynamoDB client.
+    let config = aws_config::load_from_env().await;
+    let dynamodb_client = Client::new(&config);
+    let table_name = env::var("TABLE_NAME").expect("TABLE_NAME must be set");
+
+    let result = store_call(
+        "UpdateItem",
+        dynamodb_client
+            .update_item()
+            .table_name(&table_name)
+            .key("id", AttributeValue::S(outbox_key(dedup_key)))
+            .update_expression("SET #s = :sent, sentAt = :now")
+            .condition_expression("#s = :pending")
+            .expression_attribute_names("#s", "status")
+            .expression_attribute_values(":sent", to_attribute_value(json!(OutboxStatus::Sent)))
+            .expression_attribute_values(
+                ":pending",
+                to_attribute_value(json!(OutboxStatus::Pending)),
+            )
+            .expression_attribute_values(
+                ":now",
+                AttributeValue::S(now.to_rfc3339_opts(SecondsFormat::Millis, true)),
+            )
+            .send(),
+    )
+    .await;
+
+    match result {
+        Ok(_) => Ok(()),
+        Err(SdkError::ServiceError(context))
+            if context.err().is_conditional_check_failed_exception() =>
+        {
+            Ok(())
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+//
+// Function record_outbox_failure
+//
+// Records a failed attempt to send the message, and the channels it was delivered through. After
+// `max_attempts` attempts the message is marked as failed and is not tried again. Returns the new
+// status of the message.
+//
+pub async fn record_outbox_failure(
+    message: &OutboxMessage,
+    error: &str,
+    max_attempts: u32,
+) -> Result<OutboxStatus, ContractError> {
+    // Initialize the DynamoDB client.
+    let config = aws_config::load_from_env().await;
+    let dynamodb_client = Client::new(&config);
+    let table_name = env::var("TABLE_NAME").expect("TABLE_NAME must be set");
+
+    let attempts = message.attempts + 1;
+    let status = if attempts >= max_attempts {
+        OutboxStatus::Failed
+    } else {
+        OutboxStatus::Pending
+    };
+
+    store_call(
+        "UpdateItem",
+        dynamodb_client
+            .update_item()
+            .table_name(&table_name)
+            .key("id", AttributeValue::S(outbox_key(&message.dedup_key)))
+            .update_expression(
+                "SET #s = :status, attempts = :attempts, lastError = :error, deliveredVia = :via",
+            )
+            .condition_expression("#s = :pending")
+            .expression_attribute_names("#s", "status")
+            .expression_attribute_values(":status", to_attribute_value(json!(status)))
+            .expression_attribute_values(
+                ":pending",
+                to_attribute_value(json!(OutboxStatus::Pending)),
+            )
+            .expression_attribute_values(":attempts", AttributeValue::N(attempts.to_string()))
+            .expression_attribute_values(":error", AttributeValue::S(error.to_string()))
+            .expression_attribute_values(":via", to_attribute_value(json!(message.delivered_via)))
+            .send(),
+    )
+    .await?;
+
+    Ok(status)
+}
+
+//
+// Function load_data
+//
+// Gets the `{data}` of the agreement, decrypting its encrypted fields.
+//
+pub async fn load_data(
+    encryption: Option<&FieldEncryption>,
+) -> Result<HelloWorldClause, ContractError> {
+    let mut record: Value = get_item_as("data")
+        .await?
+        .ok_or(ContractError::NotInitialized)?;
+    decrypt_record(encryption, &mut record).await?;
+
+    Ok(serde_json::from_value(record).map_err(AttributeValueError::from)?)
+}
+
+//
+// Function load_state
+//
+// Gets the current `{state}` of the agreement, together with its version.
+//
+pub async fn load_state(
+    encryption: Option<&FieldEncryption>,
+) -> Result<(ContractState, u64), ContractError> {
+    let item = get_data("state")
+        .await?
+        .ok_or(ContractError::NotInitialized)?;
+
+    let version = match item.get("version") {
+        Some(AttributeValue::N(version)) => version.parse::<u64>().map_err(|_| {
+            ContractError::InvalidState(format!("invalid state version: {}", version))
+        })?,
+        _ => 0,
+    };
+
+    Ok((decrypt_item(item, encryption).await?, version))
+}
+
 pub async fn get_data(
     input_key: &str,
-) -> Result<Option<HashMap<String, AttributeValue>>, Box<dyn std::error::Error>> {
+) -> Result<Option<HashMap<String, AttributeValue>>, ContractError> {
     let config = aws_config::load_from_env().await;
     let dynamodb_client = Client::new(&config);
     let table_name = env::var("TABLE_NAME").expect("TABLE_NAME must be set");
 
-    let result = dynamodb_client
-        .get_item()
-        .table_name(&table_name)
-        .key("id", AttributeValue::S(input_key.to_string()))
-        .send()
-        .await;
+    let result = store_call(
+        "GetItem",
+        dynamodb_client
+            .get_item()
+            .table_name(&table_name)
+            .key("id", AttributeValue::S(input_key.to_string()))
+            .send(),
+    )
+    .await;
 
     match result {
         Ok(get_item_output) => Ok(get_item_output.item),
         Err(error) => {
-            println!("Error: {:?}", error);
-            Err(format!("AWS SDK error: {:?}", error).into())
+            tracing::error!(key = input_key, error = ?error, "failed to read from DynamoDB");
+            Err(error.into())
         }
     }
 }
+
+//
+// Function get_item_as
+//
+// Gets the item stored under `input_key` and deserializes it into `T`.
+//
+pub async fn get_item_as<T: DeserializeOwned>(input_key: &str) -> Result<Option<T>, ContractError> {
+    match get_data(input_key).await? {
+        Some(item) => Ok(Some(from_item(item)?)),
+        None => Ok(None),
+    }
+}